@@ -0,0 +1,128 @@
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::pubkey::Pubkey;
+use crate::id;
+use crate::instruction::{AmmInstruction, InitMarketOptions, SwapOptions};
+use crate::pda::Pda;
+#[cfg(feature = "compute-budget")]
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+
+/// Instructions builder of the market X, Y for one user.
+/// PDA accounts are generated once and reused by every instruction.
+///
+/// ```ignore
+/// let client = AmmClient::new(minter_x_pk, minter_y_pk, user_pk);
+/// let init_ix = client.init_market(100, 300);
+/// let swap_ix = client.swap(50, minter_x_pk);
+/// ```
+#[derive(Debug, Clone)]
+pub struct AmmClient {
+    pub minter_x_pk: Pubkey,
+    pub minter_y_pk: Pubkey,
+    pub pda: Pda,
+    /// User SPL token X, Y owner and payer
+    pub user_pk: Pubkey,
+    pub user_token_x_pk: Pubkey,
+    pub user_token_y_pk: Pubkey,
+}
+
+impl AmmClient {
+    /// User SPL token X, Y holders are the associated token accounts of the user
+    pub fn new(minter_x_pk: Pubkey, minter_y_pk: Pubkey, user_pk: Pubkey) -> AmmClient {
+        AmmClient {
+            minter_x_pk,
+            minter_y_pk,
+            pda: Pda::generate(&minter_x_pk, &minter_y_pk),
+            user_pk,
+            user_token_x_pk: spl_associated_token_account::get_associated_token_address(
+                &user_pk, &minter_x_pk,
+            ),
+            user_token_y_pk: spl_associated_token_account::get_associated_token_address(
+                &user_pk, &minter_y_pk,
+            ),
+        }
+    }
+
+    /// Use other user SPL token X, Y holders than the associated token accounts
+    pub fn with_user_tokens(mut self, user_token_x_pk: Pubkey, user_token_y_pk: Pubkey) -> AmmClient {
+        self.user_token_x_pk = user_token_x_pk;
+        self.user_token_y_pk = user_token_y_pk;
+        self
+    }
+
+    pub fn init_market(&self, amount_x: u64, amount_y: u64) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::InitMarket { amount_x, amount_y },
+            self.init_market_account_meta(),
+        )
+    }
+
+    pub fn init_market_with_options(
+        &self,
+        amount_x: u64,
+        amount_y: u64,
+        options: InitMarketOptions,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::InitMarketWithOptions { amount_x, amount_y, options },
+            self.init_market_account_meta(),
+        )
+    }
+
+    pub fn swap(&self, amount: u64, minter_pk: Pubkey) -> Instruction {
+        self.swap_with_options(amount, minter_pk, SwapOptions::default())
+    }
+
+    pub fn swap_with_options(&self, amount: u64, minter_pk: Pubkey, options: SwapOptions) -> Instruction {
+        let ix_accounts = AmmInstruction::get_swap_account_meta(
+            minter_pk,
+            self.user_pk,
+            self.user_token_x_pk,
+            self.user_token_y_pk,
+            self.minter_x_pk,
+            self.minter_y_pk,
+            &self.pda,
+            &options,
+        );
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::Swap { amount, minter_pk, options },
+            ix_accounts,
+        )
+    }
+
+    fn init_market_account_meta(&self) -> Vec<AccountMeta> {
+        AmmInstruction::get_init_market_account_meta(
+            self.user_pk,
+            self.user_pk,
+            self.user_pk,
+            self.user_token_x_pk,
+            self.user_token_y_pk,
+            self.minter_x_pk,
+            self.minter_y_pk,
+            &self.pda,
+        )
+    }
+}
+
+/// Compute units limit of an instruction, the default 200k units may be too few for a swap.
+/// Requires the `compute-budget` feature.
+///
+/// ```ignore
+/// let swap_ixs = client.swap(50, minter_x_pk).with_compute_budget(300_000);
+/// ```
+#[cfg(feature = "compute-budget")]
+pub trait WithComputeBudget {
+    /// The instruction prepended by `ComputeBudgetInstruction::RequestUnits` of the solana-sdk
+    /// the crate is built with, without an additional fee
+    fn with_compute_budget(self, units: u32) -> Vec<Instruction>;
+}
+
+#[cfg(feature = "compute-budget")]
+impl WithComputeBudget for Instruction {
+    fn with_compute_budget(self, units: u32) -> Vec<Instruction> {
+        vec![ComputeBudgetInstruction::request_units(units, 0), self]
+    }
+}
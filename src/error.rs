@@ -22,7 +22,69 @@ pub enum AmmError {
     #[error("Calculated zero swap amount")]
     CalculatedZeroSwap,
     #[error("Invalid vault")]
-    InvalidVault
+    InvalidVault,
+    #[error("No pending authority to accept")]
+    NoPendingAuthority,
+    #[error("Signer is not the market authority")]
+    Unauthorized,
+    #[error("Amount exceeds the maximum reserve of the market")]
+    ReserveTooLarge,
+    #[error("Destination token account has incorrect minter")]
+    IncorrectDestinationMint,
+    #[error("Invalid fee")]
+    InvalidFee,
+    #[error("Only wrapped SOL can be unwrapped")]
+    UnwrapNotNative,
+    #[error("User token accounts must be distinct")]
+    AliasedAccounts,
+    #[error("User token account has incorrect minter")]
+    IncorrectUserTokenMint,
+    #[error("Minters do not match the minters of the vault")]
+    MintMismatch,
+    #[error("Tokens X, Y of the market cannot be rescued")]
+    CannotRescuePoolToken,
+    #[error("Ratio of the initial amounts is too large")]
+    ExtremeInitRatio,
+    #[error("Price after the swap exceeds the price limit")]
+    PriceLimitExceeded,
+    #[error("Swap amount is rounded to nothing")]
+    NoOpSwap,
+    #[error("Vault account must be writable")]
+    VaultNotWritable,
+    #[error("Swap amount overflows the reserve of the market")]
+    ReserveOverflow,
+    #[error("Market is not initialized")]
+    MarketNotInitialized,
+    #[error("Market is already swapped earlier in the transaction")]
+    DuplicateSwapInTx,
+    #[error("Bump derives no PDA of the seeds")]
+    InvalidBump,
+    #[error("Swap decreases the invariant of the market")]
+    InvariantViolation,
+    #[error("Reserve is below the minimum reserve of the market")]
+    BelowMinReserve,
+    #[error("User token balance is less than the amount")]
+    InsufficientUserBalance,
+    #[error("PDA account is passed in place of another PDA")]
+    PdaRoleMismatch,
+    #[error("Market is not live yet")]
+    MarketNotLive,
+    #[error("Unexpected accounts past the accounts of the instruction")]
+    UnexpectedAccounts,
+    #[error("Signer is not the owner of the user token account")]
+    NotTokenOwner,
+    #[error("Price impact of the swap exceeds the maximum")]
+    PriceImpactTooHigh,
+    #[error("Market is paused")]
+    MarketPaused,
+    #[error("Market is not paused")]
+    MarketNotPaused,
+    #[error("Swap returns the whole reserve of the market")]
+    ExcessiveOutput,
+    #[error("Associated token account program is not the canonical one")]
+    IncorrectAssociatedTokenProgram,
+    #[error("Referral fee is set without a referrer")]
+    MissingReferrer,
 }
 
 impl From<AmmError> for ProgramError {
@@ -49,6 +111,37 @@ impl PrintProgramError for AmmError {
             AmmError::IncorrectSwapPk => msg!("Error: Incorrect public key for tokens swap"),
             AmmError::CalculatedZeroSwap => msg!("Error: Calculated zero swap amount"),
             AmmError::InvalidVault => msg!("Error: Invalid vault"),
+            AmmError::NoPendingAuthority => msg!("Error: No pending authority to accept"),
+            AmmError::Unauthorized => msg!("Error: Signer is not the market authority"),
+            AmmError::ReserveTooLarge => msg!("Error: Amount exceeds the maximum reserve of the market"),
+            AmmError::IncorrectDestinationMint => msg!("Error: Destination token account has incorrect minter"),
+            AmmError::InvalidFee => msg!("Error: Invalid fee"),
+            AmmError::UnwrapNotNative => msg!("Error: Only wrapped SOL can be unwrapped"),
+            AmmError::AliasedAccounts => msg!("Error: User token accounts must be distinct"),
+            AmmError::IncorrectUserTokenMint => msg!("Error: User token account has incorrect minter"),
+            AmmError::MintMismatch => msg!("Error: Minters do not match the minters of the vault"),
+            AmmError::CannotRescuePoolToken => msg!("Error: Tokens X, Y of the market cannot be rescued"),
+            AmmError::ExtremeInitRatio => msg!("Error: Ratio of the initial amounts is too large"),
+            AmmError::PriceLimitExceeded => msg!("Error: Price after the swap exceeds the price limit"),
+            AmmError::NoOpSwap => msg!("Error: Swap amount is rounded to nothing"),
+            AmmError::VaultNotWritable => msg!("Error: Vault account must be writable"),
+            AmmError::ReserveOverflow => msg!("Error: Swap amount overflows the reserve of the market"),
+            AmmError::MarketNotInitialized => msg!("Error: Market is not initialized"),
+            AmmError::DuplicateSwapInTx => msg!("Error: Market is already swapped earlier in the transaction"),
+            AmmError::InvalidBump => msg!("Error: Bump derives no PDA of the seeds"),
+            AmmError::InvariantViolation => msg!("Error: Swap decreases the invariant of the market"),
+            AmmError::BelowMinReserve => msg!("Error: Reserve is below the minimum reserve of the market"),
+            AmmError::InsufficientUserBalance => msg!("Error: User token balance is less than the amount"),
+            AmmError::PdaRoleMismatch => msg!("Error: PDA account is passed in place of another PDA"),
+            AmmError::MarketNotLive => msg!("Error: Market is not live yet"),
+            AmmError::UnexpectedAccounts => msg!("Error: Unexpected accounts past the accounts of the instruction"),
+            AmmError::NotTokenOwner => msg!("Error: Signer is not the owner of the user token account"),
+            AmmError::PriceImpactTooHigh => msg!("Error: Price impact of the swap exceeds the maximum"),
+            AmmError::MarketPaused => msg!("Error: Market is paused"),
+            AmmError::MarketNotPaused => msg!("Error: Market is not paused"),
+            AmmError::ExcessiveOutput => msg!("Error: Swap returns the whole reserve of the market"),
+            AmmError::IncorrectAssociatedTokenProgram => msg!("Error: Associated token account program is not the canonical one"),
+            AmmError::MissingReferrer => msg!("Error: Referral fee is set without a referrer"),
         }
     }
 }
@@ -1,9 +1,67 @@
 use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::clock::Slot;
 use solana_program::instruction::{AccountMeta, Instruction};
 use solana_program::pubkey::Pubkey;
 use solana_program::{system_program, sysvar};
 use crate::id;
-use crate::pda::Pda;
+use crate::pda::{find_pk_and_bump, Pda, VAULT_SEED};
+
+/// Optional parameters of the swap, everything is disabled by default
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub struct SwapOptions {
+    /// Close the user SPL token holder of the returned wrapped SOL,
+    /// so the lamports are delivered to the user SPL token owner
+    pub unwrap: bool,
+    /// Worst acceptable price of token X in token Y after the swap,
+    /// Q64.64 adjusted by the decimals, see `swap::normalized_price`.
+    /// X to Y fails below the limit, Y to X fails above it
+    pub price_limit: Option<u128>,
+    /// The returned amount is floored to a multiple of the granularity,
+    /// the rest stays in the market. 0 is disabled
+    pub output_granularity: u64,
+    /// Fail if an earlier instruction of the transaction swaps in the same market,
+    /// against the sandwich within one transaction. Requires the Instructions sysvar account
+    pub reject_earlier_swap_in_tx: bool,
+    /// Create the missing user SPL token holder of the returned token,
+    /// the associated token account of the user SPL token owner, paid by `return_token_payer`.
+    /// Requires the Rent sysvar, System and SPL associated token account programs
+    pub create_return_token: bool,
+    /// Maximum change of the spot price by the swap in basis points,
+    /// see `swap::price_impact_bps`. 0 is disabled
+    pub max_impact_bps: u16,
+    /// Owner of the referrer SPL token holder, the associated token account of the swapped token.
+    /// Paid `referral_fee_bps` out of the swap fee
+    pub referrer: Option<Pubkey>,
+    /// Part of the swap fee paid to the referrer in basis points of the swapped amount,
+    /// at most `state::MAX_REFERRAL_FEE_BPS` and the swap fee. Fails without the referrer
+    pub referral_fee_bps: u16,
+    /// The returned token goes to the destination SPL token holder, the last account of the swap,
+    /// instead of the user SPL token X(or Y) holder, see `AmmInstruction::swap_to`
+    pub return_to_destination: bool,
+    /// Payer of the rent of the user SPL token holder created with `create_return_token`, signs the swap.
+    /// The user SPL token owner by default, ignored without `create_return_token`
+    pub return_token_payer: Option<Pubkey>,
+}
+
+/// Optional parameters of the market initialization, everything is disabled by default
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, Default, PartialEq)]
+pub struct InitMarketOptions {
+    /// Reuse the existing empty vault and PDA SPL token holders,
+    /// so a retried initialization deposits instead of failing
+    /// and a fully withdrawn market is re-seeded at a new ratio.
+    /// Only the authority of the market re-seeds it as the user payer,
+    /// the stored configuration is kept and the other options are ignored.
+    /// A vault with reserves is still rejected
+    pub allow_existing: bool,
+    /// Admin of the market, e.g. a multisig or a governance PDA.
+    /// The user payer by default, doesn't have to sign
+    pub authority: Option<Pubkey>,
+    /// Floor of each reserve X, Y, the initial amounts and the swaps leaving a reserve below it are rejected.
+    /// 0 is disabled. Nothing withdraws the reserves yet, a future `RemoveLiquidity` is to keep them above it
+    pub min_reserve: u64,
+    /// Swaps are rejected before the slot, against the sniping of the launch. 0 is live at once
+    pub launch_slot: Slot,
+}
 
 #[derive(BorshDeserialize, BorshSerialize, Debug, Clone)]
 pub enum AmmInstruction {
@@ -25,7 +83,8 @@ pub enum AmmInstruction {
     /// 9. `[]` - contract(PDA) SPL token X owner
     /// 10. `[]` - contract(PDA) SPL token Y owner
     /// 11. `[writable]` - contract(PDA) Vault
-    /// 12. `[]` - Rent sysvar
+    /// 12. `[]` - Rent sysvar, only passed to the SPL associated token account program, which requires it.
+    ///     The rent of the vault is read by `Rent::get()`
     /// 13. `[]` - System program
     /// 14. `[]` - SPL Token program
     /// 15. `[]` - SPL associated token account program
@@ -36,9 +95,11 @@ pub enum AmmInstruction {
     /// The user add token X(or Y) to contract.
     /// Contract return token Y(or X).
     /// dY = Y - K / (X + dX) / dX = X - K / (Y + dY)
+    /// The user SPL token owner doesn't have to be the fee payer of the transaction.
+    /// Return data is the `swap::LabeledSwapResult` of the swap.
     ///
     /// Accounts expected by this instruction:
-    /// 0. `[signer]` - user SPL token owner
+    /// 0. `[signer, writable]` - user SPL token owner, receives the unwrapped lamports
     /// 1. `[writable]` - from user SPL token X holder
     /// 2. `[writable]` - from user SPL token Y holder
     /// 3. `[]` - minter SPL token X
@@ -49,11 +110,180 @@ pub enum AmmInstruction {
     /// 8. `[]` - contract(PDA) SPL token Y owner
     /// 9. `[writable]` - contract(PDA) Vault
     /// 10. `[]` - SPL token program
+    /// 11. `[]` - optional, Instructions sysvar, only with `SwapOptions::reject_earlier_swap_in_tx`
+    /// 12. `[]` - optional, Rent sysvar, only with `SwapOptions::create_return_token`
+    /// 13. `[]` - optional, System program, only with `SwapOptions::create_return_token`
+    /// 14. `[]` - optional, SPL associated token account program, only with `SwapOptions::create_return_token`
+    /// 15. `[signer, writable]` - optional, payer of the created user SPL token holder,
+    ///     only with `SwapOptions::create_return_token` and `SwapOptions::return_token_payer`
+    /// 16. `[writable]` - optional, referrer SPL token holder of the swapped token, only with `SwapOptions::referrer`
+    /// 17. `[writable]` - optional, destination SPL token holder of the returned token,
+    ///     only with `SwapOptions::return_to_destination`.
+    ///     By default the returned token goes to the user SPL token X(or Y) holder
+    ///
+    /// The optional accounts are read by the position in this order, an account without its option is skipped.
     ///
     Swap {
         amount: u64,
         minter_pk: Pubkey,
+        options: SwapOptions,
     },
+
+    /// Propose a new authority of the market.
+    /// The current authority stays in charge until the new one accepts.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - market authority
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    ///
+    ProposeAuthority { new_authority: Pubkey },
+
+    /// Accept the proposed authority of the market.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - proposed market authority
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    ///
+    AcceptAuthority,
+
+    /// Set the swap fee of the market.
+    /// Fee = fee_numerator / fee_denominator of the token added by the user.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - market authority
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    ///
+    SetFee {
+        fee_numerator: u64,
+        fee_denominator: u64,
+        dynamic_fee_enabled: bool,
+    },
+
+    /// Initialization of an automated market maker with options.
+    /// Accounts are the same as `InitMarket`.
+    ///
+    InitMarketWithOptions {
+        amount_x: u64,
+        amount_y: u64,
+        options: InitMarketOptions,
+    },
+
+    /// Rescue tokens, other than X and Y, sent by mistake to a SPL token holder
+    /// of the contract(PDA) SPL token X(or Y) owner.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - market authority
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    /// 4. `[]` - contract(PDA) SPL token X(or Y) owner
+    /// 5. `[writable]` - SPL token holder of the stuck token, owned by the PDA owner
+    /// 6. `[writable]` - destination SPL token holder of the authority
+    /// 7. `[]` - SPL token program
+    /// 8. `[]` - minter of the stuck token
+    ///
+    RescueTokens { amount: u64 },
+
+    /// Donate tokens X, Y to the market by the authority, e.g. incentives.
+    /// Grows the reserves of the market, so X * Y = K grows too.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - market authority, user SPL token X, Y owner
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    /// 4. `[writable]` - from user SPL token X holder
+    /// 5. `[writable]` - from user SPL token Y holder
+    /// 6. `[writable]` - contract(PDA) SPL token X holder
+    /// 7. `[writable]` - contract(PDA) SPL token Y holder
+    /// 8. `[]` - SPL token program
+    ///
+    Donate { amount_x: u64, amount_y: u64 },
+
+    /// Read the reserves of the market, changes nothing.
+    /// Return data is the `state::ReservesReport` of the reserves recorded in the vault
+    /// and the balances of the contract(PDA) SPL token X, Y holders, e.g. to alert on a desync.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[]` - minter SPL token X
+    /// 1. `[]` - minter SPL token Y
+    /// 2. `[]` - contract(PDA) Vault
+    /// 3. `[]` - contract(PDA) SPL token X holder
+    /// 4. `[]` - contract(PDA) SPL token Y holder
+    ///
+    Inspect,
+
+    /// Set the treasury owner receiving the protocol fees of the market
+    /// to its associated token accounts, see `state::Vault::fee_recipient_token`.
+    /// The market takes no protocol fee yet, the recipient is stored for its collection.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - market authority
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    /// 4. `[]` - fee recipient SPL token X, an existing associated token account of the recipient
+    /// 5. `[]` - fee recipient SPL token Y, an existing associated token account of the recipient
+    ///
+    SetFeeRecipient { recipient: Pubkey },
+
+    /// Pause(or resume) the swaps of the market by the authority,
+    /// e.g. for an incident, see `EmergencyWithdraw`.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - market authority
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    ///
+    SetPaused { paused: bool },
+
+    /// Transfer the whole balances of the contract(PDA) SPL token X, Y holders
+    /// of the paused market to the authority and zero the reserves of the vault.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - market authority
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    /// 4. `[writable]` - contract(PDA) SPL token X holder
+    /// 5. `[writable]` - contract(PDA) SPL token Y holder
+    /// 6. `[]` - contract(PDA) SPL token X owner
+    /// 7. `[]` - contract(PDA) SPL token Y owner
+    /// 8. `[writable]` - destination SPL token X holder of the authority
+    /// 9. `[writable]` - destination SPL token Y holder of the authority
+    /// 10. `[]` - SPL token program
+    ///
+    EmergencyWithdraw,
+
+    /// Check the consistency of the market, changes nothing and never fails on a failed check.
+    /// Return data is the `state::MarketChecks` of the passed checks, e.g. for monitoring.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[]` - minter SPL token X
+    /// 1. `[]` - minter SPL token Y
+    /// 2. `[]` - contract(PDA) Vault
+    /// 3. `[]` - contract(PDA) SPL token X holder
+    /// 4. `[]` - contract(PDA) SPL token Y holder
+    ///
+    Validate,
+
+    /// Set(or clear by `None`) the swapper paying no swap fee by the authority.
+    /// The swapper is the signing owner of the user SPL token X, Y holders of `Swap`.
+    ///
+    /// Accounts expected by this instruction:
+    /// 0. `[signer]` - market authority
+    /// 1. `[]` - minter SPL token X
+    /// 2. `[]` - minter SPL token Y
+    /// 3. `[writable]` - contract(PDA) Vault
+    ///
+    SetFeeExempt { fee_exempt: Option<Pubkey> },
 }
 
 impl AmmInstruction {
@@ -68,6 +298,360 @@ impl AmmInstruction {
         minter_x_pk: Pubkey,
         minter_y_pk: Pubkey,
     ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::InitMarket { amount_x, amount_y },
+            Self::get_init_market_account_meta(
+                user_owner_token_x_pk,
+                user_owner_token_y_pk,
+                user_payer_pk,
+                user_token_x_pk,
+                user_token_y_pk,
+                minter_x_pk,
+                minter_y_pk,
+                &Pda::generate(&minter_x_pk, &minter_y_pk),
+            ),
+        )
+    }
+
+    pub fn init_market_with_options(
+        amount_x: u64,
+        amount_y: u64,
+        user_owner_token_x_pk: Pubkey,
+        user_owner_token_y_pk: Pubkey,
+        user_payer_pk: Pubkey,
+        user_token_x_pk: Pubkey,
+        user_token_y_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+        options: InitMarketOptions,
+    ) -> Instruction {
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::InitMarketWithOptions { amount_x, amount_y, options },
+            Self::get_init_market_account_meta(
+                user_owner_token_x_pk,
+                user_owner_token_y_pk,
+                user_payer_pk,
+                user_token_x_pk,
+                user_token_y_pk,
+                minter_x_pk,
+                minter_y_pk,
+                &Pda::generate(&minter_x_pk, &minter_y_pk),
+            ),
+        )
+    }
+
+    pub fn swap(
+        amount: u64,
+        minter_pk: Pubkey,
+        user_owner_token_pk: Pubkey,
+        user_token_x_pk: Pubkey,
+        user_token_y_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        Self::swap_with_options(
+            amount,
+            minter_pk,
+            user_owner_token_pk,
+            user_token_x_pk,
+            user_token_y_pk,
+            minter_x_pk,
+            minter_y_pk,
+            SwapOptions::default(),
+        )
+    }
+
+    pub fn swap_with_options(
+        amount: u64,
+        minter_pk: Pubkey,
+        user_owner_token_pk: Pubkey,
+        user_token_x_pk: Pubkey,
+        user_token_y_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+        options: SwapOptions,
+    ) -> Instruction {
+        let ix_accounts = Self::get_swap_account_meta(
+            minter_pk,
+            user_owner_token_pk,
+            user_token_x_pk,
+            user_token_y_pk,
+            minter_x_pk,
+            minter_y_pk,
+            &Pda::generate(&minter_x_pk, &minter_y_pk),
+            &options,
+        );
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::Swap { amount, minter_pk, options },
+            ix_accounts,
+        )
+    }
+
+    /// Same as `swap`, but the returned token goes to the destination SPL token holder.
+    /// With other options, set `SwapOptions::return_to_destination` and push the destination last
+    pub fn swap_to(
+        amount: u64,
+        minter_pk: Pubkey,
+        user_owner_token_pk: Pubkey,
+        user_token_x_pk: Pubkey,
+        user_token_y_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+        destination_token_pk: Pubkey,
+    ) -> Instruction {
+        let mut ix = Self::swap_with_options(
+            amount,
+            minter_pk,
+            user_owner_token_pk,
+            user_token_x_pk,
+            user_token_y_pk,
+            minter_x_pk,
+            minter_y_pk,
+            SwapOptions { return_to_destination: true, ..SwapOptions::default() },
+        );
+        ix.accounts.push(AccountMeta::new(destination_token_pk, false));
+        ix
+    }
+
+    pub fn propose_authority(
+        new_authority: Pubkey,
+        authority_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        let ix_accounts = Self::get_authority_account_meta(
+            &authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::ProposeAuthority { new_authority },
+            ix_accounts,
+        )
+    }
+
+    pub fn accept_authority(
+        new_authority_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        let ix_accounts = Self::get_authority_account_meta(
+            &new_authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::AcceptAuthority,
+            ix_accounts,
+        )
+    }
+
+    pub fn set_fee(
+        fee_numerator: u64,
+        fee_denominator: u64,
+        dynamic_fee_enabled: bool,
+        authority_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        let ix_accounts = Self::get_authority_account_meta(
+            &authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::SetFee { fee_numerator, fee_denominator, dynamic_fee_enabled },
+            ix_accounts,
+        )
+    }
+
+    pub fn set_fee_recipient(
+        recipient: Pubkey,
+        authority_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        let mut ix_accounts = Self::get_authority_account_meta(
+            &authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+        ix_accounts.push(AccountMeta::new_readonly(
+            spl_associated_token_account::get_associated_token_address(&recipient, &minter_x_pk),
+            false,
+        ));
+        ix_accounts.push(AccountMeta::new_readonly(
+            spl_associated_token_account::get_associated_token_address(&recipient, &minter_y_pk),
+            false,
+        ));
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::SetFeeRecipient { recipient },
+            ix_accounts,
+        )
+    }
+
+    pub fn set_fee_exempt(
+        fee_exempt: Option<Pubkey>,
+        authority_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        let ix_accounts = Self::get_authority_account_meta(
+            &authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::SetFeeExempt { fee_exempt },
+            ix_accounts,
+        )
+    }
+
+    pub fn set_paused(
+        paused: bool,
+        authority_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        let ix_accounts = Self::get_authority_account_meta(
+            &authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::SetPaused { paused },
+            ix_accounts,
+        )
+    }
+
+    pub fn emergency_withdraw(
+        authority_pk: Pubkey,
+        authority_token_x_pk: Pubkey,
+        authority_token_y_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+        let mut ix_accounts = Self::get_authority_account_meta(
+            &authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+        ix_accounts.extend(vec![
+            AccountMeta::new(pda.pda_token_x_pk, false),
+            AccountMeta::new(pda.pda_token_y_pk, false),
+            AccountMeta::new_readonly(pda.pda_owner_token_x.0, false),
+            AccountMeta::new_readonly(pda.pda_owner_token_y.0, false),
+            AccountMeta::new(authority_token_x_pk, false),
+            AccountMeta::new(authority_token_y_pk, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ]);
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::EmergencyWithdraw,
+            ix_accounts,
+        )
+    }
+
+    pub fn rescue_tokens(
+        amount: u64,
+        authority_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+        pda_owner_pk: Pubkey,
+        source_token_pk: Pubkey,
+        destination_token_pk: Pubkey,
+        stuck_minter_pk: Pubkey,
+    ) -> Instruction {
+        let mut ix_accounts = Self::get_authority_account_meta(
+            &authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+        ix_accounts.extend(vec![
+            AccountMeta::new_readonly(pda_owner_pk, false),
+            AccountMeta::new(source_token_pk, false),
+            AccountMeta::new(destination_token_pk, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(stuck_minter_pk, false),
+        ]);
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::RescueTokens { amount },
+            ix_accounts,
+        )
+    }
+
+    pub fn donate(
+        amount_x: u64,
+        amount_y: u64,
+        authority_pk: Pubkey,
+        user_token_x_pk: Pubkey,
+        user_token_y_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+    ) -> Instruction {
+        let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+        let mut ix_accounts = Self::get_authority_account_meta(
+            &authority_pk, &minter_x_pk, &minter_y_pk,
+        );
+        ix_accounts.extend(vec![
+            AccountMeta::new(user_token_x_pk, false),
+            AccountMeta::new(user_token_y_pk, false),
+            AccountMeta::new(pda.pda_token_x_pk, false),
+            AccountMeta::new(pda.pda_token_y_pk, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+        ]);
+
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::Donate { amount_x, amount_y },
+            ix_accounts,
+        )
+    }
+
+    pub fn inspect(minter_x_pk: Pubkey, minter_y_pk: Pubkey) -> Instruction {
+        let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::Inspect,
+            vec![
+                AccountMeta::new_readonly(minter_x_pk, false),
+                AccountMeta::new_readonly(minter_y_pk, false),
+                AccountMeta::new_readonly(pda.vault.0, false),
+                AccountMeta::new_readonly(pda.pda_token_x_pk, false),
+                AccountMeta::new_readonly(pda.pda_token_y_pk, false),
+            ],
+        )
+    }
+
+    pub fn validate(minter_x_pk: Pubkey, minter_y_pk: Pubkey) -> Instruction {
+        let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+        Instruction::new_with_borsh(
+            id(),
+            &AmmInstruction::Validate,
+            vec![
+                AccountMeta::new_readonly(minter_x_pk, false),
+                AccountMeta::new_readonly(minter_y_pk, false),
+                AccountMeta::new_readonly(pda.vault.0, false),
+                AccountMeta::new_readonly(pda.pda_token_x_pk, false),
+                AccountMeta::new_readonly(pda.pda_token_y_pk, false),
+            ],
+        )
+    }
+
+    pub(crate) fn get_init_market_account_meta(
+        user_owner_token_x_pk: Pubkey,
+        user_owner_token_y_pk: Pubkey,
+        user_payer_pk: Pubkey,
+        user_token_x_pk: Pubkey,
+        user_token_y_pk: Pubkey,
+        minter_x_pk: Pubkey,
+        minter_y_pk: Pubkey,
+        pda: &Pda,
+    ) -> Vec<AccountMeta> {
         let mut ix_accounts = vec![
             AccountMeta::new_readonly(user_owner_token_x_pk, true),
             AccountMeta::new_readonly(user_owner_token_y_pk, true),
@@ -77,7 +661,7 @@ impl AmmInstruction {
             AccountMeta::new_readonly(minter_x_pk, false),
             AccountMeta::new_readonly(minter_y_pk, false),
         ];
-        let pda_accounts = Self::get_pda_account_meta(&minter_x_pk, &minter_y_pk);
+        let pda_accounts = Self::get_pda_account_meta(pda);
         ix_accounts.extend(pda_accounts);
         let program_accounts = vec![
             AccountMeta::new_readonly(sysvar::rent::id(), false),
@@ -86,23 +670,19 @@ impl AmmInstruction {
             AccountMeta::new_readonly(spl_associated_token_account::id(), false),
         ];
         ix_accounts.extend(program_accounts);
-
-        Instruction::new_with_borsh(
-            id(),
-            &AmmInstruction::InitMarket { amount_x, amount_y },
-            ix_accounts,
-        )
+        ix_accounts
     }
 
-    pub fn swap(
-        amount: u64,
+    pub(crate) fn get_swap_account_meta(
         minter_pk: Pubkey,
         user_owner_token_pk: Pubkey,
         user_token_x_pk: Pubkey,
         user_token_y_pk: Pubkey,
         minter_x_pk: Pubkey,
         minter_y_pk: Pubkey,
-    ) -> Instruction {
+        pda: &Pda,
+        options: &SwapOptions,
+    ) -> Vec<AccountMeta> {
         let mut ix_accounts = vec![
             AccountMeta::new(user_owner_token_pk, true),
             AccountMeta::new(user_token_x_pk, false),
@@ -110,25 +690,49 @@ impl AmmInstruction {
             AccountMeta::new_readonly(minter_x_pk, false),
             AccountMeta::new_readonly(minter_y_pk, false),
         ];
-        let pda_accounts = Self::get_pda_account_meta(&minter_x_pk, &minter_y_pk);
+        let pda_accounts = Self::get_pda_account_meta(pda);
         ix_accounts.extend(pda_accounts);
         let program_accounts = vec![
             AccountMeta::new_readonly(spl_token::id(), false),
         ];
         ix_accounts.extend(program_accounts);
-
-        Instruction::new_with_borsh(
-            id(),
-            &AmmInstruction::Swap { amount, minter_pk },
-            ix_accounts,
-        )
+        if options.reject_earlier_swap_in_tx {
+            ix_accounts.push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+        }
+        if options.create_return_token {
+            ix_accounts.extend(vec![
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            ]);
+            if let Some(return_token_payer) = options.return_token_payer {
+                ix_accounts.push(AccountMeta::new(return_token_payer, true));
+            }
+        }
+        if let Some(referrer) = options.referrer {
+            ix_accounts.push(AccountMeta::new(
+                spl_associated_token_account::get_associated_token_address(&referrer, &minter_pk),
+                false,
+            ));
+        }
+        ix_accounts
     }
 
-    fn get_pda_account_meta(
+    fn get_authority_account_meta(
+        authority_pk: &Pubkey,
         minter_x_pk: &Pubkey,
-        minter_y_pk: &Pubkey
+        minter_y_pk: &Pubkey,
     ) -> Vec<AccountMeta> {
-        let pda = Pda::generate(minter_x_pk, minter_y_pk);
+        let (vault_pk, _) = find_pk_and_bump(VAULT_SEED, minter_x_pk, minter_y_pk);
+        vec![
+            AccountMeta::new_readonly(*authority_pk, true),
+            AccountMeta::new_readonly(*minter_x_pk, false),
+            AccountMeta::new_readonly(*minter_y_pk, false),
+            AccountMeta::new(vault_pk, false),
+        ]
+    }
+
+    fn get_pda_account_meta(pda: &Pda) -> Vec<AccountMeta> {
         vec![
             AccountMeta::new(pda.pda_token_x_pk, false),
             AccountMeta::new(pda.pda_token_y_pk, false),
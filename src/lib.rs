@@ -1,11 +1,27 @@
+#![cfg_attr(feature = "core-math-only", no_std)]
+
+pub mod math;
+
+#[cfg(feature = "program")]
 pub mod error;
+#[cfg(feature = "program")]
 pub mod processor;
+#[cfg(feature = "program")]
 pub mod instruction;
+#[cfg(feature = "program")]
 pub mod state;
+#[cfg(feature = "program")]
 pub mod pda;
+#[cfg(feature = "program")]
 pub mod swap;
+#[cfg(feature = "program")]
+pub mod client;
+#[cfg(feature = "program")]
+#[cfg(feature = "program")]
+pub mod log;
 
-#[cfg(not(feature = "no-entrypoint"))]
+#[cfg(all(feature = "program", not(feature = "no-entrypoint")))]
 pub mod entrypoint;
 
-solana_program::declare_id!("Dybvx3CExEV2zpLSJrcap37Q1cdptvekjr2R3nEu1mTS");
\ No newline at end of file
+#[cfg(feature = "program")]
+solana_program::declare_id!("Dybvx3CExEV2zpLSJrcap37Q1cdptvekjr2R3nEu1mTS");
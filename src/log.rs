@@ -0,0 +1,30 @@
+//! Compact binary logs for the indexers, cheaper than the formatted `msg!`.
+//! Emitted by `sol_log_data` with the `binary-logs` feature,
+//! the base64 data of the `Program data:` log line is decoded by `try_from_slice`.
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::entrypoint::ProgramResult;
+use solana_program::log::sol_log_data;
+
+/// Record of a swap, fixed little-endian layout:
+/// x_to_y(1) | take_amount(8) | return_amount(8) | fee_amount(8) | reserve_x(8) | reserve_y(8)
+#[derive(BorshDeserialize, BorshSerialize, Debug, Clone, PartialEq)]
+pub struct SwapLog {
+    /// Token X is added by the user, token Y is returned
+    pub x_to_y: bool,
+    pub take_amount: u64,
+    pub return_amount: u64,
+    pub fee_amount: u64,
+    /// Reserves of the market after the swap
+    pub reserve_x: u64,
+    pub reserve_y: u64,
+}
+
+impl SwapLog {
+    pub const LEN: usize = 1 + 8 + 8 + 8 + 8 + 8;
+
+    pub fn emit(&self) -> ProgramResult {
+        sol_log_data(&[&self.try_to_vec()?]);
+        Ok(())
+    }
+}
@@ -0,0 +1,377 @@
+//! Swap math of the market.
+//! Free of `solana-program` types, so it can be reused off-chain(e.g. wasm frontends).
+//! Built alone as `no_std` with the `core-math-only` feature.
+
+use core::convert::TryFrom;
+
+/// Dynamic fee is at most `DYNAMIC_FEE_MAX_MULTIPLIER` times the base fee
+pub const DYNAMIC_FEE_MAX_MULTIPLIER: u64 = 3;
+
+const BPS: u128 = 10_000;
+
+/// Amounts of a swap. Borsh layout for the other programs is
+/// `take_amount`, `return_amount`, `fee_amount`, each a little-endian u64
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "program", derive(borsh::BorshSerialize, borsh::BorshDeserialize))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SwapResult {
+    pub take_amount: u64,
+    pub return_amount: u64,
+    /// Part of the `take_amount` taken as a fee
+    pub fee_amount: u64,
+}
+
+impl SwapResult {
+    pub const LEN: usize = 8 + 8 + 8;
+}
+
+/// Widen the amount for the intermediate math, never fails
+pub fn to_u128(amount: u64) -> u128 {
+    amount as u128
+}
+
+/// Narrow the result of the intermediate math back to an amount, `Overflow` above `u64::MAX`
+pub fn to_u64(amount: u128) -> Result<u64, SwapError> {
+    u64::try_from(amount).map_err(|_| SwapError::Overflow)
+}
+
+/// Reason the swap can't be calculated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SwapError {
+    /// Added amount is rounded away, nothing is taken from the user
+    NoOp,
+    /// Nothing is returned to the user
+    ZeroReturn,
+    Overflow,
+}
+
+pub fn calc_swap(
+    add_source_amount: u64,
+    source_amount: u64,
+    destination_amount: u64,
+) -> Option<SwapResult> {
+    try_calc_swap(add_source_amount, source_amount, destination_amount).ok()
+}
+
+/// Same as `calc_swap`, but tells why the swap can't be calculated
+pub fn try_calc_swap(
+    add_source_amount: u64,
+    source_amount: u64,
+    destination_amount: u64,
+) -> Result<SwapResult, SwapError> {
+    let add_source_amount = to_u128(add_source_amount);
+    let source_amount = to_u128(source_amount);
+    let destination_amount = to_u128(destination_amount);
+
+    // K = X * Y
+    let invariant = source_amount.checked_mul(destination_amount)
+        .ok_or(SwapError::Overflow)?;
+
+    // (X + dX)
+    let new_source_amount = source_amount.checked_add(add_source_amount)
+        .ok_or(SwapError::Overflow)?;
+
+    // ((Y - dY), M(updated) = K / M
+    let (new_destination_amount, new_source_amount) = invariant.checked_ceil_div(new_source_amount)
+        .ok_or(SwapError::ZeroReturn)?;
+
+    //  dX = (X + dX) - X
+    let take_amount_x = new_source_amount.checked_sub(source_amount)
+        .ok_or(SwapError::Overflow)
+        .and_then(to_u64)?;
+    if take_amount_x == 0 {
+        return Err(SwapError::NoOp)
+    }
+
+    //  dY = Y - (Y - dY)
+    let return_amount_y = destination_amount.checked_sub(new_destination_amount)
+        .ok_or(SwapError::Overflow)
+        .and_then(to_u64)?;
+    if return_amount_y == 0 {
+        return Err(SwapError::ZeroReturn)
+    }
+
+    Ok(SwapResult { take_amount: take_amount_x, return_amount: return_amount_y, fee_amount: 0 })
+}
+
+/// Same as `calc_swap`, but the fee is taken from the added source amount
+/// and stays in the market
+pub fn calc_swap_with_fee(
+    add_source_amount: u64,
+    source_amount: u64,
+    destination_amount: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> Option<SwapResult> {
+    try_calc_swap_with_fee(
+        add_source_amount,
+        source_amount,
+        destination_amount,
+        fee_numerator,
+        fee_denominator,
+    ).ok()
+}
+
+/// Same as `calc_swap_with_fee`, but tells why the swap can't be calculated
+pub fn try_calc_swap_with_fee(
+    add_source_amount: u64,
+    source_amount: u64,
+    destination_amount: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+) -> Result<SwapResult, SwapError> {
+    // fee = dX * numerator / denominator
+    let fee_amount = if fee_numerator == 0 {
+        0
+    } else {
+        to_u128(add_source_amount)
+            .checked_mul(to_u128(fee_numerator))
+            .and_then(|fee| fee.checked_div(to_u128(fee_denominator)))
+            .ok_or(SwapError::Overflow)
+            .and_then(to_u64)?
+    };
+
+    let swap_result = try_calc_swap(
+        add_source_amount.checked_sub(fee_amount).ok_or(SwapError::Overflow)?,
+        source_amount,
+        destination_amount,
+    )?;
+
+    Ok(SwapResult {
+        take_amount: swap_result.take_amount.checked_add(fee_amount).ok_or(SwapError::Overflow)?,
+        return_amount: swap_result.return_amount,
+        fee_amount,
+    })
+}
+
+/// Quotes of the same added amount in both directions, (X to Y, Y to X).
+/// Fee is (numerator, denominator), see `calc_swap_with_fee`
+pub fn quote_both(
+    amount: u64,
+    reserve_x: u64,
+    reserve_y: u64,
+    fee: (u64, u64),
+) -> (Option<SwapResult>, Option<SwapResult>) {
+    let (fee_numerator, fee_denominator) = fee;
+    (
+        calc_swap_with_fee(amount, reserve_x, reserve_y, fee_numerator, fee_denominator),
+        calc_swap_with_fee(amount, reserve_y, reserve_x, fee_numerator, fee_denominator),
+    )
+}
+
+/// Realized price of the swap, returned per taken amount as Q64.64, is at most `tolerance_bps`
+/// worse than the reference price(Q64.64) of the returned token per added token, e.g. of an oracle.
+/// A better price is always within the reference
+pub fn within_reference(result: &SwapResult, reference_price: u128, tolerance_bps: u64) -> bool {
+    if result.take_amount == 0 {
+        return false
+    }
+    // return_amount < 2^64, so the shift never overflows
+    let realized_price = ((result.return_amount as u128) << 64) / result.take_amount as u128;
+    // reference * (1 - tolerance), split by BPS to never overflow
+    let factor = BPS.saturating_sub(tolerance_bps as u128);
+    let min_price = reference_price / BPS * factor + reference_price % BPS * factor / BPS;
+    realized_price >= min_price
+}
+
+/// Change of the spot price(Q64.64) from before to after a swap in basis points, rounded up.
+/// None for a zero price before or on overflow
+pub fn price_impact_bps(price_before: u128, price_after: u128) -> Option<u64> {
+    if price_before == 0 {
+        return None
+    }
+    let difference = if price_after > price_before {
+        price_after - price_before
+    } else {
+        price_before - price_after
+    };
+    difference.checked_mul(BPS)?
+        .checked_add(price_before - 1)?
+        .checked_div(price_before)
+        .and_then(|impact_bps| to_u64(impact_bps).ok())
+}
+
+/// Market of a hop in the swap direction, ((reserve in, reserve out), (fee numerator, fee denominator))
+pub type HopMarket = ((u64, u64), (u64, u64));
+
+/// Best of the two-hop routes X -> Y -> Z through the candidate intermediate tokens Y,
+/// each route is the markets (X -> Y, Y -> Z). The whole return of the first hop is swapped by the second.
+/// Returns (index of the best route, return amount of Z), the first one of the equal routes.
+/// None if no route returns anything
+pub fn best_two_hop(amount: u64, routes: &[(HopMarket, HopMarket)]) -> Option<(usize, u64)> {
+    let mut best_route: Option<(usize, u64)> = None;
+    for (i, (first_hop, second_hop)) in routes.iter().enumerate() {
+        let ((reserve_in, reserve_out), (fee_numerator, fee_denominator)) = *first_hop;
+        let first_result = match calc_swap_with_fee(amount, reserve_in, reserve_out, fee_numerator, fee_denominator) {
+            Some(first_result) => first_result,
+            None => continue,
+        };
+        let ((reserve_in, reserve_out), (fee_numerator, fee_denominator)) = *second_hop;
+        let second_result = match calc_swap_with_fee(
+            first_result.return_amount, reserve_in, reserve_out, fee_numerator, fee_denominator,
+        ) {
+            Some(second_result) => second_result,
+            None => continue,
+        };
+        if best_route.map_or(true, |(_, best_amount)| second_result.return_amount > best_amount) {
+            best_route = Some((i, second_result.return_amount));
+        }
+    }
+    best_route
+}
+
+/// Units of the invariant K = X * Y a swap without fees may lose to the integer rounding
+pub const INVARIANT_TOLERANCE: u128 = 1;
+
+/// Invariant K = X * Y doesn't decrease by the swap. The fee amount left in the market grows it,
+/// so only a swap without the fee absorbs the integer rounding within `INVARIANT_TOLERANCE`
+pub fn invariant_ok(invariant_before: u128, invariant_after: u128, fee_amount: u64) -> bool {
+    if fee_amount > 0 {
+        return invariant_after >= invariant_before
+    }
+    invariant_after >= invariant_before.saturating_sub(INVARIANT_TOLERANCE)
+}
+
+/// Floor the `return_amount` to a multiple of the granularity, the rest stays in the market.
+/// Granularity 0 is disabled. None if nothing is left to return
+pub fn apply_output_granularity(swap_result: SwapResult, granularity: u64) -> Option<SwapResult> {
+    if granularity == 0 {
+        return Some(swap_result)
+    }
+    let return_amount = swap_result.return_amount - swap_result.return_amount % granularity;
+    if return_amount == 0 {
+        return None
+    }
+    Some(SwapResult { return_amount, ..swap_result })
+}
+
+/// Time-weighted price accumulator, adds the price(Q64.64) lasted for `elapsed` seconds.
+/// Wraps on overflow, the average price between two observations is
+/// the wrapping difference of the accumulators divided by the elapsed time
+pub fn accumulate_price(price_cumulative: u128, price: u128, elapsed: u64) -> u128 {
+    price_cumulative.wrapping_add(price.wrapping_mul(elapsed as u128))
+}
+
+/// Integer square root rounded down, Babylonian method.
+/// Starts above the root, so the root + n / root never overflows, even for `u128::MAX`
+pub fn integer_sqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n
+    }
+    // 2^ceil(bits / 2) >= sqrt(n), at most 2^64
+    let mut root = 1u128 << ((128 - n.leading_zeros() + 1) / 2);
+    loop {
+        let next = (root + n / root) / 2;
+        if next >= root {
+            return root
+        }
+        root = next;
+    }
+}
+
+/// Part of the swap fee paid to the referrer, `referral_fee_bps` of the swapped amount rounded down.
+/// None if it exceeds the fee of the swap
+pub fn calc_referral_fee(amount: u64, referral_fee_bps: u16, fee_amount: u64) -> Option<u64> {
+    // the product of u64 and u16 always fits u128 and the share of the amount fits u64
+    let referral_fee = to_u64(to_u128(amount) * referral_fee_bps as u128 / 10_000).ok()?;
+    if referral_fee > fee_amount {
+        return None;
+    }
+    Some(referral_fee)
+}
+
+/// Fee growing with the imbalance of the reserves after the swap.
+/// imbalance = |X' - Y'| / (X' + Y'), X' = X + dX, Y' = X * Y / X'
+/// numerator = base numerator * (1 + (DYNAMIC_FEE_MAX_MULTIPLIER - 1) * imbalance)
+/// Returns (fee numerator, fee denominator)
+pub fn dynamic_fee(
+    base_fee: (u64, u64),
+    reserve_in: u64,
+    reserve_out: u64,
+    amount_in: u64,
+) -> Option<(u64, u64)> {
+    let (base_numerator, denominator) = base_fee;
+    let reserve_in = to_u128(reserve_in);
+    let reserve_out = to_u128(reserve_out);
+
+    let new_reserve_in = reserve_in.checked_add(to_u128(amount_in))?;
+    let new_reserve_out = reserve_in.checked_mul(reserve_out)?.checked_div(new_reserve_in)?;
+
+    let imbalance_bps = if new_reserve_in > new_reserve_out {
+        new_reserve_in - new_reserve_out
+    } else {
+        new_reserve_out - new_reserve_in
+    }
+        .checked_mul(BPS)?
+        .checked_div(new_reserve_in.checked_add(new_reserve_out)?)?;
+
+    let max_numerator = to_u128(base_numerator)
+        .checked_mul(to_u128(DYNAMIC_FEE_MAX_MULTIPLIER))?
+        .min(to_u128(denominator));
+    let numerator = to_u128(base_numerator)
+        .checked_mul(to_u128(DYNAMIC_FEE_MAX_MULTIPLIER.checked_sub(1)?))?
+        .checked_mul(imbalance_bps)?
+        .checked_div(BPS)?
+        .checked_add(to_u128(base_numerator))?
+        .min(max_numerator);
+    let numerator = to_u64(numerator).ok()?;
+
+    Some((numerator, denominator))
+}
+
+/// Price of token X in token Y adjusted by the decimals of the tokens, Q64.64 fixed-point.
+/// price = (Y / 10^dec_y) / (X / 10^dec_x)
+pub fn normalized_price(
+    reserve_x: u64,
+    reserve_y: u64,
+    decimals_x: u8,
+    decimals_y: u8,
+) -> Option<u128> {
+    if reserve_x == 0 {
+        return None
+    }
+    let reserve_x = to_u128(reserve_x);
+    let reserve_y = to_u128(reserve_y);
+
+    // Y / X in Q64.64, Y < 2^64 so the shift never overflows
+    let raw_price = (reserve_y << 64).checked_div(reserve_x)?;
+
+    if decimals_x >= decimals_y {
+        // scale the remainder too, so multiplying does not lose precision
+        let remainder = (reserve_y << 64).checked_rem(reserve_x)?;
+        let scale = 10u128.checked_pow((decimals_x - decimals_y) as u32)?;
+        raw_price.checked_mul(scale)?
+            .checked_add(remainder.checked_mul(scale)?.checked_div(reserve_x)?)
+    } else {
+        let scale = 10u128.checked_pow((decimals_y - decimals_x) as u32)?;
+        raw_price.checked_div(scale)
+    }
+}
+
+trait CheckedCeilDiv: Sized {
+    /// Ceiling division, returns (quotient, adjusted divisor).
+    /// The same as `spl_math::checked_ceil_div::CheckedCeilDiv`.
+    fn checked_ceil_div(&self, rhs: Self) -> Option<(Self, Self)>;
+}
+
+impl CheckedCeilDiv for u128 {
+    fn checked_ceil_div(&self, mut rhs: Self) -> Option<(Self, Self)> {
+        let mut quotient = self.checked_div(rhs)?;
+        // Avoid dividing a small number by a big one and returning 1
+        if quotient == 0 {
+            return None
+        }
+
+        let remainder = self.checked_rem(rhs)?;
+        if remainder > 0 {
+            quotient = quotient.checked_add(1)?;
+            // the minimum divisor to get the quotient, to avoid truncating too much
+            rhs = self.checked_div(quotient)?;
+            let remainder = self.checked_rem(quotient)?;
+            if remainder > 0 {
+                rhs = rhs.checked_add(1)?;
+            }
+        }
+        Some((quotient, rhs))
+    }
+}
@@ -1,4 +1,5 @@
 use solana_program::pubkey::Pubkey;
+use crate::error::AmmError;
 use crate::id;
 
 pub const SPL_TOKEN_X_OWNER_SEED: &[u8] = b"SPL_TOKEN_X_OWNER";
@@ -6,6 +7,7 @@ pub const SPL_TOKEN_Y_OWNER_SEED: &[u8] = b"SPL_TOKEN_Y_OWNER";
 pub const VAULT_SEED: &[u8] = b"VAULT";
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pda {
     pub pda_owner_token_x: (Pubkey, u8),
     pub pda_owner_token_y: (Pubkey, u8),
@@ -38,6 +40,78 @@ impl Pda {
 
         Pda { pda_owner_token_x, pda_owner_token_y, pda_token_x_pk, pda_token_y_pk, vault }
     }
+
+    /// `generate` for every pair of the minters X, Y
+    pub fn generate_many(pairs: &[(Pubkey, Pubkey)]) -> Vec<Pda> {
+        pairs.iter()
+            .map(|(minter_x_pk, minter_y_pk)| Pda::generate(minter_x_pk, minter_y_pk))
+            .collect()
+    }
+
+    /// Same as `generate` with the known canonical bumps, e.g. cached from `bumps`,
+    /// the owners and the vault are derived without the search of the bumps.
+    /// A non-canonical bump isn't detected here, it derives another address
+    pub fn generate_with_bumps(minter_x_pk: &Pubkey, minter_y_pk: &Pubkey, bumps: [u8; 3]) -> Result<Pda, AmmError> {
+        let [owner_token_x_bump, owner_token_y_bump, vault_bump] = bumps;
+
+        let pda_owner_token_x = (
+            create_pk(SPL_TOKEN_X_OWNER_SEED, minter_x_pk, minter_y_pk, owner_token_x_bump)?,
+            owner_token_x_bump,
+        );
+        let pda_token_x_pk = spl_associated_token_account::get_associated_token_address(
+            &pda_owner_token_x.0,
+            minter_x_pk,
+        );
+
+        let pda_owner_token_y = (
+            create_pk(SPL_TOKEN_Y_OWNER_SEED, minter_x_pk, minter_y_pk, owner_token_y_bump)?,
+            owner_token_y_bump,
+        );
+        let pda_token_y_pk = spl_associated_token_account::get_associated_token_address(
+            &pda_owner_token_y.0,
+            minter_y_pk,
+        );
+
+        let vault = (
+            create_pk(VAULT_SEED, minter_x_pk, minter_y_pk, vault_bump)?,
+            vault_bump,
+        );
+
+        Ok(Pda { pda_owner_token_x, pda_owner_token_y, pda_token_x_pk, pda_token_y_pk, vault })
+    }
+
+    /// Bumps of the SPL token X owner, SPL token Y owner, Vault for `generate_with_bumps`
+    pub fn bumps(&self) -> [u8; 3] {
+        [self.pda_owner_token_x.1, self.pda_owner_token_y.1, self.vault.1]
+    }
+
+    /// Addresses in the order of the accounts of the instructions:
+    /// SPL token X holder, SPL token Y holder, SPL token X owner, SPL token Y owner, Vault
+    pub fn keys(&self) -> [Pubkey; 5] {
+        [
+            self.pda_token_x_pk,
+            self.pda_token_y_pk,
+            self.pda_owner_token_x.0,
+            self.pda_owner_token_y.0,
+            self.vault.0,
+        ]
+    }
+
+    /// Passed PDA accounts, in the order of `keys`, are distinct
+    /// and none of them takes the place of another PDA.
+    /// An address derived for no role isn't checked here
+    pub fn check_roles(&self, pks: &[&Pubkey; 5]) -> Result<(), AmmError> {
+        let keys = self.keys();
+        for (i, pk) in pks.iter().enumerate() {
+            if pks[i + 1..].contains(pk) {
+                return Err(AmmError::PdaRoleMismatch);
+            }
+            if **pk != keys[i] && keys.contains(pk) {
+                return Err(AmmError::PdaRoleMismatch);
+            }
+        }
+        Ok(())
+    }
 }
 
 pub fn find_pk_and_bump(
@@ -55,3 +129,23 @@ pub fn find_pk_and_bump(
         &id()
     )
 }
+
+/// Derive the PDA with the bump, no search like `find_pk_and_bump`.
+/// The bump deriving no address is `InvalidBump`
+pub fn create_pk(
+    key_name: &[u8],
+    minter_x: &Pubkey,
+    minter_y: &Pubkey,
+    bump: u8,
+) -> Result<Pubkey, AmmError> {
+    Pubkey::create_program_address(
+        &[
+            key_name,
+            &minter_x.to_bytes(),
+            &minter_y.to_bytes(),
+            &spl_token::id().to_bytes(),
+            &[bump],
+        ],
+        &id()
+    ).map_err(|_| AmmError::InvalidBump)
+}
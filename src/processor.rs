@@ -1,34 +1,236 @@
+use std::slice::Iter;
 use solana_program::account_info::{AccountInfo, next_account_info};
 use solana_program::entrypoint::ProgramResult;
 use solana_program::{msg, system_instruction};
 use solana_program::pubkey::Pubkey;
+use solana_program::instruction::Instruction;
 use borsh::{BorshDeserialize, BorshSerialize};
-use solana_program::program::{invoke, invoke_signed};
+use solana_program::program::{invoke, invoke_signed, set_return_data};
 use solana_program::program_error::ProgramError;
+use solana_program::program_pack::Pack;
+use solana_program::clock::Clock;
 use solana_program::rent::Rent;
-use solana_program::sysvar::Sysvar;
+use solana_program::sysvar::{self, Sysvar};
 use crate::error::AmmError;
-use crate::state::Vault;
-use crate::instruction::AmmInstruction;
+use crate::state::{
+    MarketChecks, ReservesReport, Vault, VaultAccount, FEE_DENOMINATOR, MAX_INIT_RATIO, MAX_REFERRAL_FEE_BPS, MAX_RESERVE,
+};
+use crate::instruction::{AmmInstruction, InitMarketOptions, SwapOptions};
 use crate::id;
-use crate::pda::{VAULT_SEED, SPL_TOKEN_X_OWNER_SEED, SPL_TOKEN_Y_OWNER_SEED, Pda};
-use crate::swap::{calc_swap, SwapDirection};
+#[cfg(feature = "binary-logs")]
+use crate::log::SwapLog;
+use crate::pda::{VAULT_SEED, SPL_TOKEN_X_OWNER_SEED, SPL_TOKEN_Y_OWNER_SEED, Pda, find_pk_and_bump};
+use crate::swap::{
+    apply_output_granularity, calc_referral_fee, invariant_ok, normalized_price, price_impact_bps, try_calc_swap_with_fee, SwapDirection,
+    SwapError,
+};
 
 pub struct Processor;
 
+/// Accounts of `InitMarket` in the order of `AmmInstruction::get_init_market_account_meta`
+pub struct InitMarketAccounts<'a, 'b> {
+    pub user_owner_token_x_info: &'a AccountInfo<'b>,
+    pub user_owner_token_y_info: &'a AccountInfo<'b>,
+    pub user_payer_info: &'a AccountInfo<'b>,
+    pub user_token_x_info: &'a AccountInfo<'b>,
+    pub user_token_y_info: &'a AccountInfo<'b>,
+    pub minter_x_info: &'a AccountInfo<'b>,
+    pub minter_y_info: &'a AccountInfo<'b>,
+    pub pda_token_x_info: &'a AccountInfo<'b>,
+    pub pda_token_y_info: &'a AccountInfo<'b>,
+    pub pda_owner_token_x_info: &'a AccountInfo<'b>,
+    pub pda_owner_token_y_info: &'a AccountInfo<'b>,
+    pub pda_vault_info: &'a AccountInfo<'b>,
+    pub rent_info: &'a AccountInfo<'b>,
+    pub system_info: &'a AccountInfo<'b>,
+    pub spl_token_program_info: &'a AccountInfo<'b>,
+    pub spl_associated_token_program_info: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> InitMarketAccounts<'a, 'b> {
+    /// Reads the accounts by the position, nothing is verified but the count
+    pub fn read(accounts: &'a [AccountInfo<'b>]) -> Result<InitMarketAccounts<'a, 'b>, ProgramError> {
+        let acc_iter = &mut accounts.iter();
+
+        let init_market_accounts = InitMarketAccounts {
+            // user accounts
+            user_owner_token_x_info: next_account_info(acc_iter)?,
+            user_owner_token_y_info: next_account_info(acc_iter)?,
+            user_payer_info: next_account_info(acc_iter)?,
+            user_token_x_info: next_account_info(acc_iter)?,
+            user_token_y_info: next_account_info(acc_iter)?,
+            minter_x_info: next_account_info(acc_iter)?,
+            minter_y_info: next_account_info(acc_iter)?,
+
+            // contract accounts
+            pda_token_x_info: next_account_info(acc_iter)?,
+            pda_token_y_info: next_account_info(acc_iter)?,
+            pda_owner_token_x_info: next_account_info(acc_iter)?,
+            pda_owner_token_y_info: next_account_info(acc_iter)?,
+            pda_vault_info: next_account_info(acc_iter)?,
+
+            // service accounts
+            // only passed to the SPL associated token account program
+            rent_info: next_account_info(acc_iter)?,
+            system_info: next_account_info(acc_iter)?,
+            spl_token_program_info: next_account_info(acc_iter)?,
+            spl_associated_token_program_info: next_account_info(acc_iter)?,
+        };
+        Processor::check_no_more_accounts(acc_iter)?;
+
+        Ok(init_market_accounts)
+    }
+}
+
+/// Accounts of `Swap` in the order of `AmmInstruction::get_swap_account_meta`
+pub struct SwapAccounts<'a, 'b> {
+    pub user_owner_token_info: &'a AccountInfo<'b>,
+    pub user_token_x_info: &'a AccountInfo<'b>,
+    pub user_token_y_info: &'a AccountInfo<'b>,
+    pub minter_x_info: &'a AccountInfo<'b>,
+    pub minter_y_info: &'a AccountInfo<'b>,
+    pub pda_token_x_info: &'a AccountInfo<'b>,
+    pub pda_token_y_info: &'a AccountInfo<'b>,
+    pub pda_owner_token_x_info: &'a AccountInfo<'b>,
+    pub pda_owner_token_y_info: &'a AccountInfo<'b>,
+    pub pda_vault_info: &'a AccountInfo<'b>,
+    pub spl_token_program_info: &'a AccountInfo<'b>,
+    pub destination_token_info: Option<&'a AccountInfo<'b>>,
+    pub referrer_token_info: Option<&'a AccountInfo<'b>>,
+    pub instructions_sysvar_info: Option<&'a AccountInfo<'b>>,
+    pub rent_info: Option<&'a AccountInfo<'b>>,
+    pub system_info: Option<&'a AccountInfo<'b>>,
+    pub spl_associated_token_program_info: Option<&'a AccountInfo<'b>>,
+    pub return_token_payer_info: Option<&'a AccountInfo<'b>>,
+}
+
+impl<'a, 'b> SwapAccounts<'a, 'b> {
+    /// Reads the accounts by the position, the optional ones past the SPL token program by the options.
+    /// Nothing is verified but the count
+    pub fn read(
+        accounts: &'a [AccountInfo<'b>],
+        options: &SwapOptions,
+    ) -> Result<SwapAccounts<'a, 'b>, ProgramError> {
+        let acc_iter = &mut accounts.iter();
+
+        let mut swap_accounts = SwapAccounts {
+            // user accounts
+            user_owner_token_info: next_account_info(acc_iter)?,
+            user_token_x_info: next_account_info(acc_iter)?,
+            user_token_y_info: next_account_info(acc_iter)?,
+            minter_x_info: next_account_info(acc_iter)?,
+            minter_y_info: next_account_info(acc_iter)?,
+
+            // contract accounts
+            pda_token_x_info: next_account_info(acc_iter)?,
+            pda_token_y_info: next_account_info(acc_iter)?,
+            pda_owner_token_x_info: next_account_info(acc_iter)?,
+            pda_owner_token_y_info: next_account_info(acc_iter)?,
+            pda_vault_info: next_account_info(acc_iter)?,
+
+            // service accounts
+            spl_token_program_info: next_account_info(acc_iter)?,
+
+            destination_token_info: None,
+            referrer_token_info: None,
+            instructions_sysvar_info: None,
+            rent_info: None,
+            system_info: None,
+            spl_associated_token_program_info: None,
+            return_token_payer_info: None,
+        };
+
+        // optional accounts, present only with their options
+        if options.reject_earlier_swap_in_tx {
+            swap_accounts.instructions_sysvar_info = Some(next_account_info(acc_iter)?);
+        }
+        if options.create_return_token {
+            swap_accounts.rent_info = Some(next_account_info(acc_iter)?);
+            swap_accounts.system_info = Some(next_account_info(acc_iter)?);
+            swap_accounts.spl_associated_token_program_info = Some(next_account_info(acc_iter)?);
+            if options.return_token_payer.is_some() {
+                swap_accounts.return_token_payer_info = Some(next_account_info(acc_iter)?);
+            }
+        }
+        if options.referrer.is_some() {
+            swap_accounts.referrer_token_info = Some(next_account_info(acc_iter)?);
+        }
+        if options.return_to_destination {
+            swap_accounts.destination_token_info = Some(next_account_info(acc_iter)?);
+        }
+        Processor::check_no_more_accounts(acc_iter)?;
+
+        Ok(swap_accounts)
+    }
+}
+
 impl Processor {
     pub fn process(_program_id: &Pubkey,
                    accounts: &[AccountInfo],
                    instruction_data: &[u8]) -> ProgramResult {
-        let ix = AmmInstruction::try_from_slice(instruction_data)?;
+        let data = &mut &instruction_data[..];
+        let ix = AmmInstruction::deserialize(data)?;
+        // trailing bytes are a bug of the client, not a part of the instruction
+        if !data.is_empty() {
+            msg!("Error: {} bytes past the instruction data", data.len());
+            return Err(ProgramError::InvalidInstructionData);
+        }
         match ix {
             AmmInstruction::InitMarket { amount_x, amount_y } => {
                 msg!("AmmInstruction: InitMarket");
-                Self::process_init_market(amount_x, amount_y, accounts)
+                Self::process_init_market(amount_x, amount_y, InitMarketOptions::default(), accounts)
             }
-            AmmInstruction::Swap { amount, minter_pk } => {
+            AmmInstruction::Swap { amount, minter_pk, options } => {
                 msg!("AmmInstruction: Swap");
-                Self::process_swap(amount, minter_pk, accounts)
+                Self::process_swap(amount, minter_pk, options, accounts)
+            }
+            AmmInstruction::ProposeAuthority { new_authority } => {
+                msg!("AmmInstruction: ProposeAuthority");
+                Self::process_propose_authority(new_authority, accounts)
+            }
+            AmmInstruction::AcceptAuthority => {
+                msg!("AmmInstruction: AcceptAuthority");
+                Self::process_accept_authority(accounts)
+            }
+            AmmInstruction::SetFee { fee_numerator, fee_denominator, dynamic_fee_enabled } => {
+                msg!("AmmInstruction: SetFee");
+                Self::process_set_fee(fee_numerator, fee_denominator, dynamic_fee_enabled, accounts)
+            }
+            AmmInstruction::InitMarketWithOptions { amount_x, amount_y, options } => {
+                msg!("AmmInstruction: InitMarketWithOptions");
+                Self::process_init_market(amount_x, amount_y, options, accounts)
+            }
+            AmmInstruction::RescueTokens { amount } => {
+                msg!("AmmInstruction: RescueTokens");
+                Self::process_rescue_tokens(amount, accounts)
+            }
+            AmmInstruction::Donate { amount_x, amount_y } => {
+                msg!("AmmInstruction: Donate");
+                Self::process_donate(amount_x, amount_y, accounts)
+            }
+            AmmInstruction::Inspect => {
+                msg!("AmmInstruction: Inspect");
+                Self::process_inspect(accounts)
+            }
+            AmmInstruction::SetFeeRecipient { recipient } => {
+                msg!("AmmInstruction: SetFeeRecipient");
+                Self::process_set_fee_recipient(recipient, accounts)
+            }
+            AmmInstruction::SetPaused { paused } => {
+                msg!("AmmInstruction: SetPaused");
+                Self::process_set_paused(paused, accounts)
+            }
+            AmmInstruction::EmergencyWithdraw => {
+                msg!("AmmInstruction: EmergencyWithdraw");
+                Self::process_emergency_withdraw(accounts)
+            }
+            AmmInstruction::SetFeeExempt { fee_exempt } => {
+                msg!("AmmInstruction: SetFeeExempt");
+                Self::process_set_fee_exempt(fee_exempt, accounts)
+            }
+            AmmInstruction::Validate => {
+                msg!("AmmInstruction: Validate");
+                Self::process_validate(accounts)
             }
         }
     }
@@ -36,34 +238,28 @@ impl Processor {
     fn process_init_market(
         amount_x: u64,
         amount_y: u64,
+        options: InitMarketOptions,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
         msg!("process_init_market: Reading accounts");
-        let acc_iter = &mut accounts.iter();
-
-        // user accounts
-        let user_owner_token_x_info = next_account_info(acc_iter)?;
-        let user_owner_token_y_info = next_account_info(acc_iter)?;
-        let user_payer_info = next_account_info(acc_iter)?;
-        let user_token_x_info = next_account_info(acc_iter)?;
-        let user_token_y_info = next_account_info(acc_iter)?;
-        let minter_x_info = next_account_info(acc_iter)?;
-        let minter_y_info = next_account_info(acc_iter)?;
-
-        // contract accounts
-        let pda_token_x_info = next_account_info(acc_iter)?;
-        let pda_token_y_info = next_account_info(acc_iter)?;
-        let pda_owner_token_x_info = next_account_info(acc_iter)?;
-        let pda_owner_token_y_info = next_account_info(acc_iter)?;
-        let pda_vault_info = next_account_info(acc_iter)?;
-
-        // service accounts
-        let rent_info = next_account_info(acc_iter)?;
-        let rent = Rent::from_account_info(rent_info)?;
-        let system_info = next_account_info(acc_iter)?;
-        let spl_token_program_info = next_account_info(acc_iter)?;
-        let spl_associated_token_program_info = next_account_info(acc_iter)?;
-
+        let InitMarketAccounts {
+            user_owner_token_x_info,
+            user_owner_token_y_info,
+            user_payer_info,
+            user_token_x_info,
+            user_token_y_info,
+            minter_x_info,
+            minter_y_info,
+            pda_token_x_info,
+            pda_token_y_info,
+            pda_owner_token_x_info,
+            pda_owner_token_y_info,
+            pda_vault_info,
+            rent_info,
+            system_info,
+            spl_token_program_info,
+            spl_associated_token_program_info,
+        } = InitMarketAccounts::read(accounts)?;
 
         msg!("process_init_market: Verifying accounts");
         if !user_owner_token_x_info.is_signer {
@@ -89,6 +285,13 @@ impl Processor {
         let pda_associated_token_y_pk = pda.pda_token_y_pk;
         let (vault_pk, vault_bump) = pda.vault;
 
+        pda.check_roles(&[
+            pda_token_x_info.key,
+            pda_token_y_info.key,
+            pda_owner_token_x_info.key,
+            pda_owner_token_y_info.key,
+            pda_vault_info.key,
+        ])?;
         if *pda_owner_token_x_info.key != pda_owner_token_x_pk {
             msg!("Error: Pda owner token X address does not match seed derivation");
             return Err(ProgramError::InvalidSeeds);
@@ -109,10 +312,69 @@ impl Processor {
             msg!("Error: Pda vault address does not match seed derivation");
             return Err(ProgramError::InvalidSeeds);
         }
+        Self::check_vault_writable(pda_vault_info)?;
+
+        // the PDA SPL token holders are derived by the canonical program,
+        // another program must not be invoked with the signature of the payer
+        if !spl_associated_token_account::check_id(spl_associated_token_program_info.key) {
+            msg!(
+                "Error: SPL associated token account program={} is not {}",
+                spl_associated_token_program_info.key, spl_associated_token_account::id()
+            );
+            return Err(AmmError::IncorrectAssociatedTokenProgram.into());
+        }
 
         if amount_x == 0 || amount_y == 0 {
             return Err(AmmError::AmountZero.into());
         }
+        if amount_x > MAX_RESERVE || amount_y > MAX_RESERVE {
+            return Err(AmmError::ReserveTooLarge.into());
+        }
+        let (min_amount, max_amount) = (amount_x.min(amount_y), amount_x.max(amount_y));
+        if max_amount as u128 > min_amount as u128 * MAX_INIT_RATIO as u128 {
+            return Err(AmmError::ExtremeInitRatio.into());
+        }
+        // the empty market of an earlier InitMarket is re-seeded by its authority only,
+        // so a market drained by the withdrawals or `EmergencyWithdraw` is not taken over
+        let (reseed, min_reserve) = if pda_vault_info.data_is_empty() {
+            (false, options.min_reserve)
+        } else if options.allow_existing {
+            let vault = Vault::load(&pda_vault_info.data.borrow())?;
+            if vault.token_x_amount != 0 || vault.token_y_amount != 0 {
+                return Err(AmmError::AlreadyInUse.into());
+            }
+            if vault.is_initialized() {
+                Self::assert_authority(&vault, user_payer_info)?;
+                (true, vault.min_reserve)
+            } else {
+                (false, options.min_reserve)
+            }
+        } else {
+            return Err(AmmError::AlreadyInUse.into());
+        };
+        if min_amount < min_reserve {
+            msg!("Error: Initial amounts are below the minimum reserve");
+            return Err(AmmError::BelowMinReserve.into());
+        }
+        // fail before paying the rent of the PDA accounts
+        let user_token_x = spl_token::state::Account::unpack(&user_token_x_info.data.borrow())?;
+        if user_token_x.owner != *user_owner_token_x_info.key {
+            msg!("Error: User SPL token X holder is not owned by the signer");
+            return Err(AmmError::NotTokenOwner.into());
+        }
+        if user_token_x.amount < amount_x {
+            msg!("Error: User SPL token X holder has {}, required {}", user_token_x.amount, amount_x);
+            return Err(AmmError::InsufficientUserBalance.into());
+        }
+        let user_token_y = spl_token::state::Account::unpack(&user_token_y_info.data.borrow())?;
+        if user_token_y.owner != *user_owner_token_y_info.key {
+            msg!("Error: User SPL token Y holder is not owned by the signer");
+            return Err(AmmError::NotTokenOwner.into());
+        }
+        if user_token_y.amount < amount_y {
+            msg!("Error: User SPL token Y holder has {}, required {}", user_token_y.amount, amount_y);
+            return Err(AmmError::InsufficientUserBalance.into());
+        }
 
 
         if pda_token_x_info.data_is_empty() {
@@ -135,6 +397,10 @@ impl Processor {
                     spl_associated_token_program_info.clone()
                 ],
             )?;
+        } else if options.allow_existing {
+            msg!("process_init_market: Using existing pda token X associated account");
+        } else if Self::is_unused_pda_token(pda_token_x_info, &pda_owner_token_x_pk, minter_x_info.key) {
+            msg!("process_init_market: Using pda token X associated account created before the market");
         } else {
             return Err(AmmError::AlreadyInUse.into());
         }
@@ -159,17 +425,21 @@ impl Processor {
                     spl_associated_token_program_info.clone()
                 ],
             )?;
+        } else if options.allow_existing {
+            msg!("process_init_market: Using existing pda token Y associated account");
+        } else if Self::is_unused_pda_token(pda_token_y_info, &pda_owner_token_y_pk, minter_y_info.key) {
+            msg!("process_init_market: Using pda token Y associated account created before the market");
         } else {
             return Err(AmmError::AlreadyInUse.into());
         }
 
         msg!("process_init_market: Transfer amount_x={} to pda token X associated account", amount_x);
-        let transfer_token_x_ix = spl_token::instruction::transfer(
-            spl_token_program_info.key,
-            user_token_x_info.key,
-            pda_token_x_info.key,
-            user_owner_token_x_info.key,
-            &[&user_owner_token_x_info.key],
+        let transfer_token_x_ix = Self::transfer_checked_ix(
+            spl_token_program_info,
+            user_token_x_info,
+            minter_x_info,
+            pda_token_x_info,
+            user_owner_token_x_info,
             amount_x,
         )?;
         invoke(
@@ -177,18 +447,19 @@ impl Processor {
             &[
                 spl_token_program_info.clone(),
                 user_token_x_info.clone(),
+                minter_x_info.clone(),
                 pda_token_x_info.clone(),
                 user_owner_token_x_info.clone()
             ],
         )?;
 
         msg!("process_init_market: Transfer amount_y={} to pda token Y associated account", amount_y);
-        let transfer_token_y_ix = spl_token::instruction::transfer(
-            spl_token_program_info.key,
-            user_token_y_info.key,
-            pda_token_y_info.key,
-            user_owner_token_y_info.key,
-            &[&user_owner_token_y_info.key],
+        let transfer_token_y_ix = Self::transfer_checked_ix(
+            spl_token_program_info,
+            user_token_y_info,
+            minter_y_info,
+            pda_token_y_info,
+            user_owner_token_y_info,
             amount_y,
         )?;
         invoke(
@@ -196,6 +467,7 @@ impl Processor {
             &[
                 spl_token_program_info.clone(),
                 user_token_y_info.clone(),
+                minter_y_info.clone(),
                 pda_token_y_info.clone(),
                 user_owner_token_y_info.clone()
             ],
@@ -204,9 +476,8 @@ impl Processor {
 
         if pda_vault_info.data_is_empty() {
             msg!("process_init_market: Creating vault account");
-            let vault = Vault { token_x_amount: 0, token_y_amount: 0 };
-            let space = vault.try_to_vec()?.len();
-            let rent_value = rent.minimum_balance(space);
+            let space = Vault::LEN;
+            let rent_value = Rent::get()?.minimum_balance(space);
             let create_vault_acc_ix = system_instruction::create_account(
                 user_payer_info.key,
                 pda_vault_info.key,
@@ -226,19 +497,35 @@ impl Processor {
                 ]],
             )?;
         } else {
-            return Err(AmmError::AlreadyInUse.into());
+            msg!("process_init_market: Using existing empty vault account");
         }
 
 
-        let mut vault: Vault = Vault::try_from_slice(&pda_vault_info.data.borrow())?;
+        let timestamp = Clock::get()?.unix_timestamp;
+        let decimals_x = spl_token::state::Mint::unpack(&minter_x_info.data.borrow())?.decimals;
+        let decimals_y = spl_token::state::Mint::unpack(&minter_y_info.data.borrow())?.decimals;
+        let mut vault = VaultAccount::load(pda_vault_info)?;
         msg!(
             "process_init_market: Current amount_x={}, amount_y={} from vault account",
             vault.token_x_amount, vault.token_y_amount
         );
-        vault.token_x_amount = amount_x;
-        vault.token_y_amount = amount_y;
+        vault.set_reserves(amount_x, amount_y);
+        vault.last_timestamp = timestamp;
+        // the re-seeded market keeps the authority, the fees and the pause of the stored configuration
+        if !reseed {
+            vault.authority = options.authority.unwrap_or(*user_payer_info.key);
+            vault.fee_numerator = 0;
+            vault.fee_denominator = FEE_DENOMINATOR;
+            vault.dynamic_fee_enabled = false;
+            vault.minter_x = *minter_x_info.key;
+            vault.minter_y = *minter_y_info.key;
+            vault.decimals_x = decimals_x;
+            vault.decimals_y = decimals_y;
+            vault.min_reserve = options.min_reserve;
+            vault.launch_slot = options.launch_slot;
+        }
 
-        vault.serialize(&mut &mut pda_vault_info.data.borrow_mut()[..])?;
+        vault.commit()?;
         msg!(
             "process_init_market: Saved new amount_x={}, amount_y={} to vault account",
             vault.token_x_amount, vault.token_y_amount
@@ -250,27 +537,30 @@ impl Processor {
     fn process_swap(
         amount: u64,
         minter_pk: Pubkey,
+        options: SwapOptions,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
         msg!("process_swap: Reading accounts");
-        let acc_iter = &mut accounts.iter();
-
-        // user accounts
-        let user_owner_token_info = next_account_info(acc_iter)?;
-        let user_token_x_info = next_account_info(acc_iter)?;
-        let user_token_y_info = next_account_info(acc_iter)?;
-        let minter_x_info = next_account_info(acc_iter)?;
-        let minter_y_info = next_account_info(acc_iter)?;
-
-        // contract accounts
-        let pda_token_x_info = next_account_info(acc_iter)?;
-        let pda_token_y_info = next_account_info(acc_iter)?;
-        let pda_owner_token_x_info = next_account_info(acc_iter)?;
-        let pda_owner_token_y_info = next_account_info(acc_iter)?;
-        let pda_vault_info = next_account_info(acc_iter)?;
-
-        // service accounts
-        let spl_token_program_info = next_account_info(acc_iter)?;
+        let SwapAccounts {
+            user_owner_token_info,
+            user_token_x_info,
+            user_token_y_info,
+            minter_x_info,
+            minter_y_info,
+            pda_token_x_info,
+            pda_token_y_info,
+            pda_owner_token_x_info,
+            pda_owner_token_y_info,
+            pda_vault_info,
+            spl_token_program_info,
+            destination_token_info,
+            referrer_token_info,
+            instructions_sysvar_info,
+            rent_info,
+            system_info,
+            spl_associated_token_program_info,
+            return_token_payer_info,
+        } = SwapAccounts::read(accounts, &options)?;
 
         msg!("process_swap: Verifying accounts");
         if !user_owner_token_info.is_signer {
@@ -280,9 +570,79 @@ impl Processor {
         if minter_x_info.key == minter_y_info.key {
             return Err(AmmError::IdenticalMinter.into());
         }
+        // before the derivations of the PDA, a bad input fails fast
         if minter_pk != *minter_x_info.key && minter_pk != *minter_y_info.key {
+            msg!("Error: Swap minter={} is neither minter X nor minter Y", minter_pk);
             return Err(AmmError::IncorrectSwapPk.into());
         }
+        if user_token_x_info.key == user_token_y_info.key {
+            msg!("Error: User SPL token X and Y holders are the same account");
+            return Err(AmmError::AliasedAccounts.into());
+        }
+        // read with `SwapOptions::create_return_token`
+        if let (Some(rent_info), Some(system_info), Some(spl_associated_token_program_info)) =
+            (rent_info, system_info, spl_associated_token_program_info) {
+            // the optional accounts are read by the position, another program must not be invoked
+            // with the signature of the user
+            if !spl_associated_token_account::check_id(spl_associated_token_program_info.key) {
+                msg!(
+                    "Error: SPL associated token account program={} is not {}",
+                    spl_associated_token_program_info.key, spl_associated_token_account::id()
+                );
+                return Err(AmmError::IncorrectAssociatedTokenProgram.into());
+            }
+            // read with `SwapOptions::return_token_payer`, the user SPL token owner pays by default
+            let payer_info = match (options.return_token_payer, return_token_payer_info) {
+                (Some(return_token_payer), Some(payer_info)) => {
+                    if *payer_info.key != return_token_payer {
+                        msg!("Error: Payer of the returned token holder is not {}", return_token_payer);
+                        return Err(ProgramError::InvalidArgument);
+                    }
+                    if !payer_info.is_signer {
+                        msg!("Error: Required signature for payer of the returned token holder");
+                        return Err(ProgramError::MissingRequiredSignature);
+                    }
+                    payer_info
+                }
+                _ => user_owner_token_info,
+            };
+            let (return_minter_info, user_return_token_info) = if minter_pk == *minter_x_info.key {
+                (minter_y_info, user_token_y_info)
+            } else {
+                (minter_x_info, user_token_x_info)
+            };
+            if user_return_token_info.data_is_empty() {
+                msg!("process_swap: Creating user SPL token holder of the returned token");
+                let create_associated_token_acc_ix = spl_associated_token_account::create_associated_token_account(
+                    payer_info.key,
+                    user_owner_token_info.key,
+                    return_minter_info.key,
+                );
+                invoke(
+                    &create_associated_token_acc_ix,
+                    &[
+                        payer_info.clone(),
+                        user_return_token_info.clone(),
+                        user_owner_token_info.clone(),
+                        return_minter_info.clone(),
+                        system_info.clone(),
+                        spl_token_program_info.clone(),
+                        rent_info.clone(),
+                        spl_associated_token_program_info.clone()
+                    ],
+                )?;
+            }
+        }
+        let user_token_x = spl_token::state::Account::unpack(&user_token_x_info.data.borrow())?;
+        if user_token_x.mint != *minter_x_info.key {
+            msg!("Error: User SPL token X holder has incorrect minter");
+            return Err(AmmError::IncorrectUserTokenMint.into());
+        }
+        let user_token_y = spl_token::state::Account::unpack(&user_token_y_info.data.borrow())?;
+        if user_token_y.mint != *minter_y_info.key {
+            msg!("Error: User SPL token Y holder has incorrect minter");
+            return Err(AmmError::IncorrectUserTokenMint.into());
+        }
 
         let pda = Pda::generate(minter_x_info.key, minter_y_info.key);
         let (pda_owner_token_x_pk, pda_owner_token_x_bump) = pda.pda_owner_token_x;
@@ -311,47 +671,194 @@ impl Processor {
             msg!("Error: Pda vault address does not match seed derivation");
             return Err(ProgramError::InvalidSeeds);
         }
+        Self::check_vault_writable(pda_vault_info)?;
+        // read with `SwapOptions::reject_earlier_swap_in_tx`
+        if let Some(instructions_sysvar_info) = instructions_sysvar_info {
+            Self::check_no_earlier_swap(instructions_sysvar_info, &vault_pk)?;
+        }
+        if options.referral_fee_bps > MAX_REFERRAL_FEE_BPS {
+            msg!("Error: Referral fee={} bps exceeds the maximum={} bps", options.referral_fee_bps, MAX_REFERRAL_FEE_BPS);
+            return Err(AmmError::InvalidFee.into());
+        }
+        if options.referral_fee_bps != 0 && options.referrer.is_none() {
+            msg!("Error: Referral fee={} bps without a referrer", options.referral_fee_bps);
+            return Err(AmmError::MissingReferrer.into());
+        }
+        // read with `SwapOptions::referrer`
+        if let (Some(referrer), Some(referrer_token_info)) = (options.referrer, referrer_token_info) {
+            let referrer_token_pk = spl_associated_token_account::get_associated_token_address(&referrer, &minter_pk);
+            if *referrer_token_info.key != referrer_token_pk {
+                msg!("Error: Referrer SPL token holder is not the associated token account of the referrer={}", referrer);
+                return Err(ProgramError::InvalidSeeds);
+            }
+        }
 
         if amount == 0 {
             return Err(AmmError::AmountZero.into());
         }
 
-        let mut vault: Vault = Vault::try_from_slice(&pda_vault_info.data.borrow())
-            .map_err(|_| Into::<ProgramError>::into(AmmError::InvalidVault))?;
+        // never created vault account is not a corrupt one
+        if pda_vault_info.data_is_empty() {
+            msg!("Error: Vault account is empty, init the market first");
+            return Err(AmmError::MarketNotInitialized.into());
+        }
+        let mut vault = VaultAccount::load(pda_vault_info)?;
         msg!(
             "process_swap: Current amount_x={}, amount_y={} from vault account",
             vault.token_x_amount, vault.token_y_amount
         );
+        if vault.minter_x != *minter_x_info.key || vault.minter_y != *minter_y_info.key {
+            msg!("Error: Minters X, Y do not match the minters stored in vault");
+            return Err(AmmError::MintMismatch.into());
+        }
+        if vault.paused {
+            return Err(AmmError::MarketPaused.into());
+        }
+        let slot = Clock::get()?.slot;
+        if slot < vault.launch_slot {
+            msg!("Error: Market is live from slot={}, current slot={}", vault.launch_slot, slot);
+            return Err(AmmError::MarketNotLive.into());
+        }
 
         let swap_direction = SwapDirection::new(&minter_pk, minter_x_info.key, minter_y_info.key)
             .ok_or(AmmError::IncorrectSwapPk)?;
 
-        let swap_result = match swap_direction {
-            SwapDirection::XtoY => calc_swap(
-                amount,
-                vault.token_x_amount,
-                vault.token_y_amount,
+        let (return_minter_pk, user_return_token_info) = match swap_direction {
+            SwapDirection::XtoY => (minter_y_info.key, user_token_y_info),
+            SwapDirection::YtoX => (minter_x_info.key, user_token_x_info),
+        };
+        let return_token_info = match destination_token_info {
+            Some(destination_token_info) => {
+                let destination_token = spl_token::state::Account::unpack(
+                    &destination_token_info.data.borrow()
+                )?;
+                if destination_token.mint != *return_minter_pk {
+                    return Err(AmmError::IncorrectDestinationMint.into());
+                }
+                destination_token_info
+            }
+            None => user_return_token_info,
+        };
+        if options.unwrap && *return_minter_pk != spl_token::native_mint::id() {
+            return Err(AmmError::UnwrapNotNative.into());
+        }
+
+        let (source_amount, destination_amount) = vault.swap_reserves(swap_direction);
+        if source_amount.checked_add(amount).is_none() {
+            msg!(
+                "Error: Swap amount={} overflows the reserve={}, try a smaller amount at most {}",
+                amount, source_amount, u64::MAX - source_amount
+            );
+            return Err(AmmError::ReserveOverflow.into());
+        }
+        let (fee_numerator, fee_denominator) = vault.swap_fee_for(user_owner_token_info.key, amount, swap_direction)
+            .ok_or(AmmError::Overflow)?;
+        msg!("process_swap: Fee numerator={}, denominator={}", fee_numerator, fee_denominator);
+
+        let swap_result = try_calc_swap_with_fee(
+            amount,
+            source_amount,
+            destination_amount,
+            fee_numerator,
+            fee_denominator,
+        ).map_err(|e| match e {
+            SwapError::NoOp => AmmError::NoOpSwap,
+            SwapError::ZeroReturn => AmmError::CalculatedZeroSwap,
+            SwapError::Overflow => AmmError::Overflow,
+        })?;
+        let swap_result = apply_output_granularity(swap_result, options.output_granularity)
+            .ok_or(AmmError::CalculatedZeroSwap)?;
+        // X * Y = K never drains a reserve, the rounding must not either
+        if swap_result.return_amount >= destination_amount {
+            msg!(
+                "Error: Swap return amount={} drains the reserve={}",
+                swap_result.return_amount, destination_amount
+            );
+            return Err(AmmError::ExcessiveOutput.into());
+        }
+        // the referrer is paid out of the fee, the rest of the fee stays in the market
+        let referral_fee = match referrer_token_info {
+            Some(_) => calc_referral_fee(amount, options.referral_fee_bps, swap_result.fee_amount).ok_or_else(|| {
+                msg!(
+                    "Error: Referral fee={} bps of the amount={} exceeds the swap fee={}",
+                    options.referral_fee_bps, amount, swap_result.fee_amount
+                );
+                AmmError::InvalidFee
+            })?,
+            None => 0,
+        };
+        // both are bounded by the take amount
+        let market_take_amount = swap_result.take_amount - referral_fee;
+        let market_fee_amount = swap_result.fee_amount - referral_fee;
+
+        let (nex_token_x_amount, nex_token_y_amount) = match swap_direction {
+            SwapDirection::XtoY => (
+                vault.token_x_amount.checked_add(market_take_amount)
+                    .ok_or(AmmError::Overflow)?,
+                vault.token_y_amount.checked_sub(swap_result.return_amount)
+                    .ok_or(AmmError::Underflow)?
             ),
-            SwapDirection::YtoX => calc_swap(
-                amount,
-                vault.token_y_amount,
-                vault.token_x_amount,
+            SwapDirection::YtoX => (
+                vault.token_x_amount.checked_sub(swap_result.return_amount)
+                    .ok_or(AmmError::Underflow)?,
+                vault.token_y_amount.checked_add(market_take_amount)
+                    .ok_or(AmmError::Overflow)?
             )
-        }.ok_or(AmmError::CalculatedZeroSwap)?;
+        };
+        // the product of u64 always fits u128
+        let invariant_before = vault.token_x_amount as u128 * vault.token_y_amount as u128;
+        let invariant_after = nex_token_x_amount as u128 * nex_token_y_amount as u128;
+        if !invariant_ok(invariant_before, invariant_after, market_fee_amount) {
+            msg!("Error: Invariant decreased from {} to {}", invariant_before, invariant_after);
+            return Err(AmmError::InvariantViolation.into());
+        }
+        if nex_token_x_amount < vault.min_reserve || nex_token_y_amount < vault.min_reserve {
+            msg!(
+                "Error: Reserves after swap X={}, Y={} are below the minimum reserve={}",
+                nex_token_x_amount, nex_token_y_amount, vault.min_reserve
+            );
+            return Err(AmmError::BelowMinReserve.into());
+        }
+
+        if let Some(price_limit) = options.price_limit {
+            let price = normalized_price(nex_token_x_amount, nex_token_y_amount, vault.decimals_x, vault.decimals_y)
+                .ok_or(AmmError::Overflow)?;
+            msg!("process_swap: Price after swap={}, limit={}", price, price_limit);
+            // X to Y lowers the price of X, Y to X raises it
+            let limit_exceeded = match swap_direction {
+                SwapDirection::XtoY => price < price_limit,
+                SwapDirection::YtoX => price > price_limit,
+            };
+            if limit_exceeded {
+                return Err(AmmError::PriceLimitExceeded.into());
+            }
+        }
+        if options.max_impact_bps > 0 {
+            let price_before = vault.spot_price().ok_or(AmmError::Overflow)?;
+            let price_after = normalized_price(nex_token_x_amount, nex_token_y_amount, vault.decimals_x, vault.decimals_y)
+                .ok_or(AmmError::Overflow)?;
+            let impact_bps = price_impact_bps(price_before, price_after).ok_or(AmmError::Overflow)?;
+            msg!("process_swap: Price impact={} bps, max={} bps", impact_bps, options.max_impact_bps);
+            if impact_bps > options.max_impact_bps as u64 {
+                return Err(AmmError::PriceImpactTooHigh.into());
+            }
+        }
 
         match swap_direction {
             SwapDirection::XtoY => {
                 Self::transfer_to_market(
                     spl_token_program_info,
                     user_token_x_info,
+                    minter_x_info,
                     pda_token_x_info,
                     user_owner_token_info,
-                    swap_result.take_amount,
+                    market_take_amount,
                 )?;
                 Self::transfer_to_user(
                     spl_token_program_info,
                     pda_token_y_info,
-                    user_token_y_info,
+                    minter_y_info,
+                    return_token_info,
                     pda_owner_token_y_info,
                     swap_result.return_amount,
                     &[&[
@@ -367,14 +874,16 @@ impl Processor {
                 Self::transfer_to_market(
                     spl_token_program_info,
                     user_token_y_info,
+                    minter_y_info,
                     pda_token_y_info,
                     user_owner_token_info,
-                    swap_result.take_amount,
+                    market_take_amount,
                 )?;
                 Self::transfer_to_user(
                     spl_token_program_info,
                     pda_token_x_info,
-                    user_token_x_info,
+                    minter_x_info,
+                    return_token_info,
                     pda_owner_token_x_info,
                     swap_result.return_amount,
                     &[&[
@@ -387,48 +896,741 @@ impl Processor {
                 )?;
             }
         }
+        if let Some(referrer_token_info) = referrer_token_info {
+            if referral_fee > 0 {
+                msg!("process_swap: Paying referral fee={} to the referrer", referral_fee);
+                let (source_token_info, source_minter_info) = match swap_direction {
+                    SwapDirection::XtoY => (user_token_x_info, minter_x_info),
+                    SwapDirection::YtoX => (user_token_y_info, minter_y_info),
+                };
+                Self::transfer_to_market(
+                    spl_token_program_info,
+                    source_token_info,
+                    source_minter_info,
+                    referrer_token_info,
+                    user_owner_token_info,
+                    referral_fee,
+                )?;
+            }
+        }
 
-        let (nex_token_x_amount, nex_token_y_amount) = match swap_direction {
-            SwapDirection::XtoY => (
-                vault.token_x_amount.checked_add(swap_result.take_amount)
-                    .ok_or(AmmError::Overflow)?,
-                vault.token_y_amount.checked_sub(swap_result.return_amount)
-                    .ok_or(AmmError::Underflow)?
-            ),
-            SwapDirection::YtoX => (
-                vault.token_y_amount.checked_add(swap_result.take_amount)
-                    .ok_or(AmmError::Overflow)?,
-                vault.token_x_amount.checked_sub(swap_result.return_amount)
-                    .ok_or(AmmError::Underflow)?
-            )
-        };
+        // nothing fails past the first change of the vault, it is committed at the end
+        let timestamp = Clock::get()?.unix_timestamp;
+        match swap_direction {
+            SwapDirection::XtoY => {
+                vault.cumulative_fee_x = vault.cumulative_fee_x.checked_add(market_fee_amount)
+                    .ok_or(AmmError::Overflow)?;
+            }
+            SwapDirection::YtoX => {
+                vault.cumulative_fee_y = vault.cumulative_fee_y.checked_add(market_fee_amount)
+                    .ok_or(AmmError::Overflow)?;
+            }
+        }
 
-        vault.token_x_amount = nex_token_x_amount;
-        vault.token_y_amount = nex_token_y_amount;
+        vault.update_price_cumulative(timestamp);
+        vault.set_reserves(nex_token_x_amount, nex_token_y_amount);
+        vault.record_price();
 
-        vault.serialize(&mut &mut pda_vault_info.data.borrow_mut()[..])?;
+        vault.commit()?;
+        #[cfg(feature = "binary-logs")]
+        SwapLog {
+            x_to_y: swap_direction == SwapDirection::XtoY,
+            take_amount: swap_result.take_amount,
+            return_amount: swap_result.return_amount,
+            fee_amount: swap_result.fee_amount,
+            reserve_x: vault.token_x_amount,
+            reserve_y: vault.token_y_amount,
+        }.emit()?;
+        #[cfg(not(feature = "binary-logs"))]
         msg!(
             "process_swap: Saved new amount_x={}, amount_y={} to vault account",
             vault.token_x_amount, vault.token_y_amount
         );
 
+        if options.unwrap {
+            msg!("process_swap: Unwrap returned token to user SPL token owner");
+            let close_return_token_ix = spl_token::instruction::close_account(
+                spl_token_program_info.key,
+                return_token_info.key,
+                user_owner_token_info.key,
+                user_owner_token_info.key,
+                &[],
+            )?;
+            invoke(
+                &close_return_token_ix,
+                &[
+                    spl_token_program_info.clone(),
+                    return_token_info.clone(),
+                    user_owner_token_info.clone(),
+                ],
+            )?;
+        }
+
+        // after every CPI, so the return data is of the market
+        let labeled_swap_result = swap_result.labeled(swap_direction, minter_x_info.key, minter_y_info.key);
+        set_return_data(&labeled_swap_result.try_to_vec()?);
+
+        Ok(())
+    }
+
+    fn process_propose_authority(
+        new_authority: Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("process_propose_authority: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_propose_authority: Verifying accounts");
+        Self::check_vault(pda_vault_info, minter_x_info, minter_y_info)?;
+        Self::check_vault_writable(pda_vault_info)?;
+        let mut vault = VaultAccount::load(pda_vault_info)?;
+        Self::assert_authority(&vault, authority_info)?;
+
+        vault.pending_authority = Some(new_authority);
+
+        vault.commit()?;
+        msg!("process_propose_authority: Saved pending authority={} to vault account", new_authority);
+
+        Ok(())
+    }
+
+    fn process_accept_authority(
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("process_accept_authority: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let new_authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_accept_authority: Verifying accounts");
+        Self::check_vault(pda_vault_info, minter_x_info, minter_y_info)?;
+        Self::check_vault_writable(pda_vault_info)?;
+        let mut vault = VaultAccount::load(pda_vault_info)?;
+
+        let pending_authority = vault.pending_authority
+            .ok_or(AmmError::NoPendingAuthority)?;
+        if !new_authority_info.is_signer {
+            msg!("Error: Required signature for pending authority");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if *new_authority_info.key != pending_authority {
+            return Err(AmmError::Unauthorized.into());
+        }
+
+        vault.authority = pending_authority;
+        vault.pending_authority = None;
+
+        vault.commit()?;
+        msg!("process_accept_authority: Saved authority={} to vault account", vault.authority);
+
+        Ok(())
+    }
+
+    fn process_set_fee(
+        fee_numerator: u64,
+        fee_denominator: u64,
+        dynamic_fee_enabled: bool,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("process_set_fee: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_set_fee: Verifying accounts");
+        Self::check_vault(pda_vault_info, minter_x_info, minter_y_info)?;
+        Self::check_vault_writable(pda_vault_info)?;
+        let mut vault = VaultAccount::load(pda_vault_info)?;
+        Self::assert_authority(&vault, authority_info)?;
+
+        if fee_denominator == 0 || fee_numerator >= fee_denominator {
+            return Err(AmmError::InvalidFee.into());
+        }
+
+        vault.fee_numerator = fee_numerator;
+        vault.fee_denominator = fee_denominator;
+        vault.dynamic_fee_enabled = dynamic_fee_enabled;
+
+        vault.commit()?;
+        msg!(
+            "process_set_fee: Saved fee numerator={}, denominator={}, dynamic={} to vault account",
+            fee_numerator, fee_denominator, dynamic_fee_enabled
+        );
+
+        Ok(())
+    }
+
+    fn process_set_fee_recipient(
+        recipient: Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("process_set_fee_recipient: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        let recipient_token_x_info = next_account_info(acc_iter)?;
+        let recipient_token_y_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_set_fee_recipient: Verifying accounts");
+        Self::check_vault(pda_vault_info, minter_x_info, minter_y_info)?;
+        Self::check_vault_writable(pda_vault_info)?;
+        let mut vault = VaultAccount::load(pda_vault_info)?;
+        Self::assert_authority(&vault, authority_info)?;
+
+        Self::check_fee_recipient_token(&recipient, minter_x_info.key, recipient_token_x_info)?;
+        Self::check_fee_recipient_token(&recipient, minter_y_info.key, recipient_token_y_info)?;
+
+        vault.fee_recipient = Some(recipient);
+
+        vault.commit()?;
+        msg!("process_set_fee_recipient: Saved fee recipient={} to vault account", recipient);
+
+        Ok(())
+    }
+
+    fn process_set_fee_exempt(
+        fee_exempt: Option<Pubkey>,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("process_set_fee_exempt: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_set_fee_exempt: Verifying accounts");
+        Self::check_vault(pda_vault_info, minter_x_info, minter_y_info)?;
+        Self::check_vault_writable(pda_vault_info)?;
+        let mut vault = VaultAccount::load(pda_vault_info)?;
+        Self::assert_authority(&vault, authority_info)?;
+
+        vault.fee_exempt = fee_exempt;
+
+        vault.commit()?;
+        msg!("process_set_fee_exempt: Saved fee exempt={:?} to vault account", fee_exempt);
+
+        Ok(())
+    }
+
+    fn process_set_paused(
+        paused: bool,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("process_set_paused: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_set_paused: Verifying accounts");
+        Self::check_vault(pda_vault_info, minter_x_info, minter_y_info)?;
+        Self::check_vault_writable(pda_vault_info)?;
+        let mut vault = VaultAccount::load(pda_vault_info)?;
+        Self::assert_authority(&vault, authority_info)?;
+
+        vault.paused = paused;
+
+        vault.commit()?;
+        msg!("process_set_paused: Saved paused={} to vault account", paused);
+
+        Ok(())
+    }
+
+    fn process_emergency_withdraw(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_emergency_withdraw: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        let pda_token_x_info = next_account_info(acc_iter)?;
+        let pda_token_y_info = next_account_info(acc_iter)?;
+        let pda_owner_token_x_info = next_account_info(acc_iter)?;
+        let pda_owner_token_y_info = next_account_info(acc_iter)?;
+        let authority_token_x_info = next_account_info(acc_iter)?;
+        let authority_token_y_info = next_account_info(acc_iter)?;
+        let spl_token_program_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_emergency_withdraw: Verifying accounts");
+        Self::check_vault(pda_vault_info, minter_x_info, minter_y_info)?;
+        Self::check_vault_writable(pda_vault_info)?;
+        let pda = Pda::generate(minter_x_info.key, minter_y_info.key);
+        if *pda_token_x_info.key != pda.pda_token_x_pk {
+            msg!("Error: Pda token X address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if *pda_token_y_info.key != pda.pda_token_y_pk {
+            msg!("Error: Pda token Y address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let (pda_owner_token_x_pk, pda_owner_token_x_bump) = pda.pda_owner_token_x;
+        let (pda_owner_token_y_pk, pda_owner_token_y_bump) = pda.pda_owner_token_y;
+        if *pda_owner_token_x_info.key != pda_owner_token_x_pk {
+            msg!("Error: Pda owner token X address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if *pda_owner_token_y_info.key != pda_owner_token_y_pk {
+            msg!("Error: Pda owner token Y address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let mut vault = VaultAccount::load(pda_vault_info)?;
+        Self::assert_authority(&vault, authority_info)?;
+        if !vault.paused {
+            msg!("Error: Pause the market before the emergency withdraw");
+            return Err(AmmError::MarketNotPaused.into());
+        }
+        let authority_token_x = spl_token::state::Account::unpack(&authority_token_x_info.data.borrow())?;
+        let authority_token_y = spl_token::state::Account::unpack(&authority_token_y_info.data.borrow())?;
+        if authority_token_x.owner != *authority_info.key || authority_token_y.owner != *authority_info.key {
+            msg!("Error: Destination SPL token holders must be owned by the authority");
+            return Err(AmmError::NotTokenOwner.into());
+        }
+
+        // the whole balances, the tokens sent past the market too
+        let balance_x = spl_token::state::Account::unpack(&pda_token_x_info.data.borrow())?.amount;
+        let balance_y = spl_token::state::Account::unpack(&pda_token_y_info.data.borrow())?.amount;
+        msg!(
+            "process_emergency_withdraw: EMERGENCY WITHDRAW of amount_x={}, amount_y={} to authority={}",
+            balance_x, balance_y, authority_info.key
+        );
+
+        if balance_x > 0 {
+            Self::transfer_to_user(
+                spl_token_program_info,
+                pda_token_x_info,
+                minter_x_info,
+                authority_token_x_info,
+                pda_owner_token_x_info,
+                balance_x,
+                &[&[
+                    SPL_TOKEN_X_OWNER_SEED,
+                    &minter_x_info.key.to_bytes(),
+                    &minter_y_info.key.to_bytes(),
+                    &spl_token::id().to_bytes(),
+                    &[pda_owner_token_x_bump]
+                ]],
+            )?;
+        }
+        if balance_y > 0 {
+            Self::transfer_to_user(
+                spl_token_program_info,
+                pda_token_y_info,
+                minter_y_info,
+                authority_token_y_info,
+                pda_owner_token_y_info,
+                balance_y,
+                &[&[
+                    SPL_TOKEN_Y_OWNER_SEED,
+                    &minter_x_info.key.to_bytes(),
+                    &minter_y_info.key.to_bytes(),
+                    &spl_token::id().to_bytes(),
+                    &[pda_owner_token_y_bump]
+                ]],
+            )?;
+        }
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        vault.update_price_cumulative(timestamp);
+        vault.set_reserves(0, 0);
+
+        vault.commit()?;
+        msg!("process_emergency_withdraw: Saved zero amount_x, amount_y to vault account");
+
+        Ok(())
+    }
+
+    fn process_rescue_tokens(
+        amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("process_rescue_tokens: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        let pda_owner_info = next_account_info(acc_iter)?;
+        let source_token_info = next_account_info(acc_iter)?;
+        let destination_token_info = next_account_info(acc_iter)?;
+        let spl_token_program_info = next_account_info(acc_iter)?;
+        let stuck_minter_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_rescue_tokens: Verifying accounts");
+        Self::check_vault(pda_vault_info, minter_x_info, minter_y_info)?;
+        let vault: Vault = Vault::load(&pda_vault_info.data.borrow())?;
+        Self::assert_authority(&vault, authority_info)?;
+
+        let source_token = spl_token::state::Account::unpack(&source_token_info.data.borrow())?;
+        if source_token.mint == *minter_x_info.key || source_token.mint == *minter_y_info.key {
+            return Err(AmmError::CannotRescuePoolToken.into());
+        }
+
+        let (pda_owner_x_pk, pda_owner_x_bump) = find_pk_and_bump(
+            SPL_TOKEN_X_OWNER_SEED, minter_x_info.key, minter_y_info.key,
+        );
+        let (pda_owner_y_pk, pda_owner_y_bump) = find_pk_and_bump(
+            SPL_TOKEN_Y_OWNER_SEED, minter_x_info.key, minter_y_info.key,
+        );
+        let (pda_owner_seed, pda_owner_bump) = if *pda_owner_info.key == pda_owner_x_pk {
+            (SPL_TOKEN_X_OWNER_SEED, pda_owner_x_bump)
+        } else if *pda_owner_info.key == pda_owner_y_pk {
+            (SPL_TOKEN_Y_OWNER_SEED, pda_owner_y_bump)
+        } else {
+            msg!("Error: Pda owner address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        };
+
+        if amount == 0 {
+            return Err(AmmError::AmountZero.into());
+        }
+
+        msg!("process_rescue_tokens: Rescue amount={} of minter={}", amount, source_token.mint);
+        Self::transfer_to_user(
+            spl_token_program_info,
+            source_token_info,
+            stuck_minter_info,
+            destination_token_info,
+            pda_owner_info,
+            amount,
+            &[&[
+                pda_owner_seed,
+                &minter_x_info.key.to_bytes(),
+                &minter_y_info.key.to_bytes(),
+                &spl_token::id().to_bytes(),
+                &[pda_owner_bump]
+            ]],
+        )
+    }
+
+    fn process_donate(
+        amount_x: u64,
+        amount_y: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        msg!("process_donate: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let authority_info = next_account_info(acc_iter)?;
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        let user_token_x_info = next_account_info(acc_iter)?;
+        let user_token_y_info = next_account_info(acc_iter)?;
+        let pda_token_x_info = next_account_info(acc_iter)?;
+        let pda_token_y_info = next_account_info(acc_iter)?;
+        let spl_token_program_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_donate: Verifying accounts");
+        let pda = Pda::generate(minter_x_info.key, minter_y_info.key);
+        if *pda_vault_info.key != pda.vault.0 {
+            msg!("Error: Pda vault address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        Self::check_vault_writable(pda_vault_info)?;
+        if *pda_token_x_info.key != pda.pda_token_x_pk {
+            msg!("Error: Pda token X address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if *pda_token_y_info.key != pda.pda_token_y_pk {
+            msg!("Error: Pda token Y address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        let mut vault = VaultAccount::load(pda_vault_info)?;
+        Self::assert_authority(&vault, authority_info)?;
+
+        if amount_x == 0 && amount_y == 0 {
+            return Err(AmmError::AmountZero.into());
+        }
+        let new_token_x_amount = vault.token_x_amount.checked_add(amount_x)
+            .ok_or(AmmError::Overflow)?;
+        let new_token_y_amount = vault.token_y_amount.checked_add(amount_y)
+            .ok_or(AmmError::Overflow)?;
+        if new_token_x_amount > MAX_RESERVE || new_token_y_amount > MAX_RESERVE {
+            return Err(AmmError::ReserveTooLarge.into());
+        }
+
+        if amount_x > 0 {
+            Self::transfer_to_market(
+                spl_token_program_info,
+                user_token_x_info,
+                minter_x_info,
+                pda_token_x_info,
+                authority_info,
+                amount_x,
+            )?;
+        }
+        if amount_y > 0 {
+            Self::transfer_to_market(
+                spl_token_program_info,
+                user_token_y_info,
+                minter_y_info,
+                pda_token_y_info,
+                authority_info,
+                amount_y,
+            )?;
+        }
+
+        let timestamp = Clock::get()?.unix_timestamp;
+        vault.update_price_cumulative(timestamp);
+        vault.set_reserves(new_token_x_amount, new_token_y_amount);
+
+        vault.commit()?;
+        msg!(
+            "process_donate: Saved new amount_x={}, amount_y={} to vault account",
+            vault.token_x_amount, vault.token_y_amount
+        );
+
+        Ok(())
+    }
+
+    fn check_vault(
+        pda_vault_info: &AccountInfo,
+        minter_x_info: &AccountInfo,
+        minter_y_info: &AccountInfo,
+    ) -> ProgramResult {
+        let (vault_pk, _) = find_pk_and_bump(VAULT_SEED, minter_x_info.key, minter_y_info.key);
+        if *pda_vault_info.key != vault_pk {
+            msg!("Error: Pda vault address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
         Ok(())
     }
 
+    fn process_inspect(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_inspect: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        let pda_token_x_info = next_account_info(acc_iter)?;
+        let pda_token_y_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        msg!("process_inspect: Verifying accounts");
+        let pda = Pda::generate(minter_x_info.key, minter_y_info.key);
+        if *pda_vault_info.key != pda.vault.0 {
+            msg!("Error: Pda vault address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if *pda_token_x_info.key != pda.pda_token_x_pk {
+            msg!("Error: Pda token X address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if *pda_token_y_info.key != pda.pda_token_y_pk {
+            msg!("Error: Pda token Y address does not match seed derivation");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if pda_vault_info.data_is_empty() {
+            msg!("Error: Vault account is empty, init the market first");
+            return Err(AmmError::MarketNotInitialized.into());
+        }
+        let vault: Vault = Vault::load(&pda_vault_info.data.borrow())?;
+        if vault.minter_x != *minter_x_info.key || vault.minter_y != *minter_y_info.key {
+            msg!("Error: Minters X, Y do not match the minters stored in vault");
+            return Err(AmmError::MintMismatch.into());
+        }
+
+        let pda_token_x = spl_token::state::Account::unpack(&pda_token_x_info.data.borrow())?;
+        let pda_token_y = spl_token::state::Account::unpack(&pda_token_y_info.data.borrow())?;
+        let report = ReservesReport::new(&vault, pda_token_x.amount, pda_token_y.amount);
+        msg!(
+            "process_inspect: Reserves amount_x={}, amount_y={}, balances amount_x={}, amount_y={}",
+            report.reserve_x, report.reserve_y, report.balance_x, report.balance_y
+        );
+        set_return_data(&report.try_to_vec()?);
+
+        Ok(())
+    }
+
+    fn process_validate(accounts: &[AccountInfo]) -> ProgramResult {
+        msg!("process_validate: Reading accounts");
+        let acc_iter = &mut accounts.iter();
+
+        let minter_x_info = next_account_info(acc_iter)?;
+        let minter_y_info = next_account_info(acc_iter)?;
+        let pda_vault_info = next_account_info(acc_iter)?;
+        let pda_token_x_info = next_account_info(acc_iter)?;
+        let pda_token_y_info = next_account_info(acc_iter)?;
+        Self::check_no_more_accounts(acc_iter)?;
+
+        // a failed check is reported, not an error
+        let vault = if *pda_vault_info.owner == id() {
+            Vault::load(&pda_vault_info.data.borrow()).ok()
+        } else {
+            None
+        };
+        let unpack_pda_token = |pda_token_info: &AccountInfo| {
+            if *pda_token_info.owner == spl_token::id() {
+                spl_token::state::Account::unpack(&pda_token_info.data.borrow()).ok()
+            } else {
+                None
+            }
+        };
+        let pda_token_x = unpack_pda_token(pda_token_x_info);
+        let pda_token_y = unpack_pda_token(pda_token_y_info);
+
+        let checks = MarketChecks::new(
+            [minter_x_info.key, minter_y_info.key],
+            [pda_vault_info.key, pda_token_x_info.key, pda_token_y_info.key],
+            vault.as_ref(),
+            [pda_token_x.as_ref(), pda_token_y.as_ref()],
+        );
+        msg!("process_validate: Passed checks={:#06b} of {:#06b}", checks.0, MarketChecks::ALL);
+        set_return_data(&checks.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// With `strict-accounts` no accounts may follow the accounts of the instruction,
+    /// otherwise the trailing accounts are ignored
+    fn check_no_more_accounts(acc_iter: &mut Iter<AccountInfo>) -> ProgramResult {
+        if cfg!(feature = "strict-accounts") {
+            if let Some(account_info) = acc_iter.next() {
+                msg!("Error: Unexpected account {} past the accounts of the instruction", account_info.key);
+                return Err(AmmError::UnexpectedAccounts.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Anyone can create the associated token account of the PDA owner ahead of `InitMarket`.
+    /// Such an account is reused if it's an empty SPL token holder of the minter and the PDA owner
+    fn is_unused_pda_token(pda_token_info: &AccountInfo, pda_owner_pk: &Pubkey, minter_pk: &Pubkey) -> bool {
+        if *pda_token_info.owner != spl_token::id() {
+            return false
+        }
+        match spl_token::state::Account::unpack(&pda_token_info.data.borrow()) {
+            Ok(pda_token) => pda_token.owner == *pda_owner_pk && pda_token.mint == *minter_pk && pda_token.amount == 0,
+            Err(_) => false,
+        }
+    }
+
+    /// Associated token account of the fee recipient for the minter exists, so the protocol fees can be sent to it
+    fn check_fee_recipient_token(
+        recipient: &Pubkey,
+        minter_pk: &Pubkey,
+        recipient_token_info: &AccountInfo,
+    ) -> ProgramResult {
+        if *recipient_token_info.key != spl_associated_token_account::get_associated_token_address(recipient, minter_pk) {
+            msg!("Error: Fee recipient token address is not the associated token account of the recipient");
+            return Err(ProgramError::InvalidSeeds);
+        }
+        if *recipient_token_info.owner != spl_token::id() {
+            msg!("Error: Fee recipient token account does not exist");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let recipient_token = spl_token::state::Account::unpack(&recipient_token_info.data.borrow())?;
+        if recipient_token.owner != *recipient {
+            msg!("Error: Fee recipient token account must be owned by the recipient");
+            return Err(AmmError::NotTokenOwner.into());
+        }
+        Ok(())
+    }
+
+    fn check_vault_writable(pda_vault_info: &AccountInfo) -> ProgramResult {
+        if !pda_vault_info.is_writable {
+            msg!("Error: Pda vault account must be writable");
+            return Err(AmmError::VaultNotWritable.into());
+        }
+        Ok(())
+    }
+
+    /// Earlier instructions of the transaction must not swap in the market of the vault
+    fn check_no_earlier_swap(instructions_sysvar_info: &AccountInfo, vault_pk: &Pubkey) -> ProgramResult {
+        let current_index = sysvar::instructions::load_current_index_checked(instructions_sysvar_info)?;
+        for index in 0..current_index {
+            let ix = sysvar::instructions::load_instruction_at_checked(index as usize, instructions_sysvar_info)?;
+            if ix.program_id != id() {
+                continue;
+            }
+            let is_swap = matches!(AmmInstruction::try_from_slice(&ix.data), Ok(AmmInstruction::Swap { .. }));
+            if is_swap && ix.accounts.iter().any(|account| account.pubkey == *vault_pk) {
+                msg!("Error: Instruction {} of the transaction swaps in the same market", index);
+                return Err(AmmError::DuplicateSwapInTx.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn assert_authority(vault: &Vault, authority_info: &AccountInfo) -> ProgramResult {
+        if !authority_info.is_signer {
+            msg!("Error: Required signature for market authority");
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if *authority_info.key != vault.authority {
+            return Err(AmmError::Unauthorized.into());
+        }
+        Ok(())
+    }
+
+    /// `spl_token::instruction::transfer_checked` with the decimals of the minter,
+    /// the SPL token program checks the minter of the SPL token holders too
+    fn transfer_checked_ix(
+        spl_token_program_info: &AccountInfo,
+        source_info: &AccountInfo,
+        minter_info: &AccountInfo,
+        destination_info: &AccountInfo,
+        authority_info: &AccountInfo,
+        amount: u64,
+    ) -> Result<Instruction, ProgramError> {
+        let decimals = spl_token::state::Mint::unpack(&minter_info.data.borrow())?.decimals;
+        spl_token::instruction::transfer_checked(
+            spl_token_program_info.key,
+            source_info.key,
+            minter_info.key,
+            destination_info.key,
+            authority_info.key,
+            &[&authority_info.key],
+            amount,
+            decimals,
+        )
+    }
+
     fn transfer_to_market<'a>(
         spl_token_program_info: &AccountInfo<'a>,
         source_info: &AccountInfo<'a>,
+        minter_info: &AccountInfo<'a>,
         destination_info: &AccountInfo<'a>,
         authority_info: &AccountInfo<'a>,
         amount: u64,
     ) -> ProgramResult {
         msg!("process_swap: Transfer amount={} to pda token associated account", amount);
-        let transfer_token_ix = spl_token::instruction::transfer(
-            spl_token_program_info.key,
-            source_info.key,
-            destination_info.key,
-            authority_info.key,
-            &[&authority_info.key],
+        let transfer_token_ix = Self::transfer_checked_ix(
+            spl_token_program_info,
+            source_info,
+            minter_info,
+            destination_info,
+            authority_info,
             amount,
         )?;
         invoke(
@@ -436,6 +1638,7 @@ impl Processor {
             &[
                 spl_token_program_info.clone(),
                 source_info.clone(),
+                minter_info.clone(),
                 destination_info.clone(),
                 authority_info.clone()
             ],
@@ -445,18 +1648,19 @@ impl Processor {
     fn transfer_to_user<'a>(
         spl_token_program_info: &AccountInfo<'a>,
         source_info: &AccountInfo<'a>,
+        minter_info: &AccountInfo<'a>,
         destination_info: &AccountInfo<'a>,
         authority_info: &AccountInfo<'a>,
         amount: u64,
         signers_seeds: &[&[&[u8]]],
     ) -> ProgramResult {
         msg!("process_init_market: Transfer amount={} to user token account", amount);
-        let transfer_token_ix = spl_token::instruction::transfer(
-            spl_token_program_info.key,
-            source_info.key,
-            destination_info.key,
-            authority_info.key,
-            &[&authority_info.key],
+        let transfer_token_ix = Self::transfer_checked_ix(
+            spl_token_program_info,
+            source_info,
+            minter_info,
+            destination_info,
+            authority_info,
             amount,
         )?;
         invoke_signed(
@@ -464,6 +1668,7 @@ impl Processor {
             &[
                 spl_token_program_info.clone(),
                 source_info.clone(),
+                minter_info.clone(),
                 destination_info.clone(),
                 authority_info.clone()
             ],
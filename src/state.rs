@@ -1,12 +1,361 @@
+use std::ops::{Deref, DerefMut};
 use borsh::BorshSerialize;
 use borsh::BorshDeserialize;
+use solana_program::account_info::AccountInfo;
+use solana_program::clock::{Slot, UnixTimestamp};
+use solana_program::entrypoint::ProgramResult;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use spl_token::state::Account;
+use crate::error::AmmError;
+use crate::pda::Pda;
+use crate::swap::{
+    accumulate_price, calc_swap_with_fee, dynamic_fee, integer_sqrt, normalized_price, to_u128,
+    SwapDirection,
+};
+
+/// Maximum amount of each token X, Y in the market.
+/// Leaves headroom, so the reserves grown by swaps still fit `u64`.
+pub const MAX_RESERVE: u64 = u64::MAX / 2;
+
+/// Maximum ratio max(X, Y) / min(X, Y) of the initial amounts of the market.
+/// Leaves room for tokens with different decimals.
+pub const MAX_INIT_RATIO: u64 = 1_000_000_000;
+
+/// Default denominator of the swap fee, the fee is set in basis points
+pub const FEE_DENOMINATOR: u64 = 10_000;
+
+/// Maximum part of the swap fee paid to the referrer, basis points of the swapped amount
+pub const MAX_REFERRAL_FEE_BPS: u16 = 100;
+
+/// Number of the spot prices after the last swaps kept by the vault
+pub const PRICE_SAMPLES: usize = 8;
 
 /// Vault of balances of X, Y tokens of the market.
 /// Unique for every different X, Y tokens.
 /// Needed because an attacker can add tokens in PDA of
 /// a Solana on-chain program for violate the ratio X * Y = K
-#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Vault {
     pub token_x_amount: u64,
-    pub token_y_amount: u64
-}
\ No newline at end of file
+    pub token_y_amount: u64,
+    /// Admin of the market
+    pub authority: Pubkey,
+    /// Proposed admin of the market, waiting for `AcceptAuthority`
+    pub pending_authority: Option<Pubkey>,
+    /// Swap fee = numerator / denominator of the added token
+    pub fee_numerator: u64,
+    pub fee_denominator: u64,
+    /// Fee grows with the imbalance of the reserves, see `swap::dynamic_fee`
+    pub dynamic_fee_enabled: bool,
+    /// Minters of the market tokens X, Y, stored at `InitMarket`
+    pub minter_x: Pubkey,
+    pub minter_y: Pubkey,
+    /// Lifetime swap fees of the token X(or Y) added by the users, stays in the reserves
+    pub cumulative_fee_x: u64,
+    pub cumulative_fee_y: u64,
+    /// Spot price of X in Y(Q64.64, adjusted by the decimals like `spot_price`) times the seconds it lasted,
+    /// see `swap::accumulate_price`
+    pub price_x_cumulative: u128,
+    /// Unix timestamp of the last update of `price_x_cumulative`
+    pub last_timestamp: UnixTimestamp,
+    /// Floor of each reserve X, Y checked at `InitMarket` and after every swap, 0 is disabled
+    pub min_reserve: u64,
+    /// First slot the market can be swapped in
+    pub launch_slot: Slot,
+    /// Spot prices(Q64.64) after the last swaps as a ring buffer, see `recent_prices`
+    pub price_samples: [u128; PRICE_SAMPLES],
+    /// Index in `price_samples` of the next sample
+    pub price_sample_index: u8,
+    /// Treasury owner of the protocol fees, set by `SetFeeRecipient`.
+    /// Stored for the collection of the protocol fees, the market takes none yet
+    pub fee_recipient: Option<Pubkey>,
+    /// Swaps are rejected, set by `SetPaused`
+    pub paused: bool,
+    /// Swapper paying no swap fee, e.g. a router of the protocol, set by `SetFeeExempt`
+    pub fee_exempt: Option<Pubkey>,
+    /// Decimals of the minters X, Y, stored at `InitMarket`.
+    /// Every price of the market is adjusted by them, see `swap::normalized_price`
+    pub decimals_x: u8,
+    pub decimals_y: u8,
+}
+
+/// Reserves recorded in the vault and the balances of the PDA SPL token X, Y holders.
+/// Tokens sent to the holders past the market make the balances larger than the reserves
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReservesReport {
+    pub reserve_x: u64,
+    pub reserve_y: u64,
+    pub balance_x: u64,
+    pub balance_y: u64,
+    /// Balance - reserve of the token X(or Y)
+    pub difference_x: i128,
+    pub difference_y: i128,
+}
+
+/// Passed checks of the market by `Validate`, a set bit is a passed check.
+/// There is no LP supply check, the market has no LP token mint to check against the reserves
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct MarketChecks(pub u8);
+
+impl MarketChecks {
+    /// Vault, PDA SPL token X, Y holders are at the addresses derived from the minters
+    pub const PDA_KEYS: u8 = 1;
+    /// PDA SPL token X, Y holders are owned by the PDA owners
+    pub const TOKEN_OWNERS: u8 = 1 << 1;
+    /// PDA SPL token X, Y holders and the vault are of the minters X, Y
+    pub const TOKEN_MINTS: u8 = 1 << 2;
+    /// Reserves recorded in the vault equal the balances of the PDA SPL token X, Y holders
+    pub const RESERVES: u8 = 1 << 3;
+    pub const ALL: u8 = Self::PDA_KEYS | Self::TOKEN_OWNERS | Self::TOKEN_MINTS | Self::RESERVES;
+
+    /// Keys are the vault, PDA SPL token X, Y holders. An account that isn't a vault
+    /// or an SPL token holder is `None` and fails the checks of its contents
+    pub fn new(
+        minters: [&Pubkey; 2],
+        keys: [&Pubkey; 3],
+        vault: Option<&Vault>,
+        pda_tokens: [Option<&Account>; 2],
+    ) -> MarketChecks {
+        let [minter_x_pk, minter_y_pk] = minters;
+        let [vault_pk, pda_token_x_pk, pda_token_y_pk] = keys;
+        let pda = Pda::generate(minter_x_pk, minter_y_pk);
+
+        let mut checks = 0;
+        if *vault_pk == pda.vault.0 && *pda_token_x_pk == pda.pda_token_x_pk && *pda_token_y_pk == pda.pda_token_y_pk {
+            checks |= Self::PDA_KEYS;
+        }
+        if let (Some(vault), [Some(pda_token_x), Some(pda_token_y)]) = (vault, pda_tokens) {
+            if pda_token_x.owner == pda.pda_owner_token_x.0 && pda_token_y.owner == pda.pda_owner_token_y.0 {
+                checks |= Self::TOKEN_OWNERS;
+            }
+            if pda_token_x.mint == *minter_x_pk && pda_token_y.mint == *minter_y_pk
+                && vault.minter_x == *minter_x_pk && vault.minter_y == *minter_y_pk {
+                checks |= Self::TOKEN_MINTS;
+            }
+            if vault.token_x_amount == pda_token_x.amount && vault.token_y_amount == pda_token_y.amount {
+                checks |= Self::RESERVES;
+            }
+        }
+        MarketChecks(checks)
+    }
+
+    pub fn passed(&self, check: u8) -> bool {
+        self.0 & check == check
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.passed(Self::ALL)
+    }
+}
+
+impl ReservesReport {
+    pub const LEN: usize = 8 + 8 + 8 + 8 + 16 + 16;
+
+    pub fn new(vault: &Vault, balance_x: u64, balance_y: u64) -> ReservesReport {
+        ReservesReport {
+            reserve_x: vault.token_x_amount,
+            reserve_y: vault.token_y_amount,
+            balance_x,
+            balance_y,
+            difference_x: balance_x as i128 - vault.token_x_amount as i128,
+            difference_y: balance_y as i128 - vault.token_y_amount as i128,
+        }
+    }
+
+    /// Balances match the reserves
+    pub fn is_synced(&self) -> bool {
+        self.difference_x == 0 && self.difference_y == 0
+    }
+}
+
+impl Vault {
+    /// Serialized size of the vault with a pending authority, a fee recipient and a fee exempt swapper,
+    /// the largest vault.
+    /// Size of the vault account.
+    pub const LEN: usize = 8 + 8 + 32 + (1 + 32) + 8 + 8 + 1 + 32 + 32 + 8 + 8 + 16 + 8 + 8 + 8
+        + 16 * PRICE_SAMPLES + 1 + (1 + 32) + 1 + (1 + 32) + 1 + 1;
+
+    /// Deserialize vault from the account data.
+    /// Without a pending authority, a fee recipient or a fee exempt swapper the tail of the data is unused.
+    pub fn load(data: &[u8]) -> Result<Vault, ProgramError> {
+        if data.len() != Vault::LEN {
+            return Err(AmmError::InvalidVault.into());
+        }
+        Vault::deserialize(&mut &data[..])
+            .map_err(|_| AmmError::InvalidVault.into())
+    }
+
+    pub fn save(&self, data: &mut [u8]) -> ProgramResult {
+        if data.len() != Vault::LEN {
+            return Err(AmmError::InvalidVault.into());
+        }
+        self.serialize(&mut &mut data[..])?;
+        Ok(())
+    }
+
+    /// Minters are stored by `InitMarket`, a never initialized vault has none
+    pub fn is_initialized(&self) -> bool {
+        self.minter_x != Pubkey::default()
+    }
+
+    /// Price of token X in token Y, Y / X as Q64.64 fixed-point adjusted by the stored decimals,
+    /// the price of the whole tokens. None for the empty reserve X
+    pub fn spot_price(&self) -> Option<u128> {
+        self.spot_price_with_decimals(self.decimals_x, self.decimals_y)
+    }
+
+    /// Depth of the market, sqrt(X * Y), e.g. to value the liquidity for the impermanent loss.
+    /// None for an empty reserve
+    pub fn geometric_mean_value(&self) -> Option<u128> {
+        if self.token_x_amount == 0 || self.token_y_amount == 0 {
+            return None
+        }
+        // the product of u64 always fits u128
+        Some(integer_sqrt(to_u128(self.token_x_amount) * to_u128(self.token_y_amount)))
+    }
+
+    /// Accumulate the spot price lasted from the last update till the timestamp.
+    /// Must be called before the reserves change
+    pub fn update_price_cumulative(&mut self, timestamp: UnixTimestamp) {
+        if timestamp <= self.last_timestamp {
+            return
+        }
+        if let Some(price) = self.spot_price() {
+            let elapsed = (timestamp - self.last_timestamp) as u64;
+            self.price_x_cumulative = accumulate_price(self.price_x_cumulative, price, elapsed);
+        }
+        self.last_timestamp = timestamp;
+    }
+
+    /// Sample the spot price into `price_samples`, overwriting the oldest sample.
+    /// Must be called after the reserves change
+    pub fn record_price(&mut self) {
+        if let Some(price) = self.spot_price() {
+            let index = self.price_sample_index as usize % PRICE_SAMPLES;
+            self.price_samples[index] = price;
+            self.price_sample_index = ((index + 1) % PRICE_SAMPLES) as u8;
+        }
+    }
+
+    /// Spot prices(Q64.64) after the last swaps from the oldest to the latest.
+    /// 0 for the samples not taken yet
+    pub fn recent_prices(&self) -> [u128; PRICE_SAMPLES] {
+        let mut prices = self.price_samples;
+        prices.rotate_left(self.price_sample_index as usize % PRICE_SAMPLES);
+        prices
+    }
+
+    /// Associated token account of the fee recipient for the minter, the canonical destination of the protocol fees.
+    /// None without a fee recipient
+    pub fn fee_recipient_token(&self, minter_pk: &Pubkey) -> Option<Pubkey> {
+        self.fee_recipient
+            .map(|fee_recipient| spl_associated_token_account::get_associated_token_address(&fee_recipient, minter_pk))
+    }
+
+    /// Reserves (source, destination) of the swap in the direction
+    pub fn swap_reserves(&self, direction: SwapDirection) -> (u64, u64) {
+        match direction {
+            SwapDirection::XtoY => (self.token_x_amount, self.token_y_amount),
+            SwapDirection::YtoX => (self.token_y_amount, self.token_x_amount),
+        }
+    }
+
+    /// Fee (numerator, denominator) of the swap of the added amount in the direction,
+    /// the dynamic fee if enabled
+    pub fn swap_fee(&self, amount_in: u64, direction: SwapDirection) -> Option<(u64, u64)> {
+        if !self.dynamic_fee_enabled {
+            return Some((self.fee_numerator, self.fee_denominator))
+        }
+        let (source_amount, destination_amount) = self.swap_reserves(direction);
+        dynamic_fee((self.fee_numerator, self.fee_denominator), source_amount, destination_amount, amount_in)
+    }
+
+    /// Same as `swap_fee`, but the fee exempt swapper pays no fee
+    pub fn swap_fee_for(&self, swapper: &Pubkey, amount_in: u64, direction: SwapDirection) -> Option<(u64, u64)> {
+        if self.fee_exempt == Some(*swapper) {
+            return Some((0, self.fee_denominator))
+        }
+        self.swap_fee(amount_in, direction)
+    }
+
+    /// Amount returned by `Swap` without options for the added amount, net of the current fee.
+    /// None if the swap fails
+    pub fn quote_out(&self, amount_in: u64, direction: SwapDirection) -> Option<u64> {
+        let (source_amount, destination_amount) = self.swap_reserves(direction);
+        let (fee_numerator, fee_denominator) = self.swap_fee(amount_in, direction)?;
+        calc_swap_with_fee(amount_in, source_amount, destination_amount, fee_numerator, fee_denominator)
+            .map(|swap_result| swap_result.return_amount)
+    }
+
+    /// Same as `spot_price`, but adjusted by the given decimals, see `swap::normalized_price`.
+    /// 0, 0 is the raw ratio of the reserves
+    pub fn spot_price_with_decimals(&self, decimals_x: u8, decimals_y: u8) -> Option<u128> {
+        normalized_price(self.token_x_amount, self.token_y_amount, decimals_x, decimals_y)
+    }
+}
+
+/// Vault loaded from its account and saved back once by `commit`.
+/// Changes through `DerefMut` or `set_reserves` mark the vault to save,
+/// a changed vault must be committed before the drop
+pub struct VaultAccount<'a, 'b> {
+    account_info: &'a AccountInfo<'b>,
+    vault: Vault,
+    changed: bool,
+}
+
+impl<'a, 'b> VaultAccount<'a, 'b> {
+    pub fn load(account_info: &'a AccountInfo<'b>) -> Result<VaultAccount<'a, 'b>, ProgramError> {
+        let vault = Vault::load(&account_info.try_borrow_data()?)?;
+        Ok(VaultAccount { account_info, vault, changed: false })
+    }
+
+    /// Reserves X, Y of the market
+    pub fn reserves(&self) -> (u64, u64) {
+        (self.vault.token_x_amount, self.vault.token_y_amount)
+    }
+
+    pub fn set_reserves(&mut self, token_x_amount: u64, token_y_amount: u64) {
+        self.vault.token_x_amount = token_x_amount;
+        self.vault.token_y_amount = token_y_amount;
+        self.changed = true;
+    }
+
+    /// Vault changed since the load or the last commit
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Save the changed vault to the account, nothing to do for an unchanged one.
+    /// The error of the save is the caller's to handle, the vault isn't saved again
+    pub fn commit(&mut self) -> ProgramResult {
+        if self.changed {
+            self.changed = false;
+            self.vault.save(&mut self.account_info.try_borrow_mut_data()?)?;
+        }
+        Ok(())
+    }
+}
+
+impl Deref for VaultAccount<'_, '_> {
+    type Target = Vault;
+
+    fn deref(&self) -> &Vault {
+        &self.vault
+    }
+}
+
+impl DerefMut for VaultAccount<'_, '_> {
+    fn deref_mut(&mut self) -> &mut Vault {
+        self.changed = true;
+        &mut self.vault
+    }
+}
+
+impl Drop for VaultAccount<'_, '_> {
+    /// Nothing is saved on drop, the changes left without `commit` are a bug
+    fn drop(&mut self) {
+        debug_assert!(!self.changed || std::thread::panicking(), "Vault is changed but not committed");
+    }
+}
@@ -1,13 +1,9 @@
-use num_traits::ToPrimitive;
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::pubkey::Pubkey;
-use spl_math::checked_ceil_div::CheckedCeilDiv;
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub struct SwapResult {
-    pub take_amount: u64,
-    pub return_amount: u64,
-}
+pub use crate::math::*;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum SwapDirection {
     XtoY,
     YtoX,
@@ -24,37 +20,96 @@ impl SwapDirection {
             None
         }
     }
-}
 
-pub fn calc_swap(
-    add_source_amount: u64,
-    source_amount: u64,
-    destination_amount: u64,
-) -> Option<SwapResult> {
-    let add_source_amount = add_source_amount.to_u128()?;
-    let source_amount = source_amount.to_u128()?;
-    let destination_amount = destination_amount.to_u128()?;
+    /// Swap back in the opposite direction
+    pub fn flip(self) -> SwapDirection {
+        match self {
+            SwapDirection::XtoY => SwapDirection::YtoX,
+            SwapDirection::YtoX => SwapDirection::XtoY,
+        }
+    }
+
+    /// Minter of the token returned to the user
+    pub fn output_mint<'a>(&self, x_pk: &'a Pubkey, y_pk: &'a Pubkey) -> &'a Pubkey {
+        match self {
+            SwapDirection::XtoY => y_pk,
+            SwapDirection::YtoX => x_pk,
+        }
+    }
+}
 
-    // K = X * Y
-    let invariant = source_amount.checked_mul(destination_amount)?;
+/// Swap result with the minters of the taken and returned amounts.
+/// Swap sets it as the return data of the instruction
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub struct LabeledSwapResult {
+    pub take_mint: Pubkey,
+    pub take_amount: u64,
+    pub return_mint: Pubkey,
+    pub return_amount: u64,
+}
 
-    // (X + dX)
-    let new_source_amount = source_amount.checked_add(add_source_amount)?;
+impl LabeledSwapResult {
+    pub const LEN: usize = 32 + 8 + 32 + 8;
+}
 
-    // ((Y - dY), M(updated) = K / M
-    let (new_destination_amount, new_source_amount) = invariant.checked_ceil_div(new_source_amount)?;
+impl SwapResult {
+    /// Pair the amounts with the minters X, Y of the market swapped in the direction
+    pub fn labeled(&self, direction: SwapDirection, x_pk: &Pubkey, y_pk: &Pubkey) -> LabeledSwapResult {
+        LabeledSwapResult {
+            take_mint: *direction.flip().output_mint(x_pk, y_pk),
+            take_amount: self.take_amount,
+            return_mint: *direction.output_mint(x_pk, y_pk),
+            return_amount: self.return_amount,
+        }
+    }
+}
 
-    //  dX = (X + dX) - X
-    let take_amount_x = new_source_amount.checked_sub(source_amount)?.to_u64()?;
-    if take_amount_x == 0 {
+/// Direction and the added amount of the swap moving the spot price of X in Y(Q64.64)
+/// to the target price, ignoring the other swaps. The target is adjusted by the decimals
+/// like `normalized_price` and `Vault::spot_price`. Fee is (numerator, denominator),
+/// it stays in the market, so the price lands next to the target.
+/// None if the price is already at the target, for an empty reserve or on overflow
+pub fn amount_to_reach_price(
+    reserve_x: u64,
+    reserve_y: u64,
+    decimals_x: u8,
+    decimals_y: u8,
+    target_price: u128,
+    fee: (u64, u64),
+) -> Option<(SwapDirection, u64)> {
+    let (fee_numerator, fee_denominator) = fee;
+    if target_price == 0 || fee_numerator >= fee_denominator {
         return None
     }
+    let price = normalized_price(reserve_x, reserve_y, decimals_x, decimals_y)?;
+    // the reserves move by the raw ratio Y / X = target * 10^dec_y / 10^dec_x
+    let raw_target_price = if decimals_y >= decimals_x {
+        target_price.checked_mul(10u128.checked_pow((decimals_y - decimals_x) as u32)?)?
+    } else {
+        target_price / 10u128.checked_pow((decimals_x - decimals_y) as u32)?
+    };
 
-    //  dY = Y - (Y - dY)
-    let return_amount_y = destination_amount.checked_sub(new_destination_amount)?.to_u64()?;
-    if return_amount_y == 0 {
+    // X' * Y' = K and Y' / X' = target, so X' = sqrt(K) / sqrt(target), Y' = sqrt(K) * sqrt(target)
+    let sqrt_invariant = integer_sqrt((reserve_x as u128).checked_mul(reserve_y as u128)?);
+    // Q32.32
+    let sqrt_target_price = integer_sqrt(raw_target_price);
+    let (direction, source_amount, new_source_amount) = if target_price < price {
+        (SwapDirection::XtoY, reserve_x as u128, (sqrt_invariant << 32).checked_div(sqrt_target_price)?)
+    } else if target_price > price {
+        (SwapDirection::YtoX, reserve_y as u128, sqrt_invariant.checked_mul(sqrt_target_price)? >> 32)
+    } else {
+        return None
+    };
+    let amount = new_source_amount.checked_sub(source_amount)?;
+    if amount == 0 {
         return None
     }
 
-    Some(SwapResult { take_amount: take_amount_x, return_amount: return_amount_y })
+    // the fee is taken from the added amount, added * (denominator - numerator) / denominator >= amount
+    let fee_denominator = fee_denominator as u128;
+    let net_denominator = fee_denominator - fee_numerator as u128;
+    let add_source_amount = amount.checked_mul(fee_denominator)?
+        .checked_add(net_denominator - 1)?
+        .checked_div(net_denominator)?;
+    Some((direction, to_u64(add_source_amount).ok()?))
 }
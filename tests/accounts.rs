@@ -0,0 +1,236 @@
+#![cfg(feature = "program")]
+
+//! Account order of the instruction builders is the order the processor reads the accounts in.
+//! The accounts of a built instruction are read by the processor and every position is checked by the role.
+
+use solana_program::account_info::AccountInfo;
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::pubkey::Pubkey;
+use solana_program::{system_program, sysvar};
+use amm::instruction::{AmmInstruction, SwapOptions};
+use amm::pda::Pda;
+use amm::processor::{InitMarketAccounts, SwapAccounts};
+
+/// Role of an account: name, address, signer, writable
+type Role = (&'static str, Pubkey, bool, bool);
+
+/// Empty accounts of the instruction with the flags of the account metas
+struct Accounts {
+    keys: Vec<Pubkey>,
+    lamports: Vec<u64>,
+    data: Vec<Vec<u8>>,
+    owner: Pubkey,
+}
+
+impl Accounts {
+    fn new(ix: &Instruction) -> Accounts {
+        Accounts {
+            keys: ix.accounts.iter().map(|account| account.pubkey).collect(),
+            lamports: vec![0; ix.accounts.len()],
+            data: vec![vec![]; ix.accounts.len()],
+            owner: Pubkey::default(),
+        }
+    }
+
+    fn infos<'a>(&'a mut self, ix: &Instruction) -> Vec<AccountInfo<'a>> {
+        let owner = &self.owner;
+        ix.accounts.iter()
+            .zip(self.keys.iter())
+            .zip(self.lamports.iter_mut().zip(self.data.iter_mut()))
+            .map(|((account, key), (lamports, data))| {
+                AccountInfo::new(key, account.is_signer, account.is_writable, lamports, data, owner, false, 0)
+            })
+            .collect()
+    }
+}
+
+fn assert_role(role: Role, account_info: &AccountInfo) {
+    let (name, pk, is_signer, is_writable) = role;
+    assert_eq!(*account_info.key, pk, "{} is read at another position", name);
+    assert_eq!(account_info.is_signer, is_signer, "{} signer", name);
+    assert_eq!(account_info.is_writable, is_writable, "{} writable", name);
+}
+
+#[test]
+fn init_market_account_order() {
+    let user_owner_token_x_pk = Pubkey::new_unique();
+    let user_owner_token_y_pk = Pubkey::new_unique();
+    let user_payer_pk = Pubkey::new_unique();
+    let user_token_x_pk = Pubkey::new_unique();
+    let user_token_y_pk = Pubkey::new_unique();
+    let minter_x_pk = Pubkey::new_unique();
+    let minter_y_pk = Pubkey::new_unique();
+    let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+
+    let ix = AmmInstruction::init_market(
+        100,
+        300,
+        user_owner_token_x_pk,
+        user_owner_token_y_pk,
+        user_payer_pk,
+        user_token_x_pk,
+        user_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
+    );
+    let mut accounts = Accounts::new(&ix);
+    let account_infos = accounts.infos(&ix);
+    let read = InitMarketAccounts::read(&account_infos).expect("read");
+
+    let roles: [(Role, &AccountInfo); 16] = [
+        (("user owner token X", user_owner_token_x_pk, true, false), read.user_owner_token_x_info),
+        (("user owner token Y", user_owner_token_y_pk, true, false), read.user_owner_token_y_info),
+        (("user payer", user_payer_pk, true, true), read.user_payer_info),
+        (("user token X", user_token_x_pk, false, true), read.user_token_x_info),
+        (("user token Y", user_token_y_pk, false, true), read.user_token_y_info),
+        (("minter X", minter_x_pk, false, false), read.minter_x_info),
+        (("minter Y", minter_y_pk, false, false), read.minter_y_info),
+        (("PDA token X", pda.pda_token_x_pk, false, true), read.pda_token_x_info),
+        (("PDA token Y", pda.pda_token_y_pk, false, true), read.pda_token_y_info),
+        (("PDA owner token X", pda.pda_owner_token_x.0, false, false), read.pda_owner_token_x_info),
+        (("PDA owner token Y", pda.pda_owner_token_y.0, false, false), read.pda_owner_token_y_info),
+        (("vault", pda.vault.0, false, true), read.pda_vault_info),
+        (("rent sysvar", sysvar::rent::id(), false, false), read.rent_info),
+        (("system program", system_program::id(), false, false), read.system_info),
+        (("SPL token program", spl_token::id(), false, false), read.spl_token_program_info),
+        (
+            ("SPL associated token account program", spl_associated_token_account::id(), false, false),
+            read.spl_associated_token_program_info,
+        ),
+    ];
+    assert_eq!(ix.accounts.len(), roles.len());
+    for (role, account_info) in roles {
+        assert_role(role, account_info);
+    }
+
+    // a missing account is not read as another one
+    assert!(InitMarketAccounts::read(&account_infos[..roles.len() - 1]).is_err());
+}
+
+#[test]
+fn swap_account_order() {
+    let user_pk = Pubkey::new_unique();
+    let user_token_x_pk = Pubkey::new_unique();
+    let user_token_y_pk = Pubkey::new_unique();
+    let minter_x_pk = Pubkey::new_unique();
+    let minter_y_pk = Pubkey::new_unique();
+    let referrer_pk = Pubkey::new_unique();
+    let payer_pk = Pubkey::new_unique();
+    let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+    let referrer_token_x_pk = spl_associated_token_account::get_associated_token_address(&referrer_pk, &minter_x_pk);
+
+    let options = SwapOptions {
+        reject_earlier_swap_in_tx: true,
+        create_return_token: true,
+        referrer: Some(referrer_pk),
+        referral_fee_bps: 10,
+        return_token_payer: Some(payer_pk),
+        ..SwapOptions::default()
+    };
+    let ix = AmmInstruction::swap_with_options(
+        50,
+        minter_x_pk,
+        user_pk,
+        user_token_x_pk,
+        user_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
+        options.clone(),
+    );
+    let mut accounts = Accounts::new(&ix);
+    let account_infos = accounts.infos(&ix);
+    let read = SwapAccounts::read(&account_infos, &options).expect("read");
+
+    let roles: [(Role, &AccountInfo); 11] = [
+        (("user owner token", user_pk, true, true), read.user_owner_token_info),
+        (("user token X", user_token_x_pk, false, true), read.user_token_x_info),
+        (("user token Y", user_token_y_pk, false, true), read.user_token_y_info),
+        (("minter X", minter_x_pk, false, false), read.minter_x_info),
+        (("minter Y", minter_y_pk, false, false), read.minter_y_info),
+        (("PDA token X", pda.pda_token_x_pk, false, true), read.pda_token_x_info),
+        (("PDA token Y", pda.pda_token_y_pk, false, true), read.pda_token_y_info),
+        (("PDA owner token X", pda.pda_owner_token_x.0, false, false), read.pda_owner_token_x_info),
+        (("PDA owner token Y", pda.pda_owner_token_y.0, false, false), read.pda_owner_token_y_info),
+        (("vault", pda.vault.0, false, true), read.pda_vault_info),
+        (("SPL token program", spl_token::id(), false, false), read.spl_token_program_info),
+    ];
+    for (role, account_info) in roles {
+        assert_role(role, account_info);
+    }
+
+    // the optional accounts follow in the order of the options
+    let optional_roles: [(Role, Option<&AccountInfo>); 6] = [
+        (("instructions sysvar", sysvar::instructions::id(), false, false), read.instructions_sysvar_info),
+        (("rent sysvar", sysvar::rent::id(), false, false), read.rent_info),
+        (("system program", system_program::id(), false, false), read.system_info),
+        (
+            ("SPL associated token account program", spl_associated_token_account::id(), false, false),
+            read.spl_associated_token_program_info,
+        ),
+        (("return token payer", payer_pk, true, true), read.return_token_payer_info),
+        (("referrer token X", referrer_token_x_pk, false, true), read.referrer_token_info),
+    ];
+    assert_eq!(ix.accounts.len(), roles.len() + optional_roles.len());
+    for (role, account_info) in optional_roles {
+        assert_role(role, account_info.unwrap_or_else(|| panic!("{} is not read", role.0)));
+    }
+    assert!(read.destination_token_info.is_none());
+
+    // a missing account is not read as another one
+    assert!(SwapAccounts::read(&account_infos[..roles.len() - 1], &options).is_err());
+}
+
+#[test]
+fn swap_to_account_order() {
+    let user_pk = Pubkey::new_unique();
+    let user_token_x_pk = Pubkey::new_unique();
+    let user_token_y_pk = Pubkey::new_unique();
+    let minter_x_pk = Pubkey::new_unique();
+    let minter_y_pk = Pubkey::new_unique();
+    let destination_token_x_pk = Pubkey::new_unique();
+
+    let ix = AmmInstruction::swap_to(
+        50,
+        minter_y_pk,
+        user_pk,
+        user_token_x_pk,
+        user_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
+        destination_token_x_pk,
+    );
+    let mut accounts = Accounts::new(&ix);
+    let account_infos = accounts.infos(&ix);
+    let options = SwapOptions { return_to_destination: true, ..SwapOptions::default() };
+    let read = SwapAccounts::read(&account_infos, &options).expect("read");
+
+    assert_role(("user owner token", user_pk, true, true), read.user_owner_token_info);
+    assert_role(
+        ("destination token X", destination_token_x_pk, false, true),
+        read.destination_token_info.expect("destination_token_info"),
+    );
+    assert!(read.referrer_token_info.is_none());
+    assert!(read.instructions_sysvar_info.is_none());
+}
+
+#[test]
+fn swap_extra_account() {
+    let user_pk = Pubkey::new_unique();
+    let user_token_x_pk = Pubkey::new_unique();
+    let user_token_y_pk = Pubkey::new_unique();
+    let minter_x_pk = Pubkey::new_unique();
+    let minter_y_pk = Pubkey::new_unique();
+
+    let mut ix = AmmInstruction::swap(50, minter_x_pk, user_pk, user_token_x_pk, user_token_y_pk, minter_x_pk, minter_y_pk);
+    ix.accounts.push(AccountMeta::new(Pubkey::new_unique(), false));
+    let mut accounts = Accounts::new(&ix);
+    let account_infos = accounts.infos(&ix);
+
+    // an account past the accounts of the options is never read as the destination
+    let read = SwapAccounts::read(&account_infos, &SwapOptions::default());
+    if cfg!(feature = "strict-accounts") {
+        assert!(read.is_err());
+    } else {
+        assert!(read.expect("read").destination_token_info.is_none());
+    }
+}
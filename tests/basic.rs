@@ -1,5 +1,6 @@
 #![cfg(feature = "test-bpf")]
 
+use std::fmt::Debug;
 use num_traits::FromPrimitive;
 use solana_program::decode_error::DecodeError;
 use solana_program::instruction::InstructionError;
@@ -13,6 +14,7 @@ use solana_sdk::signer::Signer;
 use solana_sdk::transaction::{Transaction, TransactionError};
 use spl_token::state::{Account, AccountState, Mint};
 use amm::pda::Pda;
+use amm::state::Vault;
 use amm::id;
 use amm::entrypoint::process_instruction;
 
@@ -176,7 +178,7 @@ impl Env {
     }
 
 
-    async fn initialize_minter(
+    pub async fn initialize_minter(
         ctx: &mut ProgramTestContext,
         payer: &Keypair,
         minter: &Keypair,
@@ -226,7 +228,7 @@ impl Env {
         assert_eq!(minter_after_init.freeze_authority, COption::Some(freeze_authority.pubkey()));
     }
 
-    async fn mint_token(
+    pub async fn mint_token(
         ctx: &mut ProgramTestContext,
         payer: &Keypair,
         minter: &Keypair,
@@ -281,6 +283,125 @@ pub async fn check_pda(ctx: &mut ProgramTestContext, pda: &Pda) {
     assert_eq!(pda_vault_acc, None);
 }
 
+pub async fn get_vault(ctx: &mut ProgramTestContext, vault_pk: Pubkey) -> Vault {
+    let vault_acc = ctx.banks_client.get_account(vault_pk)
+        .await
+        .expect("vault_acc")
+        .expect("vault_acc");
+    Vault::load(&vault_acc.data).expect("vault")
+}
+
+/// Market and user SPL token holders at a moment of a multi-step test, compared by `diff`
+#[derive(Debug, PartialEq)]
+pub struct PoolSnapshot {
+    pub pda_token_x: Account,
+    pub pda_token_y: Account,
+    pub vault: Vault,
+    /// Balances of the user SPL token X, Y holders, if captured
+    pub user_token_x_amount: Option<u64>,
+    pub user_token_y_amount: Option<u64>,
+}
+
+/// Top level fields of `{:#?}` of a struct as `(name, value)`, a nested value is joined into one line
+fn debug_fields<T: Debug>(value: &T) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in format!("{:#?}", value).lines() {
+        match line.strip_prefix("    ") {
+            // the nested value of the last field and its closing bracket
+            Some(nested) if nested.starts_with(|c| matches!(c, ' ' | ']' | ')' | '}')) => {
+                let (_, field_value) = fields.last_mut().expect("nested value of a field");
+                field_value.push(' ');
+                field_value.push_str(nested.trim());
+            }
+            Some(field) => {
+                let (name, field_value) = field.split_once(": ").expect("field of a struct");
+                fields.push((name.to_string(), field_value.to_string()));
+            }
+            // the struct name and the closing brace
+            None => {}
+        }
+    }
+    for (_, field_value) in &mut fields {
+        *field_value = field_value.trim_end_matches(',').to_string();
+    }
+    fields
+}
+
+/// Changes of every field of the struct as `prefix.field: before -> after`
+fn push_changes<T: Debug + PartialEq>(changes: &mut Vec<String>, prefix: &str, before: &T, after: &T) {
+    if before == after {
+        return;
+    }
+    for ((name, before_value), (_, after_value)) in debug_fields(before).into_iter().zip(debug_fields(after)) {
+        if before_value != after_value {
+            changes.push(format!("{}.{}: {} -> {}", prefix, name, before_value, after_value));
+        }
+    }
+}
+
+impl PoolSnapshot {
+    pub async fn capture(ctx: &mut ProgramTestContext, pda: &Pda) -> PoolSnapshot {
+        let pda_token_x = ctx.banks_client.get_packed_account_data::<Account>(pda.pda_token_x_pk)
+            .await
+            .expect("pda_token_x");
+        let pda_token_y = ctx.banks_client.get_packed_account_data::<Account>(pda.pda_token_y_pk)
+            .await
+            .expect("pda_token_y");
+        let vault = get_vault(ctx, pda.vault.0).await;
+
+        PoolSnapshot { pda_token_x, pda_token_y, vault, user_token_x_amount: None, user_token_y_amount: None }
+    }
+
+    /// Same as `capture`, with the balances of the user SPL token X, Y holders
+    pub async fn capture_with_user(
+        ctx: &mut ProgramTestContext,
+        pda: &Pda,
+        user_token_x_pk: &Pubkey,
+        user_token_y_pk: &Pubkey,
+    ) -> PoolSnapshot {
+        let mut snapshot = PoolSnapshot::capture(ctx, pda).await;
+        let user_token_x = ctx.banks_client.get_packed_account_data::<Account>(*user_token_x_pk)
+            .await
+            .expect("user_token_x");
+        let user_token_y = ctx.banks_client.get_packed_account_data::<Account>(*user_token_y_pk)
+            .await
+            .expect("user_token_y");
+        snapshot.user_token_x_amount = Some(user_token_x.amount);
+        snapshot.user_token_y_amount = Some(user_token_y.amount);
+        snapshot
+    }
+
+    /// X * Y of the PDA SPL token holders
+    pub fn invariant(&self) -> u128 {
+        self.pda_token_x.amount as u128 * self.pda_token_y.amount as u128
+    }
+
+    /// X * Y of the vault
+    pub fn vault_invariant(&self) -> u128 {
+        self.vault.token_x_amount as u128 * self.vault.token_y_amount as u128
+    }
+
+    /// Changes from the snapshot to the other one as `field: before -> after`,
+    /// every field of the PDA SPL token X, Y holders and of the vault is compared
+    pub fn diff(&self, other: &PoolSnapshot) -> Vec<String> {
+        let mut changes = Vec::new();
+        push_changes(&mut changes, "pda_token_x", &self.pda_token_x, &other.pda_token_x);
+        push_changes(&mut changes, "pda_token_y", &self.pda_token_y, &other.pda_token_y);
+        push_changes(&mut changes, "vault", &self.vault, &other.vault);
+        if self.user_token_x_amount != other.user_token_x_amount {
+            changes.push(format!(
+                "user_token_x_amount: {:?} -> {:?}", self.user_token_x_amount, other.user_token_x_amount,
+            ));
+        }
+        if self.user_token_y_amount != other.user_token_y_amount {
+            changes.push(format!(
+                "user_token_y_amount: {:?} -> {:?}", self.user_token_y_amount, other.user_token_y_amount,
+            ));
+        }
+        changes
+    }
+}
+
 pub fn decode_error<T: DecodeError<T> + FromPrimitive>(e: TransactionError) -> T {
     match e {
         TransactionError::InstructionError(_, InstructionError::Custom(code)) =>
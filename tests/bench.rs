@@ -0,0 +1,221 @@
+#![cfg(all(feature = "test-bpf", feature = "bench"))]
+
+//! Compute units of the instructions, run with `cargo test-bpf --features bench -- --nocapture`.
+//! The BPF build of the program is loaded, so the compute units are metered like on-chain.
+
+use std::future::Future;
+use solana_program::program_option::COption;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_program_test::ProgramTest;
+use solana_sdk::account::Account as SolanaAccount;
+use solana_sdk::signature::Keypair;
+use solana_sdk::signer::Signer;
+use solana_sdk::transaction::Transaction;
+use spl_token::state::{Account, AccountState, Mint};
+use amm::instruction::AmmInstruction;
+use amm::pda::Pda;
+use amm::state::{Vault, FEE_DENOMINATOR};
+use amm::id;
+
+/// Compute units `InitMarket` must fit into
+const INIT_MARKET_MAX_UNITS: u64 = 150_000;
+/// Compute units `Swap` must fit into
+const SWAP_MAX_UNITS: u64 = 60_000;
+/// Default compute budget of an instruction
+const DEFAULT_MAX_UNITS: u64 = 200_000;
+
+const TOKEN_X_AMOUNT: u64 = 5_000;
+const TOKEN_Y_AMOUNT: u64 = 15_000;
+const LAMPORTS: u64 = 5_000_000_000;
+
+struct Bench {
+    user: Keypair,
+    minter_x_pk: Pubkey,
+    minter_y_pk: Pubkey,
+    user_token_x_pk: Pubkey,
+    user_token_y_pk: Pubkey,
+}
+
+impl Bench {
+    fn new() -> Bench {
+        let user = Keypair::new();
+        let minter_x_pk = Pubkey::new_unique();
+        let minter_y_pk = Pubkey::new_unique();
+        let user_token_x_pk = spl_associated_token_account::get_associated_token_address(
+            &user.pubkey(), &minter_x_pk,
+        );
+        let user_token_y_pk = spl_associated_token_account::get_associated_token_address(
+            &user.pubkey(), &minter_y_pk,
+        );
+
+        Bench { user, minter_x_pk, minter_y_pk, user_token_x_pk, user_token_y_pk }
+    }
+
+    /// Program test with the user, minters and user tokens, the market isn't initialized
+    fn program_test(&self, max_units: u64) -> ProgramTest {
+        let mut program_test = ProgramTest::new("amm", id(), None);
+        program_test.set_bpf_compute_max_units(max_units);
+
+        program_test.add_account(
+            self.user.pubkey(),
+            SolanaAccount::new(LAMPORTS, 0, &solana_program::system_program::id()),
+        );
+        add_mint(&mut program_test, &self.minter_x_pk, &self.user.pubkey(), TOKEN_X_AMOUNT);
+        add_mint(&mut program_test, &self.minter_y_pk, &self.user.pubkey(), TOKEN_Y_AMOUNT);
+        add_token(&mut program_test, &self.user_token_x_pk, &self.minter_x_pk, &self.user.pubkey(), TOKEN_X_AMOUNT);
+        add_token(&mut program_test, &self.user_token_y_pk, &self.minter_y_pk, &self.user.pubkey(), TOKEN_Y_AMOUNT);
+
+        program_test
+    }
+
+    /// Program test with the market initialized by half of the user tokens
+    fn program_test_with_market(&self, max_units: u64) -> ProgramTest {
+        let mut program_test = self.program_test(max_units);
+        let pda = Pda::generate(&self.minter_x_pk, &self.minter_y_pk);
+
+        add_token(&mut program_test, &pda.pda_token_x_pk, &self.minter_x_pk, &pda.pda_owner_token_x.0, TOKEN_X_AMOUNT / 2);
+        add_token(&mut program_test, &pda.pda_token_y_pk, &self.minter_y_pk, &pda.pda_owner_token_y.0, TOKEN_Y_AMOUNT / 2);
+
+        let vault = Vault {
+            token_x_amount: TOKEN_X_AMOUNT / 2,
+            token_y_amount: TOKEN_Y_AMOUNT / 2,
+            authority: self.user.pubkey(),
+            fee_denominator: FEE_DENOMINATOR,
+            minter_x: self.minter_x_pk,
+            minter_y: self.minter_y_pk,
+            ..Vault::default()
+        };
+        let mut data = vec![0; Vault::LEN];
+        vault.save(&mut data).unwrap();
+        program_test.add_account(
+            pda.vault.0,
+            SolanaAccount {
+                lamports: Rent::default().minimum_balance(data.len()),
+                data,
+                owner: id(),
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+
+        program_test
+    }
+
+    async fn init_market_succeeds(&self, max_units: u64) -> bool {
+        let (mut banks_client, _, recent_blockhash) = self.program_test(max_units).start().await;
+        let init_ix = AmmInstruction::init_market(
+            TOKEN_X_AMOUNT / 2,
+            TOKEN_Y_AMOUNT / 2,
+            self.user.pubkey(),
+            self.user.pubkey(),
+            self.user.pubkey(),
+            self.user_token_x_pk,
+            self.user_token_y_pk,
+            self.minter_x_pk,
+            self.minter_y_pk,
+        );
+        let init_tx = Transaction::new_signed_with_payer(
+            &[init_ix],
+            Some(&self.user.pubkey()),
+            &[&self.user],
+            recent_blockhash,
+        );
+        banks_client.process_transaction(init_tx).await.is_ok()
+    }
+
+    async fn swap_succeeds(&self, max_units: u64) -> bool {
+        let (mut banks_client, _, recent_blockhash) = self.program_test_with_market(max_units).start().await;
+        let swap_ix = AmmInstruction::swap(
+            TOKEN_X_AMOUNT / 4,
+            self.minter_x_pk,
+            self.user.pubkey(),
+            self.user_token_x_pk,
+            self.user_token_y_pk,
+            self.minter_x_pk,
+            self.minter_y_pk,
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&self.user.pubkey()),
+            &[&self.user],
+            recent_blockhash,
+        );
+        banks_client.process_transaction(swap_tx).await.is_ok()
+    }
+}
+
+fn add_mint(program_test: &mut ProgramTest, minter_pk: &Pubkey, authority_pk: &Pubkey, supply: u64) {
+    let mint = Mint {
+        mint_authority: COption::Some(*authority_pk),
+        supply,
+        decimals: 0,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    add_packed(program_test, minter_pk, mint);
+}
+
+fn add_token(program_test: &mut ProgramTest, token_pk: &Pubkey, minter_pk: &Pubkey, owner_pk: &Pubkey, amount: u64) {
+    let token = Account {
+        mint: *minter_pk,
+        owner: *owner_pk,
+        amount,
+        delegate: COption::None,
+        state: AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
+    };
+    add_packed(program_test, token_pk, token);
+}
+
+fn add_packed<T: Pack>(program_test: &mut ProgramTest, pk: &Pubkey, state: T) {
+    let mut data = vec![0; T::LEN];
+    T::pack(state, &mut data).unwrap();
+    program_test.add_account(
+        *pk,
+        SolanaAccount {
+            lamports: Rent::default().minimum_balance(T::LEN),
+            data,
+            owner: spl_token::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+}
+
+/// Smallest compute budget the instruction succeeds with
+async fn consumed_units<F, Fut>(succeeds: F) -> u64
+    where F: Fn(u64) -> Fut, Fut: Future<Output = bool>
+{
+    assert!(succeeds(DEFAULT_MAX_UNITS).await, "fails with the default compute budget");
+
+    let (mut failed, mut succeeded) = (0, DEFAULT_MAX_UNITS);
+    while succeeded - failed > 1 {
+        let max_units = failed + (succeeded - failed) / 2;
+        if succeeds(max_units).await {
+            succeeded = max_units;
+        } else {
+            failed = max_units;
+        }
+    }
+    succeeded
+}
+
+#[tokio::test]
+async fn bench_init_market() {
+    let bench = Bench::new();
+    let units = consumed_units(|max_units| bench.init_market_succeeds(max_units)).await;
+    println!("InitMarket: {} compute units (max {})", units, INIT_MARKET_MAX_UNITS);
+    assert!(units <= INIT_MARKET_MAX_UNITS);
+}
+
+#[tokio::test]
+async fn bench_swap() {
+    let bench = Bench::new();
+    let units = consumed_units(|max_units| bench.swap_succeeds(max_units)).await;
+    println!("Swap: {} compute units (max {})", units, SWAP_MAX_UNITS);
+    assert!(units <= SWAP_MAX_UNITS);
+}
@@ -0,0 +1,128 @@
+#![cfg(feature = "program")]
+
+use solana_program::pubkey::Pubkey;
+use solana_program::sysvar;
+use amm::client::AmmClient;
+#[cfg(feature = "compute-budget")]
+use amm::client::WithComputeBudget;
+use amm::instruction::{AmmInstruction, InitMarketOptions, SwapOptions};
+use amm::pda::Pda;
+#[cfg(feature = "compute-budget")]
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+
+#[test]
+fn client_init_market() {
+    let minter_x_pk = Pubkey::new_unique();
+    let minter_y_pk = Pubkey::new_unique();
+    let user_pk = Pubkey::new_unique();
+    let user_token_x_pk = spl_associated_token_account::get_associated_token_address(&user_pk, &minter_x_pk);
+    let user_token_y_pk = spl_associated_token_account::get_associated_token_address(&user_pk, &minter_y_pk);
+    let client = AmmClient::new(minter_x_pk, minter_y_pk, user_pk);
+
+    assert_eq!(client.pda.vault, Pda::generate(&minter_x_pk, &minter_y_pk).vault);
+    assert_eq!(
+        client.init_market(100, 300),
+        AmmInstruction::init_market(
+            100,
+            300,
+            user_pk,
+            user_pk,
+            user_pk,
+            user_token_x_pk,
+            user_token_y_pk,
+            minter_x_pk,
+            minter_y_pk,
+        )
+    );
+    let options = InitMarketOptions {
+        allow_existing: true,
+        authority: Some(Pubkey::new_unique()),
+        min_reserve: 10,
+        launch_slot: 100,
+    };
+    assert_eq!(
+        client.init_market_with_options(100, 300, options.clone()),
+        AmmInstruction::init_market_with_options(
+            100,
+            300,
+            user_pk,
+            user_pk,
+            user_pk,
+            user_token_x_pk,
+            user_token_y_pk,
+            minter_x_pk,
+            minter_y_pk,
+            options,
+        )
+    );
+}
+
+#[test]
+fn client_swap() {
+    let minter_x_pk = Pubkey::new_unique();
+    let minter_y_pk = Pubkey::new_unique();
+    let user_pk = Pubkey::new_unique();
+    let user_token_x_pk = Pubkey::new_unique();
+    let user_token_y_pk = Pubkey::new_unique();
+    let client = AmmClient::new(minter_x_pk, minter_y_pk, user_pk)
+        .with_user_tokens(user_token_x_pk, user_token_y_pk);
+
+    assert_eq!(
+        client.swap(50, minter_x_pk),
+        AmmInstruction::swap(
+            50,
+            minter_x_pk,
+            user_pk,
+            user_token_x_pk,
+            user_token_y_pk,
+            minter_x_pk,
+            minter_y_pk,
+        )
+    );
+    let options = SwapOptions {
+        unwrap: true,
+        price_limit: Some(1 << 64),
+        output_granularity: 10,
+        reject_earlier_swap_in_tx: true,
+        create_return_token: true,
+        max_impact_bps: 100,
+        referrer: None,
+        referral_fee_bps: 0,
+        return_to_destination: false,
+        return_token_payer: None,
+    };
+    let swap_ix = client.swap_with_options(50, minter_y_pk, options.clone());
+    assert_eq!(swap_ix.accounts[11].pubkey, sysvar::instructions::id());
+    assert_eq!(
+        swap_ix.accounts.last().map(|account| account.pubkey),
+        Some(spl_associated_token_account::id())
+    );
+    assert_eq!(
+        swap_ix,
+        AmmInstruction::swap_with_options(
+            50,
+            minter_y_pk,
+            user_pk,
+            user_token_x_pk,
+            user_token_y_pk,
+            minter_x_pk,
+            minter_y_pk,
+            options,
+        )
+    );
+}
+
+#[cfg(feature = "compute-budget")]
+#[test]
+fn client_with_compute_budget() {
+    let minter_x_pk = Pubkey::new_unique();
+    let minter_y_pk = Pubkey::new_unique();
+    let client = AmmClient::new(minter_x_pk, minter_y_pk, Pubkey::new_unique());
+    let units: u32 = 300_000;
+
+    let swap_ixs = client.swap(50, minter_x_pk).with_compute_budget(units);
+    assert_eq!(swap_ixs.len(), 2);
+    assert_eq!(swap_ixs[0].program_id, solana_sdk::compute_budget::id());
+    assert_eq!(swap_ixs[0], ComputeBudgetInstruction::request_units(units, 0));
+    assert_eq!(swap_ixs[1], client.swap(50, minter_x_pk));
+}
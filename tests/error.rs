@@ -0,0 +1,59 @@
+#![cfg(feature = "program")]
+
+use num_traits::FromPrimitive;
+use solana_program::program_error::ProgramError;
+use amm::error::AmmError;
+
+/// Codes of `ProgramError::Custom` decoded by the clients, new errors must be appended
+const ERROR_CODES: &[(AmmError, u32)] = &[
+    (AmmError::IdenticalMinter, 0),
+    (AmmError::AlreadyInUse, 1),
+    (AmmError::AmountZero, 2),
+    (AmmError::Overflow, 3),
+    (AmmError::Underflow, 4),
+    (AmmError::IncorrectSwapPk, 5),
+    (AmmError::CalculatedZeroSwap, 6),
+    (AmmError::InvalidVault, 7),
+    (AmmError::NoPendingAuthority, 8),
+    (AmmError::Unauthorized, 9),
+    (AmmError::ReserveTooLarge, 10),
+    (AmmError::IncorrectDestinationMint, 11),
+    (AmmError::InvalidFee, 12),
+    (AmmError::UnwrapNotNative, 13),
+    (AmmError::AliasedAccounts, 14),
+    (AmmError::IncorrectUserTokenMint, 15),
+    (AmmError::MintMismatch, 16),
+    (AmmError::CannotRescuePoolToken, 17),
+    (AmmError::ExtremeInitRatio, 18),
+    (AmmError::PriceLimitExceeded, 19),
+    (AmmError::NoOpSwap, 20),
+    (AmmError::VaultNotWritable, 21),
+    (AmmError::ReserveOverflow, 22),
+    (AmmError::MarketNotInitialized, 23),
+    (AmmError::DuplicateSwapInTx, 24),
+    (AmmError::InvalidBump, 25),
+    (AmmError::InvariantViolation, 26),
+    (AmmError::BelowMinReserve, 27),
+    (AmmError::InsufficientUserBalance, 28),
+    (AmmError::PdaRoleMismatch, 29),
+    (AmmError::MarketNotLive, 30),
+    (AmmError::UnexpectedAccounts, 31),
+    (AmmError::NotTokenOwner, 32),
+    (AmmError::PriceImpactTooHigh, 33),
+    (AmmError::MarketPaused, 34),
+    (AmmError::MarketNotPaused, 35),
+    (AmmError::ExcessiveOutput, 36),
+    (AmmError::IncorrectAssociatedTokenProgram, 37),
+    (AmmError::MissingReferrer, 38),
+];
+
+#[test]
+fn error_codes() {
+    for (error, code) in ERROR_CODES {
+        assert_eq!(*error as u32, *code);
+        assert_eq!(ProgramError::from(*error), ProgramError::Custom(*code));
+        assert_eq!(AmmError::from_u32(*code), Some(*error));
+    }
+    let next_code = ERROR_CODES.len() as u32;
+    assert_eq!(AmmError::from_u32(next_code), None, "new error must be added to ERROR_CODES");
+}
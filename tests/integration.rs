@@ -1,19 +1,32 @@
 #![cfg(feature = "test-bpf")]
 
+#[cfg(feature = "strict-accounts")]
+use solana_program::instruction::AccountMeta;
+use solana_program::clock::{Clock, Slot, UnixTimestamp};
+use solana_program::program_pack::Pack;
 use solana_program::pubkey::Pubkey;
+use solana_program::{system_instruction, sysvar};
 use solana_program_test::ProgramTestContext;
+use solana_sdk::account::{Account as SolanaAccount, AccountSharedData};
 use solana_sdk::signature::Keypair;
 use solana_sdk::signer::Signer;
-use solana_sdk::transaction::Transaction;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::transaction::{Transaction, TransactionError};
 use solana_sdk::transport::TransportError;
 use spl_token::error::TokenError;
-use spl_token::state::{Account, AccountState};
+use spl_token::state::{Account, AccountState, Mint};
+#[cfg(feature = "compute-budget")]
+use amm::client::{AmmClient, WithComputeBudget};
 use amm::error::AmmError;
-use amm::instruction::AmmInstruction;
+use amm::instruction::{AmmInstruction, InitMarketOptions, SwapOptions};
 use amm::pda::Pda;
-use amm::state::Vault;
-use amm::swap::{calc_swap, SwapDirection};
-use crate::basic::{check_pda, decode_error, Env};
+use amm::state::{
+    MarketChecks, ReservesReport, Vault, MAX_INIT_RATIO, MAX_REFERRAL_FEE_BPS, MAX_RESERVE, PRICE_SAMPLES,
+};
+use amm::swap::{
+    calc_referral_fee, calc_swap, calc_swap_with_fee, invariant_ok, normalized_price, SwapDirection,
+};
+use crate::basic::{check_pda, decode_error, get_vault, Env, PoolSnapshot};
 
 mod basic;
 
@@ -53,6 +66,38 @@ async fn init_market(
     ctx.banks_client.process_transaction(init_tx).await
 }
 
+async fn init_market_with_options(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    user_token_x_y_owner_and_payer: &Keypair,
+    user_token_x_pk: &Pubkey,
+    user_token_y_pk: &Pubkey,
+    amount_x: u64,
+    amount_y: u64,
+    options: InitMarketOptions,
+) -> Result<(), TransportError> {
+    let init_ix = AmmInstruction::init_market_with_options(
+        amount_x,
+        amount_y,
+        user_token_x_y_owner_and_payer.pubkey(),
+        user_token_x_y_owner_and_payer.pubkey(),
+        user_token_x_y_owner_and_payer.pubkey(),
+        *user_token_x_pk,
+        *user_token_y_pk,
+        minter_x.pubkey(),
+        minter_y.pubkey(),
+        options,
+    );
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&user_token_x_y_owner_and_payer.pubkey()),
+        &[user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(init_tx).await
+}
+
 async fn check_init_market(
     ctx: &mut ProgramTestContext,
     minter_x: &Keypair,
@@ -61,27 +106,92 @@ async fn check_init_market(
     amount_x: u64,
     amount_y: u64,
 ) {
-    let pda_token_x_acc_after_init = ctx.banks_client.get_packed_account_data::<Account>(pda.pda_token_x_pk)
-        .await
-        .expect("pda_token_x_acc_after_init");
-    assert_eq!(pda_token_x_acc_after_init.owner, pda.pda_owner_token_x.0);
-    assert_eq!(pda_token_x_acc_after_init.state, AccountState::Initialized);
-    assert_eq!(pda_token_x_acc_after_init.mint, minter_x.pubkey());
-    assert_eq!(pda_token_x_acc_after_init.amount, amount_x);
+    let after_init = PoolSnapshot::capture(ctx, pda).await;
+    assert_eq!(after_init.pda_token_x.owner, pda.pda_owner_token_x.0);
+    assert_eq!(after_init.pda_token_x.state, AccountState::Initialized);
+    assert_eq!(after_init.pda_token_x.mint, minter_x.pubkey());
+    assert_eq!(after_init.pda_token_x.amount, amount_x);
 
-    let pda_token_t_acc_after_init = ctx.banks_client.get_packed_account_data::<Account>(pda.pda_token_y_pk)
-        .await
-        .expect("pda_token_y_acc_after_init");
-    assert_eq!(pda_token_t_acc_after_init.owner, pda.pda_owner_token_y.0);
-    assert_eq!(pda_token_t_acc_after_init.state, AccountState::Initialized);
-    assert_eq!(pda_token_t_acc_after_init.mint, minter_y.pubkey());
-    assert_eq!(pda_token_t_acc_after_init.amount, amount_y);
+    assert_eq!(after_init.pda_token_y.owner, pda.pda_owner_token_y.0);
+    assert_eq!(after_init.pda_token_y.state, AccountState::Initialized);
+    assert_eq!(after_init.pda_token_y.mint, minter_y.pubkey());
+    assert_eq!(after_init.pda_token_y.amount, amount_y);
+
+    assert_eq!(after_init.vault.token_x_amount, amount_x);
+    assert_eq!(after_init.vault.token_y_amount, amount_y);
+    assert_eq!(after_init.vault.minter_x, minter_x.pubkey());
+    assert_eq!(after_init.vault.minter_y, minter_y.pubkey());
+
+    // created accounts are rent-exempt for their size
+    let rent = ctx.banks_client.get_rent().await.expect("rent");
+    for (pk, data_len) in [
+        (pda.vault.0, Vault::LEN),
+        (pda.pda_token_x_pk, Account::LEN),
+        (pda.pda_token_y_pk, Account::LEN),
+    ] {
+        let acc = ctx.banks_client.get_account(pk)
+            .await
+            .expect("acc")
+            .expect("acc");
+        assert_eq!(acc.data.len(), data_len);
+        assert!(acc.lamports >= rent.minimum_balance(acc.data.len()));
+    }
+}
+
+/// Funded user with own SPL token X, Y holders of the amounts, minted by the mint authority
+async fn new_user(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    mint_authority: &Keypair,
+    amount_x: u64,
+    amount_y: u64,
+) -> (Keypair, Pubkey, Pubkey) {
+    let user = Keypair::new();
+    let user_token_x_pk = spl_associated_token_account::get_associated_token_address(&user.pubkey(), &minter_x.pubkey());
+    let user_token_y_pk = spl_associated_token_account::get_associated_token_address(&user.pubkey(), &minter_y.pubkey());
+    let create_user_tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(&ctx.payer.pubkey(), &user.pubkey(), 1_000_000_000),
+            spl_associated_token_account::create_associated_token_account(
+                &ctx.payer.pubkey(), &user.pubkey(), &minter_x.pubkey(),
+            ),
+            spl_associated_token_account::create_associated_token_account(
+                &ctx.payer.pubkey(), &user.pubkey(), &minter_y.pubkey(),
+            ),
+        ],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(create_user_tx).await.expect("create_user_tx");
+    Env::mint_token(ctx, mint_authority, minter_x, &user_token_x_pk, mint_authority, amount_x).await;
+    Env::mint_token(ctx, mint_authority, minter_y, &user_token_y_pk, mint_authority, amount_y).await;
+
+    (user, user_token_x_pk, user_token_y_pk)
+}
 
-    let vault_after_init = ctx.banks_client.get_account_data_with_borsh::<Vault>(pda.vault.0)
+/// Fully withdrawn market: zero reserves and empty PDA SPL token holders
+async fn drain_market(ctx: &mut ProgramTestContext, pda: &Pda) {
+    for pda_token_pk in [pda.pda_token_x_pk, pda.pda_token_y_pk] {
+        let mut pda_token_acc = ctx.banks_client.get_account(pda_token_pk)
+            .await
+            .expect("pda_token_acc")
+            .expect("pda_token_acc");
+        let mut pda_token = Account::unpack(&pda_token_acc.data).expect("pda_token");
+        pda_token.amount = 0;
+        Account::pack(pda_token, &mut pda_token_acc.data).expect("pack pda_token");
+        ctx.set_account(&pda_token_pk, &AccountSharedData::from(pda_token_acc));
+    }
+    let mut vault_acc = ctx.banks_client.get_account(pda.vault.0)
         .await
-        .expect("vault_after_init");
-    assert_eq!(vault_after_init.token_x_amount, amount_x);
-    assert_eq!(vault_after_init.token_y_amount, amount_y);
+        .expect("vault_acc")
+        .expect("vault_acc");
+    let mut vault = Vault::load(&vault_acc.data).expect("vault");
+    vault.token_x_amount = 0;
+    vault.token_y_amount = 0;
+    vault.save(&mut vault_acc.data).expect("save vault");
+    ctx.set_account(&pda.vault.0, &AccountSharedData::from(vault_acc));
 }
 
 #[tokio::test]
@@ -196,170 +306,143 @@ async fn init_market_zero_amount() {
     );
 }
 
+#[tokio::test]
+async fn init_market_insufficient_user_balance() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 15_001;
 
-// Test swap
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
 
-async fn swap(
-    ctx: &mut ProgramTestContext,
-    minter_x: &Keypair,
-    minter_y: &Keypair,
-    user_token_x_y_owner_and_payer: &Keypair,
-    user_token_x_pk: &Pubkey,
-    user_token_y_pk: &Pubkey,
-    pda: &Pda,
-    swap: &Pubkey,
-    amount: u64,
-) {
-    let swap_direction = SwapDirection::new(swap, &minter_x.pubkey(), &minter_y.pubkey()).expect("swap_direction");
+    let init_error = init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await
+        .expect_err("init_error")
+        .unwrap();
 
-    let pda_token_x_acc_before_swap = ctx.banks_client
-        .get_packed_account_data::<Account>(pda.pda_token_x_pk)
-        .await
-        .expect("pda_token_x_acc_before_swap");
-    let pda_token_y_acc_before_swap = ctx.banks_client
-        .get_packed_account_data::<Account>(pda.pda_token_y_pk)
-        .await
-        .expect("pda_token_y_acc_before_swap");
-    let invariant_before_swap = pda_token_x_acc_before_swap.amount
-        .checked_mul(pda_token_y_acc_before_swap.amount)
-        .expect("invariant_before_swap");
-    let vault_before_swap = ctx.banks_client.get_account_data_with_borsh::<Vault>(pda.vault.0)
-        .await
-        .expect("vault_before_swap");
-    let invariant_vault_before_swap = vault_before_swap.token_x_amount
-        .checked_mul(vault_before_swap.token_y_amount)
-        .expect("invariant_vault_before_swap");
-    let (take_user_token_pk, return_pda_token_pk) = match swap_direction {
-        SwapDirection::XtoY => (user_token_x_pk, pda.pda_token_y_pk),
-        SwapDirection::YtoX => (user_token_y_pk, pda.pda_token_x_pk)
-    };
-    let take_user_token_acc_before_swap = ctx.banks_client
-        .get_packed_account_data::<Account>(*take_user_token_pk)
-        .await
-        .expect("take_user_token_acc_before_swap");
-    let return_pda_token_acc_before_swap = ctx.banks_client
-        .get_packed_account_data::<Account>(return_pda_token_pk)
-        .await
-        .expect("return_pda_token_acc_before_swap");
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::InsufficientUserBalance
+    );
+    // no PDA accounts are left behind
+    check_pda(ctx, &pda).await;
+}
 
-    let swap_ix = AmmInstruction::swap(
-        amount,
-        *swap,
-        user_token_x_y_owner_and_payer.pubkey(),
-        *user_token_x_pk,
-        *user_token_y_pk,
-        minter_x.pubkey(),
-        minter_y.pubkey(),
+#[tokio::test]
+async fn init_market_not_token_owner() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let user = &env.user_token_x_y_owner_and_payer;
+    let other_owner = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    // another key signs as the owner of the user SPL token Y holder
+    let init_ix = AmmInstruction::init_market(
+        1_000,
+        3_000,
+        user.pubkey(),
+        other_owner.pubkey(),
+        user.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
     );
-    let swap_tx = Transaction::new_signed_with_payer(
-        &[swap_ix],
-        Some(&user_token_x_y_owner_and_payer.pubkey()),
-        &[user_token_x_y_owner_and_payer],
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&user.pubkey()),
+        &[user, &other_owner],
         ctx.last_blockhash,
     );
-    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+    let init_error = ctx.banks_client.process_transaction(init_tx).await
+        .expect_err("init_error")
+        .unwrap();
 
-    let pda_token_x_acc_after_swap = ctx.banks_client
-        .get_packed_account_data::<Account>(pda.pda_token_x_pk)
-        .await
-        .expect("pda_token_x_acc_after_swap");
-    let pda_token_y_acc_after_swap = ctx.banks_client
-        .get_packed_account_data::<Account>(pda.pda_token_y_pk)
-        .await
-        .expect("pda_token_y_acc_after_swap");
-    let invariant_after_swap = pda_token_x_acc_after_swap.amount
-        .checked_mul(pda_token_y_acc_after_swap.amount)
-        .expect("invariant_after_swap");
-    let vault_after_swap = ctx.banks_client.get_account_data_with_borsh::<Vault>(pda.vault.0)
-        .await
-        .expect("vault_after_swap");
-    let invariant_vault_after_swap = vault_after_swap.token_x_amount
-        .checked_mul(vault_after_swap.token_y_amount)
-        .expect("invariant_vault_after_swap");
-    let take_user_token_acc_after_swap = ctx.banks_client
-        .get_packed_account_data::<Account>(*take_user_token_pk)
-        .await
-        .expect("take_user_token_acc_after_swap");
-    let return_pda_token_acc_after_swap = ctx.banks_client
-        .get_packed_account_data::<Account>(return_pda_token_pk)
-        .await
-        .expect("return_pda_token_acc_after_swap");
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::NotTokenOwner
+    );
+    check_pda(ctx, &pda).await;
+}
 
-    assert_eq!(invariant_before_swap, invariant_vault_before_swap);
-    assert_eq!(invariant_before_swap, invariant_after_swap);
-    assert_eq!(invariant_after_swap, invariant_vault_after_swap);
+#[tokio::test]
+async fn init_market_swapped_pda_accounts() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let user = &env.user_token_x_y_owner_and_payer;
 
-    let swap_result = match swap_direction {
-        SwapDirection::XtoY => calc_swap(
-            amount,
-            pda_token_x_acc_before_swap.amount,
-            pda_token_y_acc_before_swap.amount,
-        ),
-        SwapDirection::YtoX => calc_swap(
-            amount,
-            pda_token_y_acc_before_swap.amount,
-            pda_token_x_acc_before_swap.amount,
-        )
-    }.expect("swap_result");
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
 
-    assert_eq!(
-        take_user_token_acc_before_swap.amount,
-        take_user_token_acc_after_swap.amount + swap_result.take_amount
+    let mut init_ix = AmmInstruction::init_market(
+        1_000,
+        3_000,
+        user.pubkey(),
+        user.pubkey(),
+        user.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    // the vault in place of the PDA SPL token X holder and vice versa
+    assert_eq!(init_ix.accounts[7].pubkey, pda.pda_token_x_pk);
+    assert_eq!(init_ix.accounts[11].pubkey, pda.vault.0);
+    init_ix.accounts.swap(7, 11);
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
     );
+    let init_error = ctx.banks_client.process_transaction(init_tx).await
+        .expect_err("init_error")
+        .unwrap();
+
     assert_eq!(
-        return_pda_token_acc_before_swap.amount,
-        return_pda_token_acc_after_swap.amount + swap_result.return_amount
+        decode_error::<AmmError>(init_error),
+        AmmError::PdaRoleMismatch
     );
+    check_pda(ctx, &pda).await;
 }
 
-
 #[tokio::test]
-async fn swap_x_to_y() {
+async fn init_market_max_reserve() {
     let mut env = Env::new().await;
     let ctx = &mut env.ctx;
-    let amount_x = 500;
-    let amount_y = 300;
-    let swap_pk = &env.minter_x.pubkey();
+    let amount_x = MAX_RESERVE;
+    let amount_y = MAX_RESERVE;
 
     let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
     check_pda(ctx, &pda).await;
 
-    init_market(
+    Env::mint_token(
         ctx,
-        &env.minter_x,
-        &env.minter_y,
         &env.user_token_x_y_owner_and_payer,
+        &env.minter_x,
         &env.user_token_x_pk,
-        &env.user_token_y_pk,
-        amount_x,
-        amount_y,
-    ).await.expect("init_market");
-    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
-
-    swap(
+        &env.user_token_x_y_owner_and_payer,
+        MAX_RESERVE,
+    ).await;
+    Env::mint_token(
         ctx,
-        &env.minter_x,
-        &env.minter_y,
         &env.user_token_x_y_owner_and_payer,
-        &env.user_token_x_pk,
+        &env.minter_y,
         &env.user_token_y_pk,
-        &pda,
-        swap_pk,
-        100,
+        &env.user_token_x_y_owner_and_payer,
+        MAX_RESERVE,
     ).await;
-}
-
-
-#[tokio::test]
-async fn swap_y_to_x() {
-    let mut env = Env::new().await;
-    let ctx = &mut env.ctx;
-    let amount_x = 500;
-    let amount_y = 300;
-    let swap_pk = &env.minter_y.pubkey();
-
-    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
-    check_pda(ctx, &pda).await;
 
     init_market(
         ctx,
@@ -372,32 +455,19 @@ async fn swap_y_to_x() {
         amount_y,
     ).await.expect("init_market");
     check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
-
-    swap(
-        ctx,
-        &env.minter_x,
-        &env.minter_y,
-        &env.user_token_x_y_owner_and_payer,
-        &env.user_token_x_pk,
-        &env.user_token_y_pk,
-        &pda,
-        swap_pk,
-        100,
-    ).await;
 }
 
 #[tokio::test]
-async fn swap_x_to_y_revert_amount() {
+async fn init_market_reserve_too_large() {
     let mut env = Env::new().await;
     let ctx = &mut env.ctx;
-    let amount_x = 300;
-    let amount_y = 500;
-    let swap_pk = &env.minter_x.pubkey();
+    let amount_x = 100;
+    let amount_y = MAX_RESERVE + 1;
 
     let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
     check_pda(ctx, &pda).await;
 
-    init_market(
+    let init_error = init_market(
         ctx,
         &env.minter_x,
         &env.minter_y,
@@ -406,29 +476,23 @@ async fn swap_x_to_y_revert_amount() {
         &env.user_token_y_pk,
         amount_x,
         amount_y,
-    ).await.expect("init_market");
-    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+    ).await
+        .expect_err("init_error")
+        .unwrap();
 
-    swap(
-        ctx,
-        &env.minter_x,
-        &env.minter_y,
-        &env.user_token_x_y_owner_and_payer,
-        &env.user_token_x_pk,
-        &env.user_token_y_pk,
-        &pda,
-        swap_pk,
-        100,
-    ).await;
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::ReserveTooLarge
+    );
+    check_pda(ctx, &pda).await;
 }
 
 #[tokio::test]
-async fn swap_y_to_x_revert_amount() {
+async fn init_market_rent_exempt() {
     let mut env = Env::new().await;
     let ctx = &mut env.ctx;
-    let amount_x = 300;
-    let amount_y = 500;
-    let swap_pk = &env.minter_y.pubkey();
+    let amount_x = 100;
+    let amount_y = 300;
 
     let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
     check_pda(ctx, &pda).await;
@@ -445,88 +509,4248 @@ async fn swap_y_to_x_revert_amount() {
     ).await.expect("init_market");
     check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
 
-    swap(
-        ctx,
-        &env.minter_x,
-        &env.minter_y,
-        &env.user_token_x_y_owner_and_payer,
-        &env.user_token_x_pk,
-        &env.user_token_y_pk,
-        &pda,
-        swap_pk,
-        100,
-    ).await;
+    let rent = ctx.banks_client.get_rent().await.expect("rent");
+    for pk in [pda.vault.0, pda.pda_token_x_pk, pda.pda_token_y_pk] {
+        let acc = ctx.banks_client.get_account(pk)
+            .await
+            .expect("acc")
+            .expect("acc");
+        assert!(rent.is_exempt(acc.lamports, acc.data.len()));
+    }
+    let vault_acc = ctx.banks_client.get_account(pda.vault.0)
+        .await
+        .expect("vault_acc")
+        .expect("vault_acc");
+    assert_eq!(vault_acc.data.len(), Vault::LEN);
+    assert_eq!(vault_acc.lamports, rent.minimum_balance(Vault::LEN));
 }
 
 #[tokio::test]
-async fn swap_without_inited_market() {
+async fn init_market_requires_rent_sysvar_account() {
     let mut env = Env::new().await;
     let ctx = &mut env.ctx;
-    let swap_pk = env.minter_y.pubkey();
-    let amount = 100;
+    let amount_x = 100;
+    let amount_y = 300;
 
     let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
     check_pda(ctx, &pda).await;
 
-    // swap
-    let swap_ix = AmmInstruction::swap(
-        amount,
-        swap_pk,
+    // the SPL associated token account program reads the Rent sysvar from its accounts,
+    // so the account stays in the accounts of InitMarket
+    let mut init_ix = AmmInstruction::init_market(
+        amount_x,
+        amount_y,
         env.user_token_x_y_owner_and_payer.pubkey(),
-        env.user_token_x_pk,
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    assert_eq!(init_ix.accounts[12].pubkey, sysvar::rent::id());
+    init_ix.accounts.remove(12);
+
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let init_error = ctx.banks_client.process_transaction(init_tx).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        init_error,
+        TransactionError::InstructionError(0, InstructionError::NotEnoughAccountKeys)
+    );
+    check_pda(ctx, &pda).await;
+}
+
+#[tokio::test]
+async fn init_market_max_init_ratio() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1;
+    let amount_y = MAX_INIT_RATIO;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    Env::mint_token(
+        ctx,
+        &env.user_token_x_y_owner_and_payer,
+        &env.minter_y,
+        &env.user_token_y_pk,
+        &env.user_token_x_y_owner_and_payer,
+        MAX_INIT_RATIO,
+    ).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn init_market_extreme_init_ratio() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1;
+    let amount_y = MAX_INIT_RATIO + 1;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    Env::mint_token(
+        ctx,
+        &env.user_token_x_y_owner_and_payer,
+        &env.minter_y,
+        &env.user_token_y_pk,
+        &env.user_token_x_y_owner_and_payer,
+        MAX_INIT_RATIO + 1,
+    ).await;
+
+    let init_error = init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::ExtremeInitRatio
+    );
+    check_pda(ctx, &pda).await;
+}
+
+#[tokio::test]
+async fn init_market_allow_existing_fresh() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { allow_existing: true, ..InitMarketOptions::default() },
+    ).await.expect("init_market_with_options");
+
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn init_market_allow_existing_retry_after_partial() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    // partially initialized market: PDA token holders and an empty vault exist
+    let create_pda_token_x_ix = spl_associated_token_account::create_associated_token_account(
+        &env.user_token_x_y_owner_and_payer.pubkey(),
+        &pda.pda_owner_token_x.0,
+        &env.minter_x.pubkey(),
+    );
+    let create_pda_token_y_ix = spl_associated_token_account::create_associated_token_account(
+        &env.user_token_x_y_owner_and_payer.pubkey(),
+        &pda.pda_owner_token_y.0,
+        &env.minter_y.pubkey(),
+    );
+    let create_pda_token_x_y_tx = Transaction::new_signed_with_payer(
+        &[create_pda_token_x_ix, create_pda_token_y_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(create_pda_token_x_y_tx)
+        .await
+        .expect("create_pda_token_x_y_tx");
+
+    let mut vault_data = vec![0; Vault::LEN];
+    Vault::default().save(&mut vault_data).expect("save vault");
+    let rent = ctx.banks_client.get_rent().await.expect("rent");
+    let vault_acc = SolanaAccount {
+        lamports: rent.minimum_balance(vault_data.len()),
+        data: vault_data,
+        owner: amm::id(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    ctx.set_account(&pda.vault.0, &AccountSharedData::from(vault_acc));
+
+    let init_error = init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::AlreadyInUse
+    );
+
+    init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { allow_existing: true, ..InitMarketOptions::default() },
+    ).await.expect("init_market_with_options");
+
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn init_market_pda_token_created_before() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    // front-run: the PDA SPL token holders are created after the client saw them missing
+    let create_pda_token_x_ix = spl_associated_token_account::create_associated_token_account(
+        &ctx.payer.pubkey(),
+        &pda.pda_owner_token_x.0,
+        &env.minter_x.pubkey(),
+    );
+    let create_pda_token_y_ix = spl_associated_token_account::create_associated_token_account(
+        &ctx.payer.pubkey(),
+        &pda.pda_owner_token_y.0,
+        &env.minter_y.pubkey(),
+    );
+    let create_pda_token_x_y_tx = Transaction::new_signed_with_payer(
+        &[create_pda_token_x_ix, create_pda_token_y_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(create_pda_token_x_y_tx)
+        .await
+        .expect("create_pda_token_x_y_tx");
+
+    // a funded holder is not taken over
+    let transfer_ix = spl_token::instruction::transfer(
+        &spl_token::id(),
+        &env.user_token_y_pk,
+        &pda.pda_token_y_pk,
+        &user.pubkey(),
+        &[],
+        1,
+    ).expect("transfer_ix");
+    let transfer_tx = Transaction::new_signed_with_payer(
+        &[transfer_ix],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(transfer_tx).await.expect("transfer_tx");
+
+    let init_error = init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        // not a duplicate of the transaction reusing the holders
+        amount_y + 1,
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::AlreadyInUse
+    );
+
+    // the donated token is taken back, so the holders are empty again
+    let mut pda_token_y_acc = ctx.banks_client.get_account(pda.pda_token_y_pk)
+        .await
+        .expect("pda_token_y_acc")
+        .expect("pda_token_y_acc");
+    let mut pda_token_y = Account::unpack(&pda_token_y_acc.data).expect("pda_token_y");
+    pda_token_y.amount = 0;
+    Account::pack(pda_token_y, &mut pda_token_y_acc.data).expect("pack pda_token_y");
+    ctx.set_account(&pda.pda_token_y_pk, &AccountSharedData::from(pda_token_y_acc));
+
+    // empty holders are reused
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn init_market_allow_existing_funded() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let init_error = init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { allow_existing: true, ..InitMarketOptions::default() },
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::AlreadyInUse
+    );
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn init_market_allow_existing_not_authority() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+    let authority = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        authority,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    drain_market(ctx, &pda).await;
+
+    // a third party with own tokens doesn't take over the empty market
+    let (intruder, intruder_token_x_pk, intruder_token_y_pk) = new_user(
+        ctx, &env.minter_x, &env.minter_y, authority, amount_x, amount_y,
+    ).await;
+    let init_error = init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &intruder,
+        &intruder_token_x_pk,
+        &intruder_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions {
+            allow_existing: true,
+            authority: Some(intruder.pubkey()),
+            ..InitMarketOptions::default()
+        },
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::Unauthorized
+    );
+
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault.authority, authority.pubkey());
+    assert_eq!((vault.token_x_amount, vault.token_y_amount), (0, 0));
+}
+
+#[tokio::test]
+async fn init_market_allow_existing_reseed_drained() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+    let reseed_x = 200;
+    let reseed_y = 200;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    let fee_exempt = Pubkey::new_unique();
+    let set_fee_exempt_tx = Transaction::new_signed_with_payer(
+        &[AmmInstruction::set_fee_exempt(
+            Some(fee_exempt),
+            env.user_token_x_y_owner_and_payer.pubkey(),
+            env.minter_x.pubkey(),
+            env.minter_y.pubkey(),
+        )],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(set_fee_exempt_tx).await.expect("set_fee_exempt_tx");
+
+    drain_market(ctx, &pda).await;
+
+    let init_error = init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        reseed_x,
+        reseed_y,
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::AlreadyInUse
+    );
+
+    // only the authority re-seeds the market
+    let (intruder, intruder_token_x_pk, intruder_token_y_pk) = new_user(
+        ctx, &env.minter_x, &env.minter_y, &env.user_token_x_y_owner_and_payer, reseed_x, reseed_y,
+    ).await;
+    let init_error = init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &intruder,
+        &intruder_token_x_pk,
+        &intruder_token_y_pk,
+        reseed_x,
+        reseed_y,
+        InitMarketOptions { allow_existing: true, ..InitMarketOptions::default() },
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::Unauthorized
+    );
+
+    // re-seeded at its own ratio like a new market, the configuration is kept
+    init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        reseed_x,
+        reseed_y,
+        InitMarketOptions { allow_existing: true, ..InitMarketOptions::default() },
+    ).await.expect("init_market_with_options");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, reseed_x, reseed_y).await;
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault.authority, env.user_token_x_y_owner_and_payer.pubkey());
+    assert_eq!(vault.fee_exempt, Some(fee_exempt));
+
+    swap(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        &pda,
+        &env.minter_x.pubkey(),
+        50,
+    ).await;
+}
+
+#[tokio::test]
+async fn init_market_standard_associated_token_program() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    let init_ix = AmmInstruction::init_market(
+        amount_x,
+        amount_y,
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    assert_eq!(init_ix.accounts[15].pubkey, spl_associated_token_account::id());
+
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(init_tx).await.expect("init_tx");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn init_market_unknown_associated_token_program() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    let mut init_ix = AmmInstruction::init_market(
+        amount_x,
+        amount_y,
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    // an executable program, but not the associated token account program
+    init_ix.accounts[15].pubkey = spl_token::id();
+
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let init_error = ctx.banks_client.process_transaction(init_tx).await
+        .expect_err("init_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::IncorrectAssociatedTokenProgram
+    );
+    check_pda(ctx, &pda).await;
+}
+
+
+// Test swap
+
+async fn swap(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    user_token_x_y_owner_and_payer: &Keypair,
+    user_token_x_pk: &Pubkey,
+    user_token_y_pk: &Pubkey,
+    pda: &Pda,
+    swap: &Pubkey,
+    amount: u64,
+) {
+    let swap_direction = SwapDirection::new(swap, &minter_x.pubkey(), &minter_y.pubkey()).expect("swap_direction");
+    let before_swap = PoolSnapshot::capture_with_user(ctx, pda, user_token_x_pk, user_token_y_pk).await;
+
+    let swap_ix = AmmInstruction::swap(
+        amount,
+        *swap,
+        user_token_x_y_owner_and_payer.pubkey(),
+        *user_token_x_pk,
+        *user_token_y_pk,
+        minter_x.pubkey(),
+        minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&user_token_x_y_owner_and_payer.pubkey()),
+        &[user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let after_swap = PoolSnapshot::capture_with_user(ctx, pda, user_token_x_pk, user_token_y_pk).await;
+    let changes = before_swap.diff(&after_swap);
+    for field in ["vault.token_x_amount", "vault.token_y_amount", "pda_token_x.amount", "pda_token_y.amount"] {
+        let field_changed = changes.iter().any(|change| change.starts_with(&format!("{}:", field)));
+        assert!(field_changed, "{} unchanged: {:?}", field, changes);
+    }
+    assert!(!changes.iter().any(|change| change.starts_with("vault.minter_")), "{:?}", changes);
+
+    assert_eq!(before_swap.invariant(), before_swap.vault_invariant());
+    assert!(invariant_ok(before_swap.invariant(), after_swap.invariant(), 0));
+    assert_eq!(after_swap.invariant(), after_swap.vault_invariant());
+
+    let (take_user_token_amount, return_pda_token_amount, swap_result) = match swap_direction {
+        SwapDirection::XtoY => (
+            (before_swap.user_token_x_amount, after_swap.user_token_x_amount),
+            (before_swap.pda_token_y.amount, after_swap.pda_token_y.amount),
+            calc_swap(amount, before_swap.pda_token_x.amount, before_swap.pda_token_y.amount),
+        ),
+        SwapDirection::YtoX => (
+            (before_swap.user_token_y_amount, after_swap.user_token_y_amount),
+            (before_swap.pda_token_x.amount, after_swap.pda_token_x.amount),
+            calc_swap(amount, before_swap.pda_token_y.amount, before_swap.pda_token_x.amount),
+        ),
+    };
+    let swap_result = swap_result.expect("swap_result");
+
+    let (take_user_token_before_swap, take_user_token_after_swap) = take_user_token_amount;
+    assert_eq!(
+        take_user_token_before_swap.expect("take_user_token_before_swap"),
+        take_user_token_after_swap.expect("take_user_token_after_swap") + swap_result.take_amount
+    );
+    let (return_pda_token_before_swap, return_pda_token_after_swap) = return_pda_token_amount;
+    assert_eq!(return_pda_token_before_swap, return_pda_token_after_swap + swap_result.return_amount);
+}
+
+
+#[tokio::test]
+async fn swap_x_to_y() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let swap_pk = &env.minter_x.pubkey();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    swap(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        &pda,
+        swap_pk,
+        100,
+    ).await;
+}
+
+
+#[tokio::test]
+async fn swap_y_to_x() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let swap_pk = &env.minter_y.pubkey();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    swap(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        &pda,
+        swap_pk,
+        100,
+    ).await;
+}
+
+#[tokio::test]
+async fn swap_x_to_y_revert_amount() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 300;
+    let amount_y = 500;
+    let swap_pk = &env.minter_x.pubkey();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    swap(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        &pda,
+        swap_pk,
+        100,
+    ).await;
+}
+
+#[tokio::test]
+async fn swap_y_to_x_revert_amount() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 300;
+    let amount_y = 500;
+    let swap_pk = &env.minter_y.pubkey();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    swap(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        &pda,
+        swap_pk,
+        100,
+    ).await;
+}
+
+#[tokio::test]
+async fn swap_x_to_y_to_destination() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let amount = 100;
+    let swap_pk = env.minter_x.pubkey();
+    let destination_owner = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    let destination_token_y_pk = spl_associated_token_account::get_associated_token_address(
+        &destination_owner.pubkey(), &env.minter_y.pubkey(),
+    );
+    let create_destination_token_y_ix = spl_associated_token_account::create_associated_token_account(
+        &env.user_token_x_y_owner_and_payer.pubkey(),
+        &destination_owner.pubkey(),
+        &env.minter_y.pubkey(),
+    );
+    let create_destination_token_y_tx = Transaction::new_signed_with_payer(
+        &[create_destination_token_y_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client
+        .process_transaction(create_destination_token_y_tx)
+        .await
+        .expect("create_destination_token_y_tx");
+
+    let user_token_x_acc_before_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_x_pk)
+        .await
+        .expect("user_token_x_acc_before_swap");
+    let user_token_y_acc_before_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_y_pk)
+        .await
+        .expect("user_token_y_acc_before_swap");
+
+    let swap_ix = AmmInstruction::swap_to(
+        amount,
+        swap_pk,
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+        destination_token_y_pk,
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap(amount, amount_x, amount_y).expect("swap_result");
+
+    let user_token_x_acc_after_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_x_pk)
+        .await
+        .expect("user_token_x_acc_after_swap");
+    let user_token_y_acc_after_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_y_pk)
+        .await
+        .expect("user_token_y_acc_after_swap");
+    let destination_token_y_acc_after_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(destination_token_y_pk)
+        .await
+        .expect("destination_token_y_acc_after_swap");
+
+    assert_eq!(
+        user_token_x_acc_before_swap.amount,
+        user_token_x_acc_after_swap.amount + swap_result.take_amount
+    );
+    assert_eq!(user_token_y_acc_before_swap.amount, user_token_y_acc_after_swap.amount);
+    assert_eq!(destination_token_y_acc_after_swap.amount, swap_result.return_amount);
+
+    let vault_after_swap = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_after_swap.token_x_amount, amount_x + swap_result.take_amount);
+    assert_eq!(vault_after_swap.token_y_amount, amount_y - swap_result.return_amount);
+}
+
+#[cfg(feature = "strict-accounts")]
+#[tokio::test]
+async fn strict_accounts_extra_account() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let amount = 100;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    let mut init_ix = AmmInstruction::init_market(
+        amount_x,
+        amount_y,
+        user.pubkey(),
+        user.pubkey(),
+        user.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    init_ix.accounts.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    let init_error = ctx.banks_client.process_transaction(init_tx).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::UnexpectedAccounts
+    );
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // the destination is an optional account of the swap, the account past it is not
+    let mut swap_ix = AmmInstruction::swap_to(
+        amount,
+        env.minter_x.pubkey(),
+        user.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+        env.user_token_y_pk,
+    );
+    swap_ix.accounts.push(AccountMeta::new_readonly(Pubkey::new_unique(), false));
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::UnexpectedAccounts
+    );
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn swap_rounding_audit() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000_000;
+    let amount_y = 3_000_000;
+    let steps = 1000;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    Env::mint_token(
+        ctx,
+        &env.user_token_x_y_owner_and_payer,
+        &env.minter_x,
+        &env.user_token_x_pk,
+        &env.user_token_x_y_owner_and_payer,
+        10_000_000,
+    ).await;
+    Env::mint_token(
+        ctx,
+        &env.user_token_x_y_owner_and_payer,
+        &env.minter_y,
+        &env.user_token_y_pk,
+        &env.user_token_x_y_owner_and_payer,
+        10_000_000,
+    ).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    let mut invariant_before_step = amount_x as u128 * amount_y as u128;
+    for step in 0..steps {
+        // every amount is unique, so transactions with the same blockhash are not duplicated
+        let amount = 10 + step;
+        // the blockhash of the context expires during the long run
+        let recent_blockhash = ctx.banks_client
+            .get_recent_blockhash()
+            .await
+            .expect("recent_blockhash");
+        let swap_pk = if step % 2 == 0 {
+            env.minter_x.pubkey()
+        } else {
+            env.minter_y.pubkey()
+        };
+
+        let swap_ix = AmmInstruction::swap(
+            amount,
+            swap_pk,
+            env.user_token_x_y_owner_and_payer.pubkey(),
+            env.user_token_x_pk,
+            env.user_token_y_pk,
+            env.minter_x.pubkey(),
+            env.minter_y.pubkey(),
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+            &[&env.user_token_x_y_owner_and_payer],
+            recent_blockhash,
+        );
+        if let Err(e) = ctx.banks_client.process_transaction(swap_tx).await {
+            panic!("step {}: swap_tx failed: {:?}", step, e);
+        }
+
+        let pda_token_x_acc = ctx.banks_client
+            .get_packed_account_data::<Account>(pda.pda_token_x_pk)
+            .await
+            .expect("pda_token_x_acc");
+        let pda_token_y_acc = ctx.banks_client
+            .get_packed_account_data::<Account>(pda.pda_token_y_pk)
+            .await
+            .expect("pda_token_y_acc");
+        let vault = get_vault(ctx, pda.vault.0).await;
+
+        assert_eq!(
+            vault.token_x_amount, pda_token_x_acc.amount,
+            "step {}: vault token X is out of sync with pda token X account", step
+        );
+        assert_eq!(
+            vault.token_y_amount, pda_token_y_acc.amount,
+            "step {}: vault token Y is out of sync with pda token Y account", step
+        );
+
+        let invariant_after_step = pda_token_x_acc.amount as u128 * pda_token_y_acc.amount as u128;
+        assert!(
+            invariant_ok(invariant_before_step, invariant_after_step, 0),
+            "step {}: invariant decreased from {} to {}", step, invariant_before_step, invariant_after_step
+        );
+        invariant_before_step = invariant_after_step;
+    }
+}
+
+#[tokio::test]
+async fn swap_x_to_wrapped_sol_unwrap() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000_000;
+    let amount = 500;
+    let user = &env.user_token_x_y_owner_and_payer;
+    let native_minter_pk = spl_token::native_mint::id();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &native_minter_pk);
+    check_pda(ctx, &pda).await;
+
+    // wrap SOL
+    let user_token_native_pk = spl_associated_token_account::get_associated_token_address(
+        &user.pubkey(), &native_minter_pk,
+    );
+    let create_user_token_native_ix = spl_associated_token_account::create_associated_token_account(
+        &user.pubkey(),
+        &user.pubkey(),
+        &native_minter_pk,
+    );
+    let wrap_ix = system_instruction::transfer(&user.pubkey(), &user_token_native_pk, amount_y);
+    let sync_native_ix = spl_token::instruction::sync_native(&spl_token::id(), &user_token_native_pk)
+        .expect("sync_native_ix");
+    let wrap_tx = Transaction::new_signed_with_payer(
+        &[create_user_token_native_ix, wrap_ix, sync_native_ix],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(wrap_tx).await.expect("wrap_tx");
+
+    let init_ix = AmmInstruction::init_market(
+        amount_x,
+        amount_y,
+        user.pubkey(),
+        user.pubkey(),
+        user.pubkey(),
+        env.user_token_x_pk,
+        user_token_native_pk,
+        env.minter_x.pubkey(),
+        native_minter_pk,
+    );
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(init_tx).await.expect("init_tx");
+
+    let user_lamports_before_swap = ctx.banks_client
+        .get_balance(user.pubkey())
+        .await
+        .expect("user_lamports_before_swap");
+    let user_token_native_lamports_before_swap = ctx.banks_client
+        .get_balance(user_token_native_pk)
+        .await
+        .expect("user_token_native_lamports_before_swap");
+
+    let swap_ix = AmmInstruction::swap_with_options(
+        amount,
+        env.minter_x.pubkey(),
+        user.pubkey(),
+        env.user_token_x_pk,
+        user_token_native_pk,
+        env.minter_x.pubkey(),
+        native_minter_pk,
+        SwapOptions { unwrap: true, ..SwapOptions::default() },
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap(amount, amount_x, amount_y).expect("swap_result");
+
+    let user_lamports_after_swap = ctx.banks_client
+        .get_balance(user.pubkey())
+        .await
+        .expect("user_lamports_after_swap");
+    let user_token_native_acc_after_swap = ctx.banks_client
+        .get_account(user_token_native_pk)
+        .await
+        .expect("user_token_native_acc_after_swap");
+
+    assert_eq!(user_token_native_acc_after_swap, None);
+    assert_eq!(
+        user_lamports_after_swap,
+        user_lamports_before_swap + user_token_native_lamports_before_swap + swap_result.return_amount
+    );
+}
+
+#[tokio::test]
+async fn swap_unwrap_not_native() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let swap_ix = AmmInstruction::swap_with_options(
+        100,
+        env.minter_x.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+        SwapOptions { unwrap: true, ..SwapOptions::default() },
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::UnwrapNotNative
+    );
+}
+
+#[tokio::test]
+async fn swap_aliased_user_token_accounts() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let swap_ix = AmmInstruction::swap(
+        100,
+        env.minter_x.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_x_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::AliasedAccounts
+    );
+}
+
+#[tokio::test]
+async fn swap_with_relayer_fee_payer() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let amount = 100;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let user_lamports_before_swap = ctx.banks_client.get_balance(user.pubkey())
+        .await
+        .expect("user_lamports_before_swap");
+    let user_token_x_before_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_x_pk)
+        .await
+        .expect("user_token_x_before_swap");
+    let user_token_y_before_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_y_pk)
+        .await
+        .expect("user_token_y_before_swap");
+
+    // the relayer pays the transaction fee, the user signs for the tokens
+    let swap_ix = AmmInstruction::swap(
+        amount,
+        env.minter_x.pubkey(),
+        user.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap(amount, amount_x, amount_y).expect("swap_result");
+    let user_lamports_after_swap = ctx.banks_client.get_balance(user.pubkey())
+        .await
+        .expect("user_lamports_after_swap");
+    assert_eq!(user_lamports_after_swap, user_lamports_before_swap);
+    let user_token_x_after_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_x_pk)
+        .await
+        .expect("user_token_x_after_swap");
+    let user_token_y_after_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_y_pk)
+        .await
+        .expect("user_token_y_after_swap");
+    assert_eq!(user_token_x_after_swap.amount, user_token_x_before_swap.amount - swap_result.take_amount);
+    assert_eq!(user_token_y_after_swap.amount, user_token_y_before_swap.amount + swap_result.return_amount);
+}
+
+async fn swap_with_price_limit(
+    env: &mut Env,
+    minter_pk: Pubkey,
+    amount: u64,
+    price_limit: u128,
+) -> Result<(), TransportError> {
+    let swap_ix = AmmInstruction::swap_with_options(
+        amount,
+        minter_pk,
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+        SwapOptions { price_limit: Some(price_limit), ..SwapOptions::default() },
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(swap_tx).await
+}
+
+async fn price_after_swap(env: &mut Env, minter_pk: Pubkey, amount: u64) -> u128 {
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    let vault = get_vault(&mut env.ctx, pda.vault.0).await;
+    let minter_x = env.ctx.banks_client.get_packed_account_data::<Mint>(env.minter_x.pubkey())
+        .await
+        .expect("minter_x");
+    let minter_y = env.ctx.banks_client.get_packed_account_data::<Mint>(env.minter_y.pubkey())
+        .await
+        .expect("minter_y");
+    let (reserve_x, reserve_y) = match SwapDirection::new(&minter_pk, &env.minter_x.pubkey(), &env.minter_y.pubkey()) {
+        Some(SwapDirection::XtoY) => {
+            let swap_result = calc_swap(amount, vault.token_x_amount, vault.token_y_amount).expect("swap_result");
+            (vault.token_x_amount + swap_result.take_amount, vault.token_y_amount - swap_result.return_amount)
+        }
+        Some(SwapDirection::YtoX) => {
+            let swap_result = calc_swap(amount, vault.token_y_amount, vault.token_x_amount).expect("swap_result");
+            (vault.token_x_amount - swap_result.return_amount, vault.token_y_amount + swap_result.take_amount)
+        }
+        None => panic!("swap_direction"),
+    };
+    normalized_price(reserve_x, reserve_y, minter_x.decimals, minter_y.decimals).expect("price")
+}
+
+#[tokio::test]
+async fn swap_cross_decimals() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let user = &env.user_token_x_y_owner_and_payer;
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+
+    let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let decimals_x = ctx.banks_client.get_packed_account_data::<Mint>(minter_x_pk)
+        .await
+        .expect("minter_x")
+        .decimals;
+    let decimals_y = ctx.banks_client.get_packed_account_data::<Mint>(minter_y_pk)
+        .await
+        .expect("minter_y")
+        .decimals;
+    assert_eq!((decimals_x, decimals_y), (5, 9));
+
+    // raw price is 3 Y per X, but 1 X = 10^5 raw X and 1 Y = 10^9 raw Y,
+    // so 1 X costs 3 * 10^5 / 10^9 = 0.0003 Y
+    let scale = 10u128.pow((decimals_y - decimals_x) as u32);
+    assert_eq!(
+        normalized_price(amount_x, amount_y, decimals_x, decimals_y),
+        Some((3 << 64) / scale)
+    );
+
+    let (mut reserve_x, mut reserve_y) = (amount_x, amount_y);
+    for (minter_pk, amount) in [(minter_x_pk, 500), (minter_y_pk, 700)] {
+        let user_token_x_before = ctx.banks_client.get_packed_account_data::<Account>(env.user_token_x_pk)
+            .await
+            .expect("user_token_x_before");
+        let user_token_y_before = ctx.banks_client.get_packed_account_data::<Account>(env.user_token_y_pk)
+            .await
+            .expect("user_token_y_before");
+
+        let swap_ix = AmmInstruction::swap(
+            amount,
+            minter_pk,
+            user.pubkey(),
+            env.user_token_x_pk,
+            env.user_token_y_pk,
+            minter_x_pk,
+            minter_y_pk,
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&user.pubkey()),
+            &[user],
+            ctx.last_blockhash,
+        );
+        ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+        let user_token_x_after = ctx.banks_client.get_packed_account_data::<Account>(env.user_token_x_pk)
+            .await
+            .expect("user_token_x_after");
+        let user_token_y_after = ctx.banks_client.get_packed_account_data::<Account>(env.user_token_y_pk)
+            .await
+            .expect("user_token_y_after");
+
+        // the raw amounts don't depend on the decimals
+        if minter_pk == minter_x_pk {
+            let swap_result = calc_swap(amount, reserve_x, reserve_y).expect("swap_result");
+            assert_eq!(user_token_x_before.amount - user_token_x_after.amount, swap_result.take_amount);
+            assert_eq!(user_token_y_after.amount - user_token_y_before.amount, swap_result.return_amount);
+            reserve_x += swap_result.take_amount;
+            reserve_y -= swap_result.return_amount;
+        } else {
+            let swap_result = calc_swap(amount, reserve_y, reserve_x).expect("swap_result");
+            assert_eq!(user_token_y_before.amount - user_token_y_after.amount, swap_result.take_amount);
+            assert_eq!(user_token_x_after.amount - user_token_x_before.amount, swap_result.return_amount);
+            reserve_x -= swap_result.return_amount;
+            reserve_y += swap_result.take_amount;
+        }
+
+        let vault = get_vault(ctx, pda.vault.0).await;
+        assert_eq!((vault.token_x_amount, vault.token_y_amount), (reserve_x, reserve_y));
+        assert_eq!(
+            vault.spot_price_with_decimals(decimals_x, decimals_y),
+            Some((((reserve_y as u128) << 64) / reserve_x as u128) / scale)
+        );
+    }
+}
+
+#[cfg(feature = "compute-budget")]
+#[tokio::test]
+async fn swap_with_compute_budget() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let amount = 100;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let client = AmmClient::new(env.minter_x.pubkey(), env.minter_y.pubkey(), user.pubkey())
+        .with_user_tokens(env.user_token_x_pk, env.user_token_y_pk);
+    let swap_ixs = client.swap(amount, env.minter_x.pubkey()).with_compute_budget(300_000);
+    let swap_tx = Transaction::new_signed_with_payer(
+        &swap_ixs,
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap(amount, amount_x, amount_y).expect("swap_result");
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault.token_x_amount, amount_x + swap_result.take_amount);
+    assert_eq!(vault.token_y_amount, amount_y - swap_result.return_amount);
+}
+
+#[tokio::test]
+async fn swap_x_to_y_price_limit() {
+    let mut env = Env::new().await;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let amount = 100;
+    let minter_pk = env.minter_x.pubkey();
+
+    init_market(
+        &mut env.ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let price = price_after_swap(&mut env, minter_pk, amount).await;
+
+    let swap_error = swap_with_price_limit(&mut env, minter_pk, amount, price + 1).await
+        .expect_err("swap_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::PriceLimitExceeded
+    );
+
+    swap_with_price_limit(&mut env, minter_pk, amount, price).await.expect("swap");
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    let swap_result = calc_swap(amount, amount_x, amount_y).expect("swap_result");
+    assert_eq!(get_vault(&mut env.ctx, pda.vault.0).await.token_x_amount, amount_x + swap_result.take_amount);
+}
+
+#[tokio::test]
+async fn swap_y_to_x_price_limit() {
+    let mut env = Env::new().await;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let amount = 300;
+    let minter_pk = env.minter_y.pubkey();
+
+    init_market(
+        &mut env.ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let price = price_after_swap(&mut env, minter_pk, amount).await;
+
+    let swap_error = swap_with_price_limit(&mut env, minter_pk, amount, price - 1).await
+        .expect_err("swap_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::PriceLimitExceeded
+    );
+
+    swap_with_price_limit(&mut env, minter_pk, amount, price).await.expect("swap");
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    let swap_result = calc_swap(amount, amount_y, amount_x).expect("swap_result");
+    assert_eq!(get_vault(&mut env.ctx, pda.vault.0).await.token_y_amount, amount_y + swap_result.take_amount);
+}
+
+async fn swap_with_max_impact(
+    env: &mut Env,
+    minter_pk: Pubkey,
+    amount: u64,
+    max_impact_bps: u16,
+) -> Result<(), TransportError> {
+    let swap_ix = AmmInstruction::swap_with_options(
+        amount,
+        minter_pk,
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+        SwapOptions { max_impact_bps, ..SwapOptions::default() },
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        env.ctx.last_blockhash,
+    );
+    env.ctx.banks_client.process_transaction(swap_tx).await
+}
+
+#[tokio::test]
+async fn swap_unrelated_minter() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let user = &env.user_token_x_y_owner_and_payer;
+    let unrelated_minter_pk = Pubkey::new_unique();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        1_000,
+        3_000,
+    ).await.expect("init_market");
+
+    let swap_ix = AmmInstruction::swap(
+        100,
+        unrelated_minter_pk,
+        user.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    // the PDA accounts are never derived, so even the wrong ones fail with the swap minter
+    let mut bogus_pda_swap_ix = swap_ix.clone();
+    for account_meta in &mut bogus_pda_swap_ix.accounts[5..10] {
+        account_meta.pubkey = Pubkey::new_unique();
+    }
+
+    for ix in [swap_ix, bogus_pda_swap_ix] {
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&user.pubkey()),
+            &[user],
+            ctx.last_blockhash,
+        );
+        let swap_error = ctx.banks_client.process_transaction(swap_tx)
+            .await
+            .expect_err("swap_error")
+            .unwrap();
+        assert_eq!(
+            decode_error::<AmmError>(swap_error),
+            AmmError::IncorrectSwapPk
+        );
+    }
+
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!((vault.token_x_amount, vault.token_y_amount), (1_000, 3_000));
+}
+
+#[tokio::test]
+async fn swap_drain_to_near_zero() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1;
+    let amount_y = 3_000;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // X + dX = K, the largest swap leaves a single unit of Y
+    for (amount, expected_error) in [(2_999, None), (5, Some(AmmError::CalculatedZeroSwap))] {
+        let swap_ix = AmmInstruction::swap(
+            amount,
+            env.minter_x.pubkey(),
+            user.pubkey(),
+            env.user_token_x_pk,
+            env.user_token_y_pk,
+            env.minter_x.pubkey(),
+            env.minter_y.pubkey(),
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&user.pubkey()),
+            &[user],
+            ctx.last_blockhash,
+        );
+        let swap_result = ctx.banks_client.process_transaction(swap_tx).await;
+        match expected_error {
+            None => swap_result.expect("swap_tx"),
+            Some(expected_error) => assert_eq!(
+                decode_error::<AmmError>(swap_result.expect_err("swap_error").unwrap()),
+                expected_error
+            ),
+        }
+
+        let after_swap = PoolSnapshot::capture(ctx, &pda).await;
+        assert_eq!((after_swap.vault.token_x_amount, after_swap.vault.token_y_amount), (3_000, 1));
+        assert_eq!(after_swap.pda_token_y.amount, 1);
+    }
+}
+
+#[tokio::test]
+async fn swap_max_impact() {
+    let mut env = Env::new().await;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let minter_pk = env.minter_x.pubkey();
+    let max_impact_bps = 100;
+
+    init_market(
+        &mut env.ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // 10% of the reserve X moves the price by ~17%
+    let swap_error = swap_with_max_impact(&mut env, minter_pk, 100, max_impact_bps).await
+        .expect_err("swap_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::PriceImpactTooHigh
+    );
+
+    // 0.5% of the reserve X moves the price by ~1%
+    swap_with_max_impact(&mut env, minter_pk, 5, max_impact_bps).await.expect("swap");
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    let swap_result = calc_swap(5, amount_x, amount_y).expect("swap_result");
+    assert_eq!(get_vault(&mut env.ctx, pda.vault.0).await.token_x_amount, amount_x + swap_result.take_amount);
+}
+
+#[tokio::test]
+async fn swap_no_op() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 4_000;
+    let amount_y = 3;
+    let amount = 1;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let swap_ix = AmmInstruction::swap(
+        amount,
+        env.minter_x.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::NoOpSwap
+    );
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn swap_vault_not_writable() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let mut swap_ix = AmmInstruction::swap(
+        100,
+        env.minter_x.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    assert_eq!(swap_ix.accounts[9].pubkey, pda.vault.0);
+    swap_ix.accounts[9].is_writable = false;
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::VaultNotWritable
+    );
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn swap_reject_earlier_swap_in_tx() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let user_pk = env.user_token_x_y_owner_and_payer.pubkey();
+    let (user_token_x_pk, user_token_y_pk) = (env.user_token_x_pk, env.user_token_y_pk);
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+    let swap_ix = |amount, minter_pk, reject_earlier_swap_in_tx| AmmInstruction::swap_with_options(
+        amount,
+        minter_pk,
+        user_pk,
+        user_token_x_pk,
+        user_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
+        SwapOptions { reject_earlier_swap_in_tx, ..SwapOptions::default() },
+    );
+
+    // the second swap of the market in the transaction is rejected
+    let sandwich_tx = Transaction::new_signed_with_payer(
+        &[
+            swap_ix(100, minter_x_pk, false),
+            swap_ix(50, minter_y_pk, true),
+        ],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let sandwich_error = ctx.banks_client.process_transaction(sandwich_tx).await
+        .expect_err("sandwich_error")
+        .unwrap();
+    assert_eq!(
+        sandwich_error,
+        TransactionError::InstructionError(1, InstructionError::Custom(AmmError::DuplicateSwapInTx as u32))
+    );
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    // opt-in, the same swaps are allowed without the option
+    let swaps_tx = Transaction::new_signed_with_payer(
+        &[
+            swap_ix(100, minter_x_pk, false),
+            swap_ix(50, minter_y_pk, false),
+        ],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swaps_tx).await.expect("swaps_tx");
+
+    // the first swap of the market in the transaction passes
+    let single_swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(70, minter_x_pk, true)],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(single_swap_tx).await.expect("single_swap_tx");
+}
+
+#[tokio::test]
+async fn swap_create_return_token() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let amount = 100;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // the trader holds only token X
+    let trader = Keypair::new();
+    let trader_token_x_pk = spl_associated_token_account::get_associated_token_address(
+        &trader.pubkey(), &env.minter_x.pubkey(),
+    );
+    let trader_token_y_pk = spl_associated_token_account::get_associated_token_address(
+        &trader.pubkey(), &env.minter_y.pubkey(),
+    );
+    let fund_trader_tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::transfer(&ctx.payer.pubkey(), &trader.pubkey(), 1_000_000_000),
+            spl_associated_token_account::create_associated_token_account(
+                &ctx.payer.pubkey(),
+                &trader.pubkey(),
+                &env.minter_x.pubkey(),
+            ),
+        ],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(fund_trader_tx).await.expect("fund_trader_tx");
+    Env::mint_token(
+        ctx,
+        &env.user_token_x_y_owner_and_payer,
+        &env.minter_x,
+        &trader_token_x_pk,
+        &env.user_token_x_y_owner_and_payer,
+        amount,
+    ).await;
+    assert_eq!(ctx.banks_client.get_account(trader_token_y_pk).await.expect("trader_token_y_acc"), None);
+
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+    let swap_ix = |create_return_token| AmmInstruction::swap_with_options(
+        amount,
+        minter_x_pk,
+        trader.pubkey(),
+        trader_token_x_pk,
+        trader_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
+        SwapOptions { create_return_token, ..SwapOptions::default() },
+    );
+
+    // opt-in, the missing holder isn't created without the option
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(false)],
+        Some(&trader.pubkey()),
+        &[&trader],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect_err("swap_without_return_token");
+
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(true)],
+        Some(&trader.pubkey()),
+        &[&trader],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap(amount, amount_x, amount_y).expect("swap_result");
+    let trader_token_x = ctx.banks_client
+        .get_packed_account_data::<Account>(trader_token_x_pk)
+        .await
+        .expect("trader_token_x");
+    assert_eq!(trader_token_x.amount, amount - swap_result.take_amount);
+    let trader_token_y = ctx.banks_client
+        .get_packed_account_data::<Account>(trader_token_y_pk)
+        .await
+        .expect("trader_token_y");
+    assert_eq!(trader_token_y.owner, trader.pubkey());
+    assert_eq!(trader_token_y.mint, env.minter_y.pubkey());
+    assert_eq!(trader_token_y.amount, swap_result.return_amount);
+}
+
+#[tokio::test]
+async fn swap_create_return_token_by_payer() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let amount = 100;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // the trader holds only token X and no lamports for the rent
+    let trader = Keypair::new();
+    let trader_token_x_pk = spl_associated_token_account::get_associated_token_address(
+        &trader.pubkey(), &env.minter_x.pubkey(),
+    );
+    let trader_token_y_pk = spl_associated_token_account::get_associated_token_address(
+        &trader.pubkey(), &env.minter_y.pubkey(),
+    );
+    let create_trader_token_x_tx = Transaction::new_signed_with_payer(
+        &[
+            spl_associated_token_account::create_associated_token_account(
+                &ctx.payer.pubkey(),
+                &trader.pubkey(),
+                &env.minter_x.pubkey(),
+            ),
+        ],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(create_trader_token_x_tx).await.expect("create_trader_token_x_tx");
+    Env::mint_token(
+        ctx,
+        &env.user_token_x_y_owner_and_payer,
+        &env.minter_x,
+        &trader_token_x_pk,
+        &env.user_token_x_y_owner_and_payer,
+        amount,
+    ).await;
+
+    let payer_pk = ctx.payer.pubkey();
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+    let swap_ix = |return_token_payer| AmmInstruction::swap_with_options(
+        amount,
+        minter_x_pk,
+        trader.pubkey(),
+        trader_token_x_pk,
+        trader_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
+        SwapOptions { create_return_token: true, return_token_payer, ..SwapOptions::default() },
+    );
+
+    // by default the trader pays the rent of the created holder
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(None)],
+        Some(&payer_pk),
+        &[&ctx.payer, &trader],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect_err("swap_without_rent");
+    assert_eq!(ctx.banks_client.get_account(trader_token_y_pk).await.expect("trader_token_y_acc"), None);
+
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(Some(payer_pk))],
+        Some(&payer_pk),
+        &[&ctx.payer, &trader],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap(amount, amount_x, amount_y).expect("swap_result");
+    let trader_token_y = ctx.banks_client
+        .get_packed_account_data::<Account>(trader_token_y_pk)
+        .await
+        .expect("trader_token_y");
+    assert_eq!(trader_token_y.owner, trader.pubkey());
+    assert_eq!(trader_token_y.amount, swap_result.return_amount);
+    assert_eq!(ctx.banks_client.get_balance(trader.pubkey()).await.expect("trader_balance"), 0);
+}
+
+#[tokio::test]
+async fn swap_transfer_checked() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // the transfers of the market pass the decimals of the minters
+    for (minter_pk, amount) in [(env.minter_x.pubkey(), 100), (env.minter_y.pubkey(), 50)] {
+        let swap_ix = AmmInstruction::swap(
+            amount,
+            minter_pk,
+            user.pubkey(),
+            env.user_token_x_pk,
+            env.user_token_y_pk,
+            env.minter_x.pubkey(),
+            env.minter_y.pubkey(),
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&user.pubkey()),
+            &[user],
+            ctx.last_blockhash,
+        );
+        ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+    }
+
+    // wrong decimals are rejected by the SPL token program
+    let minter_x = ctx.banks_client.get_packed_account_data::<Mint>(env.minter_x.pubkey())
+        .await
+        .expect("minter_x");
+    let transfer_ix = spl_token::instruction::transfer_checked(
+        &spl_token::id(),
+        &env.user_token_x_pk,
+        &env.minter_x.pubkey(),
+        &pda.pda_token_x_pk,
+        &user.pubkey(),
+        &[],
+        100,
+        minter_x.decimals + 1,
+    ).expect("transfer_ix");
+    let transfer_tx = Transaction::new_signed_with_payer(
+        &[transfer_ix],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    let transfer_error = ctx.banks_client.process_transaction(transfer_tx).await
+        .expect_err("transfer_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<TokenError>(transfer_error),
+        TokenError::MintDecimalsMismatch
+    );
+}
+
+#[tokio::test]
+async fn swap_output_granularity() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let granularity = 100;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let swaps = [
+        (env.minter_x.pubkey(), 100),
+        (env.minter_y.pubkey(), 700),
+        (env.minter_x.pubkey(), 333),
+        (env.minter_y.pubkey(), 1_234),
+    ];
+    for (minter_pk, amount) in swaps {
+        let swap_direction = SwapDirection::new(&minter_pk, &env.minter_x.pubkey(), &env.minter_y.pubkey())
+            .expect("swap_direction");
+        let return_token_pk = match swap_direction {
+            SwapDirection::XtoY => env.user_token_y_pk,
+            SwapDirection::YtoX => env.user_token_x_pk,
+        };
+        let vault_before_swap = get_vault(ctx, pda.vault.0).await;
+        let return_token_before_swap = ctx.banks_client.get_packed_account_data::<Account>(return_token_pk)
+            .await
+            .expect("return_token_before_swap");
+
+        let swap_ix = AmmInstruction::swap_with_options(
+            amount,
+            minter_pk,
+            env.user_token_x_y_owner_and_payer.pubkey(),
+            env.user_token_x_pk,
+            env.user_token_y_pk,
+            env.minter_x.pubkey(),
+            env.minter_y.pubkey(),
+            SwapOptions { output_granularity: granularity, ..SwapOptions::default() },
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+            &[&env.user_token_x_y_owner_and_payer],
+            ctx.last_blockhash,
+        );
+        ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+        let return_token_after_swap = ctx.banks_client.get_packed_account_data::<Account>(return_token_pk)
+            .await
+            .expect("return_token_after_swap");
+        let return_amount = return_token_after_swap.amount - return_token_before_swap.amount;
+        assert!(return_amount > 0);
+        assert_eq!(return_amount % granularity, 0);
+
+        let vault_after_swap = get_vault(ctx, pda.vault.0).await;
+        assert!(
+            vault_after_swap.token_x_amount as u128 * vault_after_swap.token_y_amount as u128 >=
+                vault_before_swap.token_x_amount as u128 * vault_before_swap.token_y_amount as u128
+        );
+    }
+}
+
+#[tokio::test]
+async fn swap_reserve_overflow() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    Env::mint_token(
+        ctx,
+        &env.user_token_x_y_owner_and_payer,
+        &env.minter_x,
+        &env.user_token_x_pk,
+        &env.user_token_x_y_owner_and_payer,
+        MAX_RESERVE,
+    ).await;
+    Env::mint_token(
+        ctx,
+        &env.user_token_x_y_owner_and_payer,
+        &env.minter_y,
+        &env.user_token_y_pk,
+        &env.user_token_x_y_owner_and_payer,
+        MAX_RESERVE,
+    ).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        MAX_RESERVE,
+        MAX_RESERVE,
+    ).await.expect("init_market");
+
+    let vault_before_swap = get_vault(ctx, pda.vault.0).await;
+    let user_token_x_before_swap = ctx.banks_client.get_packed_account_data::<Account>(env.user_token_x_pk)
+        .await
+        .expect("user_token_x_before_swap");
+    let user_token_y_before_swap = ctx.banks_client.get_packed_account_data::<Account>(env.user_token_y_pk)
+        .await
+        .expect("user_token_y_before_swap");
+
+    let swap_ix = AmmInstruction::swap(
+        u64::MAX - MAX_RESERVE + 1,
+        env.minter_x.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_tx_err = ctx.banks_client.process_transaction(swap_tx).await.unwrap_err().unwrap();
+    assert_eq!(decode_error::<AmmError>(swap_tx_err), AmmError::ReserveOverflow);
+
+    assert_eq!(get_vault(ctx, pda.vault.0).await, vault_before_swap);
+    let user_token_x_after_swap = ctx.banks_client.get_packed_account_data::<Account>(env.user_token_x_pk)
+        .await
+        .expect("user_token_x_after_swap");
+    let user_token_y_after_swap = ctx.banks_client.get_packed_account_data::<Account>(env.user_token_y_pk)
+        .await
+        .expect("user_token_y_after_swap");
+    assert_eq!(user_token_x_after_swap.amount, user_token_x_before_swap.amount);
+    assert_eq!(user_token_y_after_swap.amount, user_token_y_before_swap.amount);
+}
+
+#[tokio::test]
+async fn swap_mint_mismatch() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // vault recorded other minters than the passed self-consistent pair
+    let mut vault_acc = ctx.banks_client.get_account(pda.vault.0)
+        .await
+        .expect("vault_acc")
+        .expect("vault_acc");
+    let mut vault = get_vault(ctx, pda.vault.0).await;
+    vault.minter_x = Pubkey::new_unique();
+    vault.save(&mut vault_acc.data).expect("save vault");
+    ctx.set_account(&pda.vault.0, &AccountSharedData::from(vault_acc));
+
+    let swap_ix = AmmInstruction::swap(
+        100,
+        env.minter_x.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::MintMismatch
+    );
+}
+
+#[tokio::test]
+async fn swap_without_inited_market() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let swap_pk = env.minter_y.pubkey();
+    let amount = 100;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    // swap
+    let swap_ix = AmmInstruction::swap(
+        amount,
+        swap_pk,
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::MarketNotInitialized
+    );
+}
+
+#[tokio::test]
+async fn swap_corrupt_vault() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 500;
+    let amount_y = 300;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // not empty, but can't be deserialized
+    let mut vault_acc = ctx.banks_client.get_account(pda.vault.0)
+        .await
+        .expect("vault_acc")
+        .expect("vault_acc");
+    vault_acc.data.truncate(Vault::LEN - 1);
+    ctx.set_account(&pda.vault.0, &AccountSharedData::from(vault_acc));
+
+    let swap_ix = AmmInstruction::swap(
+        100,
+        env.minter_x.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::InvalidVault
+    );
+}
+
+#[tokio::test]
+async fn swap_zero_amount() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let swap_pk = env.minter_y.pubkey();
+    let amount = 0;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    // swap
+    let swap_ix = AmmInstruction::swap(
+        amount,
+        swap_pk,
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
+
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::AmountZero
+    );
+}
+
+
+
+// Test authority
+
+async fn propose_authority(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    authority: &Keypair,
+    new_authority: &Pubkey,
+) -> Result<(), TransportError> {
+    let propose_ix = AmmInstruction::propose_authority(
+        *new_authority,
+        authority.pubkey(),
+        minter_x.pubkey(),
+        minter_y.pubkey(),
+    );
+    let propose_tx = Transaction::new_signed_with_payer(
+        &[propose_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(propose_tx).await
+}
+
+async fn accept_authority(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    new_authority: &Keypair,
+) -> Result<(), TransportError> {
+    let accept_ix = AmmInstruction::accept_authority(
+        new_authority.pubkey(),
+        minter_x.pubkey(),
+        minter_y.pubkey(),
+    );
+    let accept_tx = Transaction::new_signed_with_payer(
+        &[accept_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, new_authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(accept_tx).await
+}
+
+#[tokio::test]
+async fn propose_and_accept_authority() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+    let new_authority = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    let vault_after_init = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_after_init.authority, env.user_token_x_y_owner_and_payer.pubkey());
+    assert_eq!(vault_after_init.pending_authority, None);
+
+    propose_authority(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &new_authority.pubkey(),
+    ).await.expect("propose_authority");
+    let vault_after_propose = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_after_propose.authority, env.user_token_x_y_owner_and_payer.pubkey());
+    assert_eq!(vault_after_propose.pending_authority, Some(new_authority.pubkey()));
+
+    accept_authority(ctx, &env.minter_x, &env.minter_y, &new_authority)
+        .await
+        .expect("accept_authority");
+    let vault_after_accept = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_after_accept.authority, new_authority.pubkey());
+    assert_eq!(vault_after_accept.pending_authority, None);
+    assert_eq!(vault_after_accept.token_x_amount, amount_x);
+    assert_eq!(vault_after_accept.token_y_amount, amount_y);
+}
+
+#[tokio::test]
+async fn accept_authority_wrong_signer() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 100;
+    let amount_y = 300;
+    let new_authority = Keypair::new();
+    let wrong_authority = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let accept_error = accept_authority(ctx, &env.minter_x, &env.minter_y, &new_authority)
+        .await
+        .expect_err("accept_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(accept_error),
+        AmmError::NoPendingAuthority
+    );
+
+    propose_authority(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &new_authority.pubkey(),
+    ).await.expect("propose_authority");
+
+    let accept_error = accept_authority(ctx, &env.minter_x, &env.minter_y, &wrong_authority)
+        .await
+        .expect_err("accept_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(accept_error),
+        AmmError::Unauthorized
+    );
+
+    let vault_after_accept = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_after_accept.authority, env.user_token_x_y_owner_and_payer.pubkey());
+    assert_eq!(vault_after_accept.pending_authority, Some(new_authority.pubkey()));
+}
+
+
+// Test fee
+
+async fn set_fee(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    authority: &Keypair,
+    fee_numerator: u64,
+    fee_denominator: u64,
+    dynamic_fee_enabled: bool,
+) -> Result<(), TransportError> {
+    let set_fee_ix = AmmInstruction::set_fee(
+        fee_numerator,
+        fee_denominator,
+        dynamic_fee_enabled,
+        authority.pubkey(),
+        minter_x.pubkey(),
+        minter_y.pubkey(),
+    );
+    let set_fee_tx = Transaction::new_signed_with_payer(
+        &[set_fee_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(set_fee_tx).await
+}
+
+#[tokio::test]
+async fn swap_x_to_y_with_fee() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let amount = 500;
+    let fee_numerator = 100;
+    let fee_denominator = 10_000;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    set_fee(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        fee_numerator,
+        fee_denominator,
+        false,
+    ).await.expect("set_fee");
+    let vault_after_set_fee = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_after_set_fee.fee_numerator, fee_numerator);
+    assert_eq!(vault_after_set_fee.fee_denominator, fee_denominator);
+    assert_eq!(vault_after_set_fee.dynamic_fee_enabled, false);
+
+    let swap_ix = AmmInstruction::swap(
+        amount,
+        env.minter_x.pubkey(),
+        env.user_token_x_y_owner_and_payer.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix],
+        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+        &[&env.user_token_x_y_owner_and_payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap_with_fee(amount, amount_x, amount_y, fee_numerator, fee_denominator)
+        .expect("swap_result");
+    assert_eq!(swap_result.fee_amount, 5);
+
+    let vault_after_swap = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_after_swap.token_x_amount, amount_x + swap_result.take_amount);
+    assert_eq!(vault_after_swap.token_y_amount, amount_y - swap_result.return_amount);
+    assert!(
+        vault_after_swap.token_x_amount as u128 * vault_after_swap.token_y_amount as u128 >
+            amount_x as u128 * amount_y as u128
+    );
+}
+
+#[tokio::test]
+async fn swap_quote_out() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let swaps = [
+        (false, env.minter_x.pubkey(), 100),
+        (false, env.minter_y.pubkey(), 777),
+        (true, env.minter_x.pubkey(), 250),
+        (true, env.minter_y.pubkey(), 1_500),
+        (true, env.minter_x.pubkey(), 3),
+    ];
+    let mut fee_dynamic = None;
+    for (dynamic_fee_enabled, minter_pk, amount) in swaps {
+        if fee_dynamic != Some(dynamic_fee_enabled) {
+            set_fee(ctx, &env.minter_x, &env.minter_y, user, 100, 10_000, dynamic_fee_enabled).await.expect("set_fee");
+            fee_dynamic = Some(dynamic_fee_enabled);
+        }
+        let before_swap = PoolSnapshot::capture_with_user(ctx, &pda, &env.user_token_x_pk, &env.user_token_y_pk).await;
+        let swap_direction = SwapDirection::new(&minter_pk, &env.minter_x.pubkey(), &env.minter_y.pubkey())
+            .expect("swap_direction");
+        let quote = before_swap.vault.quote_out(amount, swap_direction).expect("quote");
+
+        let swap_ix = AmmInstruction::swap(
+            amount,
+            minter_pk,
+            user.pubkey(),
+            env.user_token_x_pk,
+            env.user_token_y_pk,
+            env.minter_x.pubkey(),
+            env.minter_y.pubkey(),
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&user.pubkey()),
+            &[user],
+            ctx.last_blockhash,
+        );
+        ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+        let after_swap = PoolSnapshot::capture_with_user(ctx, &pda, &env.user_token_x_pk, &env.user_token_y_pk).await;
+        let returned_amount = match swap_direction {
+            SwapDirection::XtoY => after_swap.user_token_y_amount.unwrap() - before_swap.user_token_y_amount.unwrap(),
+            SwapDirection::YtoX => after_swap.user_token_x_amount.unwrap() - before_swap.user_token_x_amount.unwrap(),
+        };
+        assert_eq!(returned_amount, quote, "swap of {} {:?}", amount, swap_direction);
+    }
+}
+
+#[tokio::test]
+async fn swap_cumulative_fee() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let fee_numerator = 100;
+    let fee_denominator = 10_000;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    set_fee(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        fee_numerator,
+        fee_denominator,
+        false,
+    ).await.expect("set_fee");
+
+    let vault_before_swaps = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_before_swaps.cumulative_fee_x, 0);
+    assert_eq!(vault_before_swaps.cumulative_fee_y, 0);
+
+    let swaps = [
+        (env.minter_x.pubkey(), 500),
+        (env.minter_y.pubkey(), 600),
+        (env.minter_x.pubkey(), 700),
+        (env.minter_y.pubkey(), 1_200),
+    ];
+    for (minter_pk, amount) in swaps {
+        let vault_before_swap = get_vault(ctx, pda.vault.0).await;
+        let swap_direction = SwapDirection::new(&minter_pk, &env.minter_x.pubkey(), &env.minter_y.pubkey())
+            .expect("swap_direction");
+        let (source_amount, destination_amount) = match swap_direction {
+            SwapDirection::XtoY => (vault_before_swap.token_x_amount, vault_before_swap.token_y_amount),
+            SwapDirection::YtoX => (vault_before_swap.token_y_amount, vault_before_swap.token_x_amount),
+        };
+        let swap_result = calc_swap_with_fee(amount, source_amount, destination_amount, fee_numerator, fee_denominator)
+            .expect("swap_result");
+        assert!(swap_result.fee_amount > 0);
+
+        let swap_ix = AmmInstruction::swap(
+            amount,
+            minter_pk,
+            env.user_token_x_y_owner_and_payer.pubkey(),
+            env.user_token_x_pk,
+            env.user_token_y_pk,
+            env.minter_x.pubkey(),
+            env.minter_y.pubkey(),
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+            &[&env.user_token_x_y_owner_and_payer],
+            ctx.last_blockhash,
+        );
+        ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+        let vault_after_swap = get_vault(ctx, pda.vault.0).await;
+        match swap_direction {
+            SwapDirection::XtoY => {
+                assert_eq!(
+                    vault_after_swap.cumulative_fee_x,
+                    vault_before_swap.cumulative_fee_x + swap_result.fee_amount
+                );
+                assert_eq!(vault_after_swap.cumulative_fee_y, vault_before_swap.cumulative_fee_y);
+            }
+            SwapDirection::YtoX => {
+                assert_eq!(vault_after_swap.cumulative_fee_x, vault_before_swap.cumulative_fee_x);
+                assert_eq!(
+                    vault_after_swap.cumulative_fee_y,
+                    vault_before_swap.cumulative_fee_y + swap_result.fee_amount
+                );
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn swap_recent_prices() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    assert_eq!(get_vault(ctx, pda.vault.0).await.recent_prices(), [0; PRICE_SAMPLES]);
+
+    let mut prices = vec![];
+    for amount in 10..10 + PRICE_SAMPLES as u64 + 1 {
+        swap(
+            ctx,
+            &env.minter_x,
+            &env.minter_y,
+            &env.user_token_x_y_owner_and_payer,
+            &env.user_token_x_pk,
+            &env.user_token_y_pk,
+            &pda,
+            &env.minter_x.pubkey(),
+            amount,
+        ).await;
+        prices.push(get_vault(ctx, pda.vault.0).await.spot_price().expect("price"));
+
+        if prices.len() == PRICE_SAMPLES {
+            assert_eq!(get_vault(ctx, pda.vault.0).await.recent_prices()[..], prices[..]);
+        }
+    }
+    // the ninth swap overwrites the first price
+    assert_eq!(get_vault(ctx, pda.vault.0).await.recent_prices()[..], prices[1..]);
+}
+
+/// Warp the bank to a later slot and time, the new bank starts from the time of the `Clock` sysvar account
+async fn warp_to_unix_timestamp(ctx: &mut ProgramTestContext, slot: Slot, unix_timestamp: UnixTimestamp) {
+    let clock = Clock { unix_timestamp, ..ctx.banks_client.get_sysvar::<Clock>().await.expect("clock") };
+    let lamports = ctx.banks_client.get_balance(sysvar::clock::id()).await.expect("clock_lamports");
+    let clock_account = AccountSharedData::new_data(lamports, &clock, &sysvar::id()).expect("clock_account");
+    ctx.set_account(&sysvar::clock::id(), &clock_account);
+    ctx.warp_to_slot(slot).expect("warp_to_slot");
+}
+
+#[tokio::test]
+async fn swap_price_cumulative() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    let init_clock = ctx.banks_client.get_sysvar::<Clock>().await.expect("init_clock");
+
+    let vault_after_init = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault_after_init.price_x_cumulative, 0);
+    assert_eq!(vault_after_init.last_timestamp, init_clock.unix_timestamp);
+    // the accumulated price is adjusted by the decimals, like the price limit of the swap
+    let minter_x = ctx.banks_client.get_packed_account_data::<Mint>(env.minter_x.pubkey()).await.expect("minter_x");
+    let minter_y = ctx.banks_client.get_packed_account_data::<Mint>(env.minter_y.pubkey()).await.expect("minter_y");
+    assert_eq!((vault_after_init.decimals_x, vault_after_init.decimals_y), (minter_x.decimals, minter_y.decimals));
+
+    let swaps = [
+        (env.minter_x.pubkey(), 500, init_clock.slot + 150, init_clock.unix_timestamp + 60),
+        (env.minter_y.pubkey(), 700, init_clock.slot + 250, init_clock.unix_timestamp + 100),
+    ];
+    for (minter_pk, amount, slot, timestamp) in swaps {
+        warp_to_unix_timestamp(ctx, slot, timestamp).await;
+        let clock = ctx.banks_client.get_sysvar::<Clock>().await.expect("clock");
+        assert_eq!((clock.slot, clock.unix_timestamp), (slot, timestamp));
+        let vault_before_swap = get_vault(ctx, pda.vault.0).await;
+        let price_before_swap = normalized_price(
+            vault_before_swap.token_x_amount, vault_before_swap.token_y_amount, minter_x.decimals, minter_y.decimals,
+        ).expect("price_before_swap");
+        assert_eq!(vault_before_swap.spot_price(), Some(price_before_swap));
+
+        let swap_ix = AmmInstruction::swap(
+            amount,
+            minter_pk,
+            env.user_token_x_y_owner_and_payer.pubkey(),
+            env.user_token_x_pk,
+            env.user_token_y_pk,
+            env.minter_x.pubkey(),
+            env.minter_y.pubkey(),
+        );
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix],
+            Some(&env.user_token_x_y_owner_and_payer.pubkey()),
+            &[&env.user_token_x_y_owner_and_payer],
+            ctx.last_blockhash,
+        );
+        ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+        let vault_after_swap = get_vault(ctx, pda.vault.0).await;
+        let elapsed = (timestamp - vault_before_swap.last_timestamp) as u128;
+        assert_eq!(
+            vault_after_swap.price_x_cumulative,
+            vault_before_swap.price_x_cumulative + price_before_swap * elapsed
+        );
+        assert_eq!(vault_after_swap.last_timestamp, timestamp);
+    }
+}
+
+#[tokio::test]
+async fn rescue_tokens() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let stuck_amount = 700;
+    let authority = &env.user_token_x_y_owner_and_payer;
+    let minter_z = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        authority,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // token Z sent by mistake to the PDA token X owner
+    Env::initialize_minter(ctx, authority, &minter_z, authority, authority, 6).await;
+    let stuck_token_pk = spl_associated_token_account::get_associated_token_address(
+        &pda.pda_owner_token_x.0, &minter_z.pubkey(),
+    );
+    let authority_token_z_pk = spl_associated_token_account::get_associated_token_address(
+        &authority.pubkey(), &minter_z.pubkey(),
+    );
+    let create_stuck_token_ix = spl_associated_token_account::create_associated_token_account(
+        &authority.pubkey(),
+        &pda.pda_owner_token_x.0,
+        &minter_z.pubkey(),
+    );
+    let create_authority_token_z_ix = spl_associated_token_account::create_associated_token_account(
+        &authority.pubkey(),
+        &authority.pubkey(),
+        &minter_z.pubkey(),
+    );
+    let create_token_z_tx = Transaction::new_signed_with_payer(
+        &[create_stuck_token_ix, create_authority_token_z_ix],
+        Some(&authority.pubkey()),
+        &[authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(create_token_z_tx).await.expect("create_token_z_tx");
+    Env::mint_token(ctx, authority, &minter_z, &stuck_token_pk, authority, stuck_amount).await;
+
+    let rescue_ix = AmmInstruction::rescue_tokens(
+        stuck_amount,
+        authority.pubkey(),
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+        pda.pda_owner_token_x.0,
+        stuck_token_pk,
+        authority_token_z_pk,
+        minter_z.pubkey(),
+    );
+    let rescue_tx = Transaction::new_signed_with_payer(
+        &[rescue_ix],
+        Some(&authority.pubkey()),
+        &[authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(rescue_tx).await.expect("rescue_tx");
+
+    let stuck_token_acc = ctx.banks_client.get_packed_account_data::<Account>(stuck_token_pk)
+        .await
+        .expect("stuck_token_acc");
+    assert_eq!(stuck_token_acc.amount, 0);
+    let authority_token_z_acc = ctx.banks_client.get_packed_account_data::<Account>(authority_token_z_pk)
+        .await
+        .expect("authority_token_z_acc");
+    assert_eq!(authority_token_z_acc.amount, stuck_amount);
+
+    // tokens X, Y of the market are protected
+    let rescue_pool_ix = AmmInstruction::rescue_tokens(
+        amount_x,
+        authority.pubkey(),
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+        pda.pda_owner_token_x.0,
+        pda.pda_token_x_pk,
+        env.user_token_x_pk,
+        env.minter_x.pubkey(),
+    );
+    let rescue_pool_tx = Transaction::new_signed_with_payer(
+        &[rescue_pool_ix],
+        Some(&authority.pubkey()),
+        &[authority],
+        ctx.last_blockhash,
+    );
+    let rescue_pool_error = ctx.banks_client.process_transaction(rescue_pool_tx).await
+        .expect_err("rescue_pool_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(rescue_pool_error),
+        AmmError::CannotRescuePoolToken
+    );
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+}
+
+#[tokio::test]
+async fn donate() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let donate_x = 100;
+    let donate_y = 200;
+    let authority = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        authority,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let donate_zero_ix = AmmInstruction::donate(
+        0,
+        0,
+        authority.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let donate_zero_tx = Transaction::new_signed_with_payer(
+        &[donate_zero_ix],
+        Some(&authority.pubkey()),
+        &[authority],
+        ctx.last_blockhash,
+    );
+    let donate_zero_error = ctx.banks_client.process_transaction(donate_zero_tx).await
+        .expect_err("donate_zero_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(donate_zero_error),
+        AmmError::AmountZero
+    );
+
+    let donate_ix = AmmInstruction::donate(
+        donate_x,
+        donate_y,
+        authority.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let donate_tx = Transaction::new_signed_with_payer(
+        &[donate_ix],
+        Some(&authority.pubkey()),
+        &[authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(donate_tx).await.expect("donate_tx");
+
+    // no LP tokens are issued, the donation only grows the reserves
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x + donate_x, amount_y + donate_y).await;
+    let vault_after_donate = get_vault(ctx, pda.vault.0).await;
+    assert!(
+        vault_after_donate.token_x_amount as u128 * vault_after_donate.token_y_amount as u128 >
+            amount_x as u128 * amount_y as u128
+    );
+}
+
+#[tokio::test]
+async fn donate_unauthorized() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let not_authority = Keypair::new();
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        1_000,
+        3_000,
+    ).await.expect("init_market");
+
+    let donate_ix = AmmInstruction::donate(
+        100,
+        200,
+        not_authority.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let donate_tx = Transaction::new_signed_with_payer(
+        &[donate_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, &not_authority],
+        ctx.last_blockhash,
+    );
+    let donate_error = ctx.banks_client.process_transaction(donate_tx).await
+        .expect_err("donate_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(donate_error),
+        AmmError::Unauthorized
+    );
+}
+
+#[tokio::test]
+async fn set_fee_invalid_and_unauthorized() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let not_authority = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    let set_fee_error = set_fee(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        10_000,
+        10_000,
+        false,
+    ).await
+        .expect_err("set_fee_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(set_fee_error),
+        AmmError::InvalidFee
+    );
+
+    let set_fee_error = set_fee(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &not_authority,
+        30,
+        10_000,
+        true,
+    ).await
+        .expect_err("set_fee_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(set_fee_error),
+        AmmError::Unauthorized
+    );
+}
+
+async fn set_fee_recipient(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    authority: &Keypair,
+    recipient: &Pubkey,
+) -> Result<(), TransportError> {
+    let set_fee_recipient_ix = AmmInstruction::set_fee_recipient(
+        *recipient,
+        authority.pubkey(),
+        minter_x.pubkey(),
+        minter_y.pubkey(),
+    );
+    let set_fee_recipient_tx = Transaction::new_signed_with_payer(
+        &[set_fee_recipient_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(set_fee_recipient_tx).await
+}
+
+#[tokio::test]
+async fn swap_fee_exempt() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let amount = 500;
+    let (fee_numerator, fee_denominator) = (30, 10_000);
+    let user = &env.user_token_x_y_owner_and_payer;
+    let not_authority = Keypair::new();
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+
+    let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    set_fee(ctx, &env.minter_x, &env.minter_y, user, fee_numerator, fee_denominator, false)
+        .await
+        .expect("set_fee");
+
+    let set_fee_exempt_tx = |ctx: &ProgramTestContext, fee_exempt: Option<Pubkey>, authority: &Keypair| {
+        Transaction::new_signed_with_payer(
+            &[AmmInstruction::set_fee_exempt(fee_exempt, authority.pubkey(), minter_x_pk, minter_y_pk)],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer, authority],
+            ctx.last_blockhash,
+        )
+    };
+    let unauthorized_tx = set_fee_exempt_tx(ctx, Some(user.pubkey()), &not_authority);
+    let set_fee_exempt_error = ctx.banks_client.process_transaction(unauthorized_tx)
+        .await
+        .expect_err("set_fee_exempt_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(set_fee_exempt_error),
+        AmmError::Unauthorized
+    );
+
+    let swap_ix = AmmInstruction::swap(
+        amount,
+        minter_x_pk,
+        user.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
+    );
+    for fee_exempt in [Some(user.pubkey()), None] {
+        let authorized_tx = set_fee_exempt_tx(ctx, fee_exempt, user);
+        ctx.banks_client.process_transaction(authorized_tx)
+            .await
+            .expect("set_fee_exempt_tx");
+        assert_eq!(get_vault(ctx, pda.vault.0).await.fee_exempt, fee_exempt);
+
+        // the same trade, paid by another payer each time, so it is not a duplicate
+        let payer = if fee_exempt.is_some() { user } else { &ctx.payer };
+        let swap_tx = Transaction::new_signed_with_payer(
+            &[swap_ix.clone()],
+            Some(&payer.pubkey()),
+            &[payer, user],
+            ctx.last_blockhash,
+        );
+        let before_swap = get_vault(ctx, pda.vault.0).await;
+        ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+        let after_swap = get_vault(ctx, pda.vault.0).await;
+
+        let swap_result = if fee_exempt.is_some() {
+            calc_swap(amount, before_swap.token_x_amount, before_swap.token_y_amount).expect("swap_result")
+        } else {
+            calc_swap_with_fee(
+                amount,
+                before_swap.token_x_amount,
+                before_swap.token_y_amount,
+                fee_numerator,
+                fee_denominator,
+            ).expect("swap_result")
+        };
+        assert_eq!(swap_result.fee_amount > 0, fee_exempt.is_none());
+        assert_eq!(after_swap.token_x_amount, before_swap.token_x_amount + swap_result.take_amount);
+        assert_eq!(after_swap.token_y_amount, before_swap.token_y_amount - swap_result.return_amount);
+        assert_eq!(after_swap.cumulative_fee_x, before_swap.cumulative_fee_x + swap_result.fee_amount);
+    }
+}
+
+#[tokio::test]
+async fn set_fee_recipient_update() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let authority = &env.user_token_x_y_owner_and_payer;
+    let not_authority = Keypair::new();
+    let missing_treasury = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        authority,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        1_000,
+        3_000,
+    ).await.expect("init_market");
+    assert_eq!(get_vault(ctx, pda.vault.0).await.fee_recipient, None);
+
+    let (treasury, _, _) = new_user(ctx, &env.minter_x, &env.minter_y, authority, 1, 1).await;
+    let (new_treasury, new_treasury_token_x_pk, new_treasury_token_y_pk) = new_user(
+        ctx, &env.minter_x, &env.minter_y, authority, 1, 1,
+    ).await;
+
+    let set_fee_recipient_error = set_fee_recipient(
+        ctx, &env.minter_x, &env.minter_y, &not_authority, &treasury.pubkey(),
+    ).await
+        .expect_err("set_fee_recipient_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(set_fee_recipient_error),
+        AmmError::Unauthorized
+    );
+
+    // a recipient without the associated token accounts can't receive the fees
+    let set_fee_recipient_error = set_fee_recipient(
+        ctx, &env.minter_x, &env.minter_y, authority, &missing_treasury.pubkey(),
+    ).await
+        .expect_err("set_fee_recipient_error")
+        .unwrap();
+    assert_eq!(
+        set_fee_recipient_error,
+        TransactionError::InstructionError(0, InstructionError::IncorrectProgramId)
+    );
+
+    set_fee_recipient(ctx, &env.minter_x, &env.minter_y, authority, &treasury.pubkey())
+        .await
+        .expect("set_fee_recipient");
+    set_fee_recipient(ctx, &env.minter_x, &env.minter_y, authority, &new_treasury.pubkey())
+        .await
+        .expect("set_fee_recipient");
+
+    // the protocol fees go to the existing associated token accounts of the new treasury
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault.fee_recipient, Some(new_treasury.pubkey()));
+    assert_eq!(vault.fee_recipient_token(&env.minter_x.pubkey()), Some(new_treasury_token_x_pk));
+    assert_eq!(vault.fee_recipient_token(&env.minter_y.pubkey()), Some(new_treasury_token_y_pk));
+}
+
+async fn set_paused(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    authority: &Keypair,
+    paused: bool,
+) -> Result<(), TransportError> {
+    let set_paused_ix = AmmInstruction::set_paused(
+        paused,
+        authority.pubkey(),
+        minter_x.pubkey(),
+        minter_y.pubkey(),
+    );
+    let set_paused_tx = Transaction::new_signed_with_payer(
+        &[set_paused_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(set_paused_tx).await
+}
+
+async fn emergency_withdraw(
+    ctx: &mut ProgramTestContext,
+    minter_x: &Keypair,
+    minter_y: &Keypair,
+    authority: &Keypair,
+    authority_token_x_pk: &Pubkey,
+    authority_token_y_pk: &Pubkey,
+) -> Result<(), TransportError> {
+    let emergency_withdraw_ix = AmmInstruction::emergency_withdraw(
+        authority.pubkey(),
+        *authority_token_x_pk,
+        *authority_token_y_pk,
+        minter_x.pubkey(),
+        minter_y.pubkey(),
+    );
+    let emergency_withdraw_tx = Transaction::new_signed_with_payer(
+        &[emergency_withdraw_ix],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer, authority],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(emergency_withdraw_tx).await
+}
+
+#[tokio::test]
+async fn emergency_withdraw_paused() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let authority = &env.user_token_x_y_owner_and_payer;
+    let not_authority = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        authority,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // paid by the authority, so it is not a duplicate of the withdraw of the paused market below
+    let emergency_withdraw_ix = AmmInstruction::emergency_withdraw(
+        authority.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    let emergency_withdraw_tx = Transaction::new_signed_with_payer(
+        &[emergency_withdraw_ix],
+        Some(&authority.pubkey()),
+        &[authority],
+        ctx.last_blockhash,
+    );
+    let emergency_withdraw_error = ctx.banks_client.process_transaction(emergency_withdraw_tx)
+        .await
+        .expect_err("emergency_withdraw_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(emergency_withdraw_error),
+        AmmError::MarketNotPaused
+    );
+
+    let set_paused_error = set_paused(ctx, &env.minter_x, &env.minter_y, &not_authority, true)
+        .await
+        .expect_err("set_paused_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(set_paused_error),
+        AmmError::Unauthorized
+    );
+    set_paused(ctx, &env.minter_x, &env.minter_y, authority, true)
+        .await
+        .expect("set_paused");
+    assert!(get_vault(ctx, pda.vault.0).await.paused);
+
+    let swap_ix = AmmInstruction::swap(
+        100,
+        env.minter_x.pubkey(),
+        authority.pubkey(),
+        env.user_token_x_pk,
         env.user_token_y_pk,
         env.minter_x.pubkey(),
         env.minter_y.pubkey(),
     );
     let swap_tx = Transaction::new_signed_with_payer(
-        &[swap_ix],
-        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
-        &[&env.user_token_x_y_owner_and_payer],
+        &[swap_ix],
+        Some(&authority.pubkey()),
+        &[authority],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx)
+        .await
+        .expect_err("swap_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::MarketPaused
+    );
+
+    let emergency_withdraw_error = emergency_withdraw(
+        ctx, &env.minter_x, &env.minter_y, &not_authority, &env.user_token_x_pk, &env.user_token_y_pk,
+    ).await
+        .expect_err("emergency_withdraw_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(emergency_withdraw_error),
+        AmmError::Unauthorized
+    );
+
+    let before_withdraw = PoolSnapshot::capture_with_user(ctx, &pda, &env.user_token_x_pk, &env.user_token_y_pk).await;
+    emergency_withdraw(
+        ctx, &env.minter_x, &env.minter_y, authority, &env.user_token_x_pk, &env.user_token_y_pk,
+    ).await.expect("emergency_withdraw");
+    let after_withdraw = PoolSnapshot::capture_with_user(ctx, &pda, &env.user_token_x_pk, &env.user_token_y_pk).await;
+
+    assert_eq!((after_withdraw.pda_token_x.amount, after_withdraw.pda_token_y.amount), (0, 0));
+    assert_eq!(after_withdraw.user_token_x_amount, before_withdraw.user_token_x_amount.map(|amount| amount + amount_x));
+    assert_eq!(after_withdraw.user_token_y_amount, before_withdraw.user_token_y_amount.map(|amount| amount + amount_y));
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!((vault.token_x_amount, vault.token_y_amount), (0, 0));
+    assert!(vault.paused);
+
+    // the evacuated market is not re-seeded by a third party
+    let (intruder, intruder_token_x_pk, intruder_token_y_pk) = new_user(
+        ctx, &env.minter_x, &env.minter_y, authority, amount_x, amount_y,
+    ).await;
+    let init_error = init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &intruder,
+        &intruder_token_x_pk,
+        &intruder_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { allow_existing: true, ..InitMarketOptions::default() },
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::Unauthorized
+    );
+
+    // the authority re-seeds it, still paused until `SetPaused`
+    init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        authority,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { allow_existing: true, ..InitMarketOptions::default() },
+    ).await.expect("init_market_with_options");
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!((vault.token_x_amount, vault.token_y_amount), (amount_x, amount_y));
+    assert_eq!(vault.authority, authority.pubkey());
+    assert!(vault.paused);
+}
+
+#[tokio::test]
+async fn init_market_with_authority() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let authority = Keypair::new();
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { authority: Some(authority.pubkey()), ..InitMarketOptions::default() },
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+    assert_eq!(get_vault(ctx, pda.vault.0).await.authority, authority.pubkey());
+
+    // the payer isn't the admin
+    let set_fee_error = set_fee(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        30,
+        10_000,
+        false,
+    ).await
+        .expect_err("set_fee_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(set_fee_error),
+        AmmError::Unauthorized
+    );
+
+    set_fee(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &authority,
+        30,
+        10_000,
+        false,
+    ).await.expect("set_fee");
+    assert_eq!(get_vault(ctx, pda.vault.0).await.fee_numerator, 30);
+}
+
+#[tokio::test]
+async fn init_market_with_min_reserve() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    // the initial amounts are below the floor
+    let init_error = init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { min_reserve: amount_x + 1, ..InitMarketOptions::default() },
+    ).await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(init_error),
+        AmmError::BelowMinReserve
+    );
+
+    init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        &env.user_token_x_y_owner_and_payer,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { min_reserve: amount_x, ..InitMarketOptions::default() },
+    ).await.expect("init_market");
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+    assert_eq!(get_vault(ctx, pda.vault.0).await.min_reserve, amount_x);
+}
+
+#[tokio::test]
+async fn swap_below_min_reserve() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    // the reserve X is at the floor
+    init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { min_reserve: amount_x, ..InitMarketOptions::default() },
+    ).await.expect("init_market");
+
+    let user_pk = user.pubkey();
+    let (user_token_x_pk, user_token_y_pk) = (env.user_token_x_pk, env.user_token_y_pk);
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+    let swap_ix = |minter_pk, amount| AmmInstruction::swap(
+        amount,
+        minter_pk,
+        user_pk,
+        user_token_x_pk,
+        user_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
+    );
+
+    // Y to X takes the reserve X below the floor
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(minter_y_pk, 100)],
+        Some(&user.pubkey()),
+        &[user],
         ctx.last_blockhash,
     );
     let swap_error = ctx.banks_client.process_transaction(swap_tx).await
         .expect_err("swap_error")
         .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(swap_error),
+        AmmError::BelowMinReserve
+    );
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    // X to Y keeps the reserve Y above the floor
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(minter_x_pk, 100)],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert!(vault.token_x_amount > amount_x && vault.token_y_amount > amount_x);
 
+    // a large X to Y takes the reserve Y below the floor
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(minter_x_pk, 5_000)],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    let swap_error = ctx.banks_client.process_transaction(swap_tx).await
+        .expect_err("swap_error")
+        .unwrap();
     assert_eq!(
         decode_error::<AmmError>(swap_error),
-        AmmError::InvalidVault
+        AmmError::BelowMinReserve
     );
+    assert_eq!(get_vault(ctx, pda.vault.0).await, vault);
 }
 
 #[tokio::test]
-async fn swap_zero_amount() {
+async fn inspect_desynced_reserves() {
     let mut env = Env::new().await;
     let ctx = &mut env.ctx;
-    let swap_pk = env.minter_y.pubkey();
-    let amount = 0;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let sent_x = 250;
+    let user = &env.user_token_x_y_owner_and_payer;
 
     let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
     check_pda(ctx, &pda).await;
 
-    // swap
-    let swap_ix = AmmInstruction::swap(
+    let inspect_tx = Transaction::new_signed_with_payer(
+        &[AmmInstruction::inspect(env.minter_x.pubkey(), env.minter_y.pubkey())],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    let inspect_error = ctx.banks_client.process_transaction(inspect_tx).await
+        .expect_err("inspect_error")
+        .unwrap();
+    assert_eq!(
+        decode_error::<AmmError>(inspect_error),
+        AmmError::MarketNotInitialized
+    );
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // tokens sent straight to the PDA SPL token X holder, past the market
+    let transfer_ix = spl_token::instruction::transfer(
+        &spl_token::id(),
+        &env.user_token_x_pk,
+        &pda.pda_token_x_pk,
+        &user.pubkey(),
+        &[],
+        sent_x,
+    ).expect("transfer_ix");
+    let inspect_tx = Transaction::new_signed_with_payer(
+        &[transfer_ix, AmmInstruction::inspect(env.minter_x.pubkey(), env.minter_y.pubkey())],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(inspect_tx).await.expect("inspect_tx");
+
+    // the banks client doesn't return the return data, the same report from the accounts
+    let after_transfer = PoolSnapshot::capture(ctx, &pda).await;
+    let report = ReservesReport::new(
+        &after_transfer.vault,
+        after_transfer.pda_token_x.amount,
+        after_transfer.pda_token_y.amount,
+    );
+    assert_eq!(report.reserve_x, amount_x);
+    assert_eq!(report.difference_x, sent_x as i128);
+    assert_eq!(report.difference_y, 0);
+    assert!(!report.is_synced());
+}
+
+#[tokio::test]
+async fn swap_before_launch_slot() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let amount = 100;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    let launch_slot = ctx.banks_client.get_root_slot().await.expect("root_slot") + 100;
+    init_market_with_options(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+        InitMarketOptions { launch_slot, ..InitMarketOptions::default() },
+    ).await.expect("init_market");
+    assert_eq!(get_vault(ctx, pda.vault.0).await.launch_slot, launch_slot);
+
+    let user_pk = user.pubkey();
+    let (user_token_x_pk, user_token_y_pk) = (env.user_token_x_pk, env.user_token_y_pk);
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+    let swap_ix = |amount| AmmInstruction::swap(
         amount,
-        swap_pk,
-        env.user_token_x_y_owner_and_payer.pubkey(),
-        env.user_token_x_pk,
-        env.user_token_y_pk,
-        env.minter_x.pubkey(),
-        env.minter_y.pubkey(),
+        minter_x_pk,
+        user_pk,
+        user_token_x_pk,
+        user_token_y_pk,
+        minter_x_pk,
+        minter_y_pk,
     );
     let swap_tx = Transaction::new_signed_with_payer(
-        &[swap_ix],
-        Some(&env.user_token_x_y_owner_and_payer.pubkey()),
-        &[&env.user_token_x_y_owner_and_payer],
+        &[swap_ix(amount)],
+        Some(&user.pubkey()),
+        &[user],
         ctx.last_blockhash,
     );
     let swap_error = ctx.banks_client.process_transaction(swap_tx).await
         .expect_err("swap_error")
         .unwrap();
-
     assert_eq!(
         decode_error::<AmmError>(swap_error),
-        AmmError::AmountZero
+        AmmError::MarketNotLive
+    );
+    check_init_market(ctx, &env.minter_x, &env.minter_y, &pda, amount_x, amount_y).await;
+
+    ctx.warp_to_slot(launch_slot).expect("warp_to_slot");
+    let swap_tx = Transaction::new_signed_with_payer(
+        &[swap_ix(amount)],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap(amount, amount_x, amount_y).expect("swap_result");
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault.token_x_amount, amount_x + swap_result.take_amount);
+}
+
+#[tokio::test]
+async fn validate_healthy_and_surplus() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let sent_y = 300;
+    let user = &env.user_token_x_y_owner_and_payer;
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+
+    let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+    check_pda(ctx, &pda).await;
+
+    // nothing is created yet, the failed checks are reported
+    let validate_tx = Transaction::new_signed_with_payer(
+        &[AmmInstruction::validate(minter_x_pk, minter_y_pk)],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(validate_tx).await.expect("validate_tx");
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+
+    // the banks client doesn't return the return data, the same checks from the accounts
+    let market_checks = |snapshot: &PoolSnapshot| MarketChecks::new(
+        [&minter_x_pk, &minter_y_pk],
+        [&pda.vault.0, &pda.pda_token_x_pk, &pda.pda_token_y_pk],
+        Some(&snapshot.vault),
+        [Some(&snapshot.pda_token_x), Some(&snapshot.pda_token_y)],
+    );
+    let healthy = PoolSnapshot::capture(ctx, &pda).await;
+    assert!(market_checks(&healthy).is_healthy());
+
+    // tokens sent straight to the PDA SPL token Y holder, past the market
+    let transfer_ix = spl_token::instruction::transfer(
+        &spl_token::id(),
+        &env.user_token_y_pk,
+        &pda.pda_token_y_pk,
+        &user.pubkey(),
+        &[],
+        sent_y,
+    ).expect("transfer_ix");
+    let validate_tx = Transaction::new_signed_with_payer(
+        &[transfer_ix, AmmInstruction::validate(minter_x_pk, minter_y_pk)],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client.process_transaction(validate_tx).await.expect("validate_tx");
+
+    let surplus = PoolSnapshot::capture(ctx, &pda).await;
+    assert_eq!(surplus.pda_token_y.amount, amount_y + sent_y);
+    assert_eq!(market_checks(&surplus), MarketChecks(MarketChecks::ALL & !MarketChecks::RESERVES));
+}
+
+#[tokio::test]
+async fn instruction_data_trailing_bytes() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let user = &env.user_token_x_y_owner_and_payer;
+
+    let pda = Pda::generate(&env.minter_x.pubkey(), &env.minter_y.pubkey());
+    check_pda(ctx, &pda).await;
+
+    let mut init_ix = AmmInstruction::init_market(
+        1_000,
+        3_000,
+        user.pubkey(),
+        user.pubkey(),
+        user.pubkey(),
+        env.user_token_x_pk,
+        env.user_token_y_pk,
+        env.minter_x.pubkey(),
+        env.minter_y.pubkey(),
+    );
+    init_ix.data.extend_from_slice(&[0, 1, 2]);
+    let init_tx = Transaction::new_signed_with_payer(
+        &[init_ix],
+        Some(&user.pubkey()),
+        &[user],
+        ctx.last_blockhash,
+    );
+    let init_error = ctx.banks_client.process_transaction(init_tx)
+        .await
+        .expect_err("init_error")
+        .unwrap();
+    assert_eq!(
+        init_error,
+        TransactionError::InstructionError(0, InstructionError::InvalidInstructionData)
     );
+
+    // nothing is created by the rejected instruction
+    assert!(ctx.banks_client.get_account(pda.vault.0).await.expect("get_account").is_none());
 }
 
+#[tokio::test]
+async fn swap_referral_fee() {
+    let mut env = Env::new().await;
+    let ctx = &mut env.ctx;
+    let amount_x = 1_000;
+    let amount_y = 3_000;
+    let amount = 500;
+    let referral_fee_bps = 50;
+    let (fee_numerator, fee_denominator) = (100, 10_000);
+    let user = &env.user_token_x_y_owner_and_payer;
+    let referrer = Keypair::new();
+    let (minter_x_pk, minter_y_pk) = (env.minter_x.pubkey(), env.minter_y.pubkey());
+    let (user_token_x_pk, user_token_y_pk) = (env.user_token_x_pk, env.user_token_y_pk);
+
+    let pda = Pda::generate(&minter_x_pk, &minter_y_pk);
+    check_pda(ctx, &pda).await;
+
+    init_market(
+        ctx,
+        &env.minter_x,
+        &env.minter_y,
+        user,
+        &env.user_token_x_pk,
+        &env.user_token_y_pk,
+        amount_x,
+        amount_y,
+    ).await.expect("init_market");
+    set_fee(ctx, &env.minter_x, &env.minter_y, user, fee_numerator, fee_denominator, false)
+        .await
+        .expect("set_fee");
+
+    // the referrer is paid in the swapped token X
+    let referrer_token_x_pk = spl_associated_token_account::get_associated_token_address(
+        &referrer.pubkey(), &minter_x_pk,
+    );
+    let create_referrer_token_x_tx = Transaction::new_signed_with_payer(
+        &[spl_associated_token_account::create_associated_token_account(
+            &ctx.payer.pubkey(), &referrer.pubkey(), &minter_x_pk,
+        )],
+        Some(&ctx.payer.pubkey()),
+        &[&ctx.payer],
+        ctx.last_blockhash,
+    );
+    ctx.banks_client
+        .process_transaction(create_referrer_token_x_tx)
+        .await
+        .expect("create_referrer_token_x_tx");
+
+    let referral_swap_tx = |ctx: &ProgramTestContext, referrer: Option<Pubkey>, referral_fee_bps: u16| {
+        let options = SwapOptions { referrer, referral_fee_bps, ..SwapOptions::default() };
+        Transaction::new_signed_with_payer(
+            &[AmmInstruction::swap_with_options(
+                amount,
+                minter_x_pk,
+                user.pubkey(),
+                user_token_x_pk,
+                user_token_y_pk,
+                minter_x_pk,
+                minter_y_pk,
+                options,
+            )],
+            Some(&user.pubkey()),
+            &[user],
+            ctx.last_blockhash,
+        )
+    };
+    // the referral fee isn't dropped silently without the referrer
+    let no_referrer_tx = referral_swap_tx(ctx, None, referral_fee_bps);
+    let no_referrer_error = ctx.banks_client.process_transaction(no_referrer_tx)
+        .await
+        .expect_err("no_referrer_error")
+        .unwrap();
+    assert_eq!(decode_error::<AmmError>(no_referrer_error), AmmError::MissingReferrer);
+
+    let over_cap_tx = referral_swap_tx(ctx, Some(referrer.pubkey()), MAX_REFERRAL_FEE_BPS + 1);
+    let over_cap_error = ctx.banks_client.process_transaction(over_cap_tx)
+        .await
+        .expect_err("over_cap_error")
+        .unwrap();
+    assert_eq!(decode_error::<AmmError>(over_cap_error), AmmError::InvalidFee);
+
+    let user_token_x_before_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_x_pk)
+        .await
+        .expect("user_token_x_before_swap");
+    let swap_tx = referral_swap_tx(ctx, Some(referrer.pubkey()), referral_fee_bps);
+    ctx.banks_client.process_transaction(swap_tx).await.expect("swap_tx");
+
+    let swap_result = calc_swap_with_fee(amount, amount_x, amount_y, fee_numerator, fee_denominator)
+        .expect("swap_result");
+    let referral_fee = calc_referral_fee(amount, referral_fee_bps, swap_result.fee_amount).expect("referral_fee");
+    assert_eq!(referral_fee, amount * referral_fee_bps as u64 / 10_000);
+
+    let referrer_token_x = ctx.banks_client
+        .get_packed_account_data::<Account>(referrer_token_x_pk)
+        .await
+        .expect("referrer_token_x");
+    assert_eq!(referrer_token_x.amount, referral_fee);
+    let user_token_x_after_swap = ctx.banks_client
+        .get_packed_account_data::<Account>(env.user_token_x_pk)
+        .await
+        .expect("user_token_x_after_swap");
+    assert_eq!(user_token_x_after_swap.amount, user_token_x_before_swap.amount - swap_result.take_amount);
+
+    // the rest of the take amount and the fee stays in the market
+    let vault = get_vault(ctx, pda.vault.0).await;
+    assert_eq!(vault.token_x_amount, amount_x + swap_result.take_amount - referral_fee);
+    assert_eq!(vault.token_y_amount, amount_y - swap_result.return_amount);
+    assert_eq!(vault.cumulative_fee_x, swap_result.fee_amount - referral_fee);
+    let pda_token_x = ctx.banks_client
+        .get_packed_account_data::<Account>(pda.pda_token_x_pk)
+        .await
+        .expect("pda_token_x");
+    assert_eq!(pda_token_x.amount, vault.token_x_amount);
+}
@@ -0,0 +1,46 @@
+#![cfg(feature = "program")]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use amm::log::SwapLog;
+
+#[test]
+fn swap_log_layout() {
+    let swap_log = SwapLog {
+        x_to_y: true,
+        take_amount: 100,
+        return_amount: 0x0102,
+        fee_amount: 1,
+        reserve_x: 600,
+        reserve_y: u64::MAX,
+    };
+    let data = swap_log.try_to_vec().unwrap();
+    assert_eq!(data.len(), SwapLog::LEN);
+
+    assert_eq!(data[0], 1);
+    assert_eq!(data[1..9], 100u64.to_le_bytes());
+    assert_eq!(data[9..17], [0x02, 0x01, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(data[17..25], 1u64.to_le_bytes());
+    assert_eq!(data[25..33], 600u64.to_le_bytes());
+    assert_eq!(data[33..41], [0xff; 8]);
+}
+
+#[test]
+fn swap_log_decode() {
+    let mut data = vec![0];
+    for amount in [300u64, 50, 3, 4_000, 250] {
+        data.extend_from_slice(&amount.to_le_bytes());
+    }
+
+    assert_eq!(
+        SwapLog::try_from_slice(&data).unwrap(),
+        SwapLog {
+            x_to_y: false,
+            take_amount: 300,
+            return_amount: 50,
+            fee_amount: 3,
+            reserve_x: 4_000,
+            reserve_y: 250,
+        }
+    );
+    assert!(SwapLog::try_from_slice(&data[..SwapLog::LEN - 1]).is_err());
+}
@@ -0,0 +1,27 @@
+#![cfg(feature = "core-math-only")]
+
+// $ cargo test --no-default-features --features core-math-only --test math
+
+use amm::math::{calc_swap, calc_swap_with_fee, SwapResult};
+
+#[test]
+fn calc_swap_without_program() {
+    let swap_result = calc_swap(100, 500, 300).expect("swap_result");
+    assert_eq!(swap_result, SwapResult { take_amount: 100, return_amount: 50, fee_amount: 0 });
+
+    let invariant_before_swap = 500u128 * 300;
+    let invariant_after_swap = (500 + swap_result.take_amount as u128) *
+        (300 - swap_result.return_amount as u128);
+    assert_eq!(invariant_before_swap, invariant_after_swap);
+}
+
+#[test]
+fn calc_swap_with_fee_without_program() {
+    let swap_result = calc_swap_with_fee(1_000, 100_000, 300_000, 100, 10_000)
+        .expect("swap_result");
+    assert_eq!(swap_result.fee_amount, 10);
+    assert_eq!(
+        swap_result.return_amount,
+        calc_swap(990, 100_000, 300_000).expect("swap_result_without_fee").return_amount
+    );
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "program")]
+
+use solana_program::pubkey::Pubkey;
+use amm::error::AmmError;
+use amm::pda::{create_pk, Pda, VAULT_SEED};
+
+#[test]
+fn check_pda_roles() {
+    let pda = Pda::generate(&Pubkey::new_unique(), &Pubkey::new_unique());
+    let keys = pda.keys();
+    let [token_x, token_y, owner_x, owner_y, vault] = &keys;
+    assert_eq!(pda.check_roles(&[token_x, token_y, owner_x, owner_y, vault]), Ok(()));
+
+    // the vault in place of the SPL token X holder and vice versa
+    assert_eq!(pda.check_roles(&[vault, token_y, owner_x, owner_y, token_x]), Err(AmmError::PdaRoleMismatch));
+    // the same account twice
+    assert_eq!(pda.check_roles(&[token_x, token_x, owner_x, owner_y, vault]), Err(AmmError::PdaRoleMismatch));
+    let unknown = Pubkey::new_unique();
+    assert_eq!(pda.check_roles(&[token_x, token_y, &unknown, &unknown, vault]), Err(AmmError::PdaRoleMismatch));
+
+    // not a PDA of the market at all is left to the seed derivation checks
+    assert_eq!(pda.check_roles(&[token_x, token_y, owner_x, owner_y, &unknown]), Ok(()));
+}
+
+#[test]
+fn generate_with_cached_bumps() {
+    let pairs: Vec<(Pubkey, Pubkey)> = (0..4)
+        .map(|_| (Pubkey::new_unique(), Pubkey::new_unique()))
+        .collect();
+    let pdas = Pda::generate_many(&pairs);
+    assert_eq!(pdas.len(), pairs.len());
+
+    for ((minter_x, minter_y), pda) in pairs.iter().zip(&pdas) {
+        assert_eq!(pda.keys(), Pda::generate(minter_x, minter_y).keys());
+
+        let cached_pda = Pda::generate_with_bumps(minter_x, minter_y, pda.bumps()).expect("cached_pda");
+        assert_eq!(cached_pda.keys(), pda.keys());
+        assert_eq!(cached_pda.bumps(), pda.bumps());
+    }
+}
+
+#[test]
+fn generate_with_invalid_bumps() {
+    let minter_x = Pubkey::new_unique();
+    let minter_y = Pubkey::new_unique();
+    let pda = Pda::generate(&minter_x, &minter_y);
+    let [owner_x_bump, owner_y_bump, vault_bump] = pda.bumps();
+
+    // a bump deriving no address of the vault
+    let invalid_bump = (0..=u8::MAX)
+        .find(|bump| create_pk(VAULT_SEED, &minter_x, &minter_y, *bump).is_err())
+        .expect("invalid_bump");
+    assert_eq!(
+        Pda::generate_with_bumps(&minter_x, &minter_y, [owner_x_bump, owner_y_bump, invalid_bump]).unwrap_err(),
+        AmmError::InvalidBump
+    );
+
+    // a non-canonical bump derives another vault
+    let non_canonical_bump = (0..vault_bump).rev()
+        .find(|bump| create_pk(VAULT_SEED, &minter_x, &minter_y, *bump).is_ok());
+    if let Some(non_canonical_bump) = non_canonical_bump {
+        let other_pda = Pda::generate_with_bumps(&minter_x, &minter_y, [owner_x_bump, owner_y_bump, non_canonical_bump])
+            .expect("other_pda");
+        assert_ne!(other_pda.vault.0, pda.vault.0);
+    }
+}
@@ -0,0 +1,44 @@
+#![cfg(all(feature = "program", feature = "serde"))]
+
+// $ cargo test --features serde --test serde
+
+use solana_program::pubkey::Pubkey;
+use amm::pda::Pda;
+use amm::state::Vault;
+use amm::swap::calc_swap_with_fee;
+
+#[test]
+fn vault_json_round_trip() {
+    let vault = Vault {
+        token_x_amount: 1_000,
+        token_y_amount: 3_000,
+        authority: Pubkey::new_unique(),
+        pending_authority: Some(Pubkey::new_unique()),
+        fee_numerator: 30,
+        fee_denominator: 10_000,
+        minter_x: Pubkey::new_unique(),
+        minter_y: Pubkey::new_unique(),
+        price_x_cumulative: u128::MAX,
+        last_timestamp: -1,
+        ..Vault::default()
+    };
+    let json = serde_json::to_string(&vault).unwrap();
+    assert_eq!(serde_json::from_str::<Vault>(&json).unwrap(), vault);
+}
+
+#[test]
+fn swap_result_json_round_trip() {
+    let swap_result = calc_swap_with_fee(1_000, 100_000, 300_000, 100, 10_000).expect("swap_result");
+    let json = serde_json::to_value(&swap_result).unwrap();
+    assert_eq!(json["fee_amount"], 10);
+    assert_eq!(serde_json::from_value::<amm::swap::SwapResult>(json).unwrap(), swap_result);
+}
+
+#[test]
+fn pda_json_round_trip() {
+    let pda = Pda::generate(&Pubkey::new_unique(), &Pubkey::new_unique());
+    let json = serde_json::to_value(&pda).unwrap();
+    let decoded_pda: Pda = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(decoded_pda.vault, pda.vault);
+    assert_eq!(serde_json::to_value(&decoded_pda).unwrap(), json);
+}
@@ -0,0 +1,300 @@
+#![cfg(feature = "program")]
+
+use borsh::BorshSerialize;
+use solana_program::account_info::AccountInfo;
+use solana_program::pubkey::Pubkey;
+use spl_token::state::Account;
+use amm::pda::Pda;
+use amm::error::AmmError;
+use amm::state::{MarketChecks, ReservesReport, Vault, VaultAccount, PRICE_SAMPLES};
+use amm::swap::{calc_swap_with_fee, dynamic_fee, SwapDirection};
+use solana_program::program_error::ProgramError;
+
+#[test]
+fn vault_len() {
+    let vault = Vault {
+        pending_authority: Some(Pubkey::new_unique()),
+        fee_recipient: Some(Pubkey::new_unique()),
+        fee_exempt: Some(Pubkey::new_unique()),
+        ..Vault::default()
+    };
+    assert_eq!(Vault::LEN, vault.try_to_vec().unwrap().len());
+    assert!(Vault::default().try_to_vec().unwrap().len() <= Vault::LEN);
+}
+
+#[test]
+fn vault_save_load() {
+    let vault = Vault {
+        token_x_amount: 100,
+        token_y_amount: 300,
+        authority: Pubkey::new_unique(),
+        minter_x: Pubkey::new_unique(),
+        minter_y: Pubkey::new_unique(),
+        ..Vault::default()
+    };
+    let mut data = vec![0; Vault::LEN];
+    vault.save(&mut data).unwrap();
+    assert_eq!(Vault::load(&data).unwrap(), vault);
+
+    let invalid_vault: ProgramError = AmmError::InvalidVault.into();
+    assert_eq!(Vault::load(&data[..Vault::LEN - 1]).unwrap_err(), invalid_vault);
+    assert_eq!(vault.save(&mut vec![0; Vault::LEN - 1]).unwrap_err(), invalid_vault);
+}
+
+#[test]
+fn vault_spot_price() {
+    let q64: u128 = 1 << 64;
+    let balanced_vault = Vault { token_x_amount: 1_000, token_y_amount: 1_000, ..Vault::default() };
+    assert_eq!(balanced_vault.spot_price(), Some(q64));
+
+    let skewed_vault = Vault { token_x_amount: 1_000, token_y_amount: 3_000, ..Vault::default() };
+    assert_eq!(skewed_vault.spot_price(), Some(3 * q64));
+    let reverted_vault = Vault { token_x_amount: 4_000, token_y_amount: 1_000, ..Vault::default() };
+    assert_eq!(reverted_vault.spot_price(), Some(q64 / 4));
+
+    // 0.01 X(5 decimals) for 3 Y(9 decimals) => 1 X costs 300 Y
+    let decimals_vault = Vault {
+        token_x_amount: 1_000,
+        token_y_amount: 3_000_000_000,
+        decimals_x: 5,
+        decimals_y: 9,
+        ..Vault::default()
+    };
+    assert_eq!(decimals_vault.spot_price(), Some(300 * q64));
+    assert_eq!(decimals_vault.spot_price_with_decimals(5, 9), Some(300 * q64));
+    // the raw ratio of the reserves
+    assert_eq!(decimals_vault.spot_price_with_decimals(0, 0), Some(3_000_000 * q64));
+
+    let empty_vault = Vault { token_x_amount: 0, token_y_amount: 3_000, ..Vault::default() };
+    assert_eq!(empty_vault.spot_price(), None);
+    assert_eq!(empty_vault.spot_price_with_decimals(5, 9), None);
+}
+
+#[test]
+fn vault_geometric_mean_value() {
+    let vault = |token_x_amount, token_y_amount| Vault { token_x_amount, token_y_amount, ..Vault::default() };
+    assert_eq!(vault(100, 100).geometric_mean_value(), Some(100));
+    assert_eq!(vault(400, 900).geometric_mean_value(), Some(600));
+    assert_eq!(vault(1, 1_000_000).geometric_mean_value(), Some(1_000));
+
+    // skewed, rounded down
+    assert_eq!(vault(1_000, 3_000).geometric_mean_value(), Some(1_732));
+    assert_eq!(vault(1, u64::MAX).geometric_mean_value(), Some(u32::MAX as u128));
+    assert_eq!(vault(u64::MAX, u64::MAX).geometric_mean_value(), Some(u64::MAX as u128));
+
+    // the same depth for the same K at any price
+    assert_eq!(vault(2, 8).geometric_mean_value(), vault(8, 2).geometric_mean_value());
+
+    assert_eq!(vault(0, 3_000).geometric_mean_value(), None);
+    assert_eq!(Vault::default().geometric_mean_value(), None);
+}
+
+#[test]
+fn vault_update_price_cumulative() {
+    let q64: u128 = 1 << 64;
+    let mut vault = Vault { token_x_amount: 1_000, token_y_amount: 3_000, last_timestamp: 100, ..Vault::default() };
+    vault.update_price_cumulative(160);
+    assert_eq!(vault.price_x_cumulative, 180 * q64);
+    assert_eq!(vault.last_timestamp, 160);
+
+    // time never goes back
+    vault.update_price_cumulative(150);
+    assert_eq!(vault.price_x_cumulative, 180 * q64);
+    assert_eq!(vault.last_timestamp, 160);
+
+    // no price of the empty reserve, only the timestamp moves
+    let mut empty_vault = Vault { token_y_amount: 3_000, last_timestamp: 100, ..Vault::default() };
+    empty_vault.update_price_cumulative(160);
+    assert_eq!(empty_vault.price_x_cumulative, 0);
+    assert_eq!(empty_vault.last_timestamp, 160);
+}
+
+#[test]
+fn vault_recent_prices_wraparound() {
+    let q64: u128 = 1 << 64;
+    let mut vault = Vault { token_x_amount: 1_000, ..Vault::default() };
+    assert_eq!(vault.recent_prices(), [0; PRICE_SAMPLES]);
+
+    vault.token_y_amount = 1_000;
+    vault.record_price();
+    assert_eq!(vault.recent_prices(), [0, 0, 0, 0, 0, 0, 0, q64]);
+
+    for price in 2..=9 {
+        vault.token_y_amount = price * 1_000;
+        vault.record_price();
+    }
+    // the first sample is overwritten by the ninth
+    assert_eq!(
+        vault.recent_prices(),
+        [2 * q64, 3 * q64, 4 * q64, 5 * q64, 6 * q64, 7 * q64, 8 * q64, 9 * q64]
+    );
+    assert_eq!(vault.price_sample_index, 1);
+
+    // no price of the empty reserve, no sample
+    vault.token_x_amount = 0;
+    vault.record_price();
+    assert_eq!(vault.price_sample_index, 1);
+}
+
+#[test]
+fn market_checks() {
+    let (minter_x, minter_y) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let pda = Pda::generate(&minter_x, &minter_y);
+    let vault = Vault { token_x_amount: 1_000, token_y_amount: 3_000, minter_x, minter_y, ..Vault::default() };
+    let pda_token_x = Account { mint: minter_x, owner: pda.pda_owner_token_x.0, amount: 1_000, ..Account::default() };
+    let pda_token_y = Account { mint: minter_y, owner: pda.pda_owner_token_y.0, amount: 3_000, ..Account::default() };
+    let keys = [&pda.vault.0, &pda.pda_token_x_pk, &pda.pda_token_y_pk];
+
+    let checks = MarketChecks::new([&minter_x, &minter_y], keys, Some(&vault), [Some(&pda_token_x), Some(&pda_token_y)]);
+    assert_eq!(checks, MarketChecks(MarketChecks::ALL));
+    assert!(checks.is_healthy());
+
+    // tokens sent to the holder past the market
+    let surplus_token_x = Account { amount: 1_250, ..pda_token_x };
+    let checks = MarketChecks::new([&minter_x, &minter_y], keys, Some(&vault), [Some(&surplus_token_x), Some(&pda_token_y)]);
+    assert_eq!(checks, MarketChecks(MarketChecks::ALL & !MarketChecks::RESERVES));
+    assert!(!checks.is_healthy());
+
+    // the holders swapped
+    let checks = MarketChecks::new(
+        [&minter_x, &minter_y],
+        [&pda.vault.0, &pda.pda_token_y_pk, &pda.pda_token_x_pk],
+        Some(&vault),
+        [Some(&pda_token_y), Some(&pda_token_x)],
+    );
+    assert!(!checks.passed(MarketChecks::PDA_KEYS));
+    assert!(!checks.passed(MarketChecks::TOKEN_OWNERS));
+    assert!(!checks.passed(MarketChecks::TOKEN_MINTS));
+
+    // no vault, only the addresses are checked
+    let checks = MarketChecks::new([&minter_x, &minter_y], keys, None, [Some(&pda_token_x), Some(&pda_token_y)]);
+    assert_eq!(checks, MarketChecks(MarketChecks::PDA_KEYS));
+}
+
+#[test]
+fn reserves_report_difference() {
+    let vault = Vault { token_x_amount: 1_000, token_y_amount: 3_000, ..Vault::default() };
+    let synced_report = ReservesReport::new(&vault, 1_000, 3_000);
+    assert!(synced_report.is_synced());
+
+    let report = ReservesReport::new(&vault, 1_250, 2_999);
+    assert_eq!(
+        report,
+        ReservesReport {
+            reserve_x: 1_000,
+            reserve_y: 3_000,
+            balance_x: 1_250,
+            balance_y: 2_999,
+            difference_x: 250,
+            difference_y: -1,
+        }
+    );
+    assert!(!report.is_synced());
+    assert_eq!(report.try_to_vec().unwrap().len(), ReservesReport::LEN);
+}
+
+#[test]
+fn vault_quote_out() {
+    let mut vault = Vault {
+        token_x_amount: 1_000,
+        token_y_amount: 3_000,
+        fee_numerator: 100,
+        fee_denominator: 10_000,
+        ..Vault::default()
+    };
+    for amount in [1, 100, 999, 5_000] {
+        assert_eq!(
+            vault.quote_out(amount, SwapDirection::XtoY),
+            calc_swap_with_fee(amount, 1_000, 3_000, 100, 10_000).map(|swap_result| swap_result.return_amount)
+        );
+        assert_eq!(
+            vault.quote_out(amount, SwapDirection::YtoX),
+            calc_swap_with_fee(amount, 3_000, 1_000, 100, 10_000).map(|swap_result| swap_result.return_amount)
+        );
+    }
+
+    vault.dynamic_fee_enabled = true;
+    let (fee_numerator, fee_denominator) = dynamic_fee((100, 10_000), 1_000, 3_000, 500).expect("dynamic_fee");
+    assert!(fee_numerator > 100);
+    assert_eq!(
+        vault.quote_out(500, SwapDirection::XtoY),
+        calc_swap_with_fee(500, 1_000, 3_000, fee_numerator, fee_denominator)
+            .map(|swap_result| swap_result.return_amount)
+    );
+
+    // rounded to nothing
+    assert_eq!(vault.quote_out(1, SwapDirection::YtoX), None);
+}
+
+#[test]
+fn vault_swap_fee_exempt() {
+    let router = Pubkey::new_unique();
+    let mut vault = Vault {
+        token_x_amount: 1_000,
+        token_y_amount: 3_000,
+        fee_numerator: 100,
+        fee_denominator: 10_000,
+        dynamic_fee_enabled: true,
+        ..Vault::default()
+    };
+    let swap_fee = vault.swap_fee(500, SwapDirection::XtoY);
+    assert_eq!(vault.swap_fee_for(&router, 500, SwapDirection::XtoY), swap_fee);
+
+    vault.fee_exempt = Some(router);
+    assert_eq!(vault.swap_fee_for(&router, 500, SwapDirection::XtoY), Some((0, 10_000)));
+    assert_eq!(vault.swap_fee_for(&Pubkey::new_unique(), 500, SwapDirection::XtoY), swap_fee);
+}
+
+#[test]
+fn vault_account_read_modify_write() {
+    let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut lamports = 0;
+    let mut data = vec![0; Vault::LEN];
+    Vault { token_x_amount: 1_000, token_y_amount: 3_000, ..Vault::default() }.save(&mut data).unwrap();
+    let account_info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, 0);
+
+    let mut vault = VaultAccount::load(&account_info).unwrap();
+    assert_eq!(vault.reserves(), (1_000, 3_000));
+    assert!(!vault.is_changed());
+
+    vault.set_reserves(1_100, 2_728);
+    vault.fee_numerator = 30;
+    assert!(vault.is_changed());
+    // nothing is saved before the commit
+    assert_eq!(Vault::load(&account_info.data.borrow()).unwrap().token_x_amount, 1_000);
+
+    vault.commit().unwrap();
+    assert!(!vault.is_changed());
+    let saved_vault = Vault::load(&account_info.data.borrow()).unwrap();
+    assert_eq!((saved_vault.token_x_amount, saved_vault.token_y_amount, saved_vault.fee_numerator), (1_100, 2_728, 30));
+
+    // saved once, the unchanged vault is not saved again by a commit or on drop
+    account_info.data.borrow_mut().fill(0);
+    vault.commit().unwrap();
+    drop(vault);
+    assert!(account_info.data.borrow().iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn vault_account_load_short_data() {
+    let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut lamports = 0;
+    let mut short_data = vec![0; Vault::LEN - 1];
+    let short_account_info = AccountInfo::new(&key, false, true, &mut lamports, &mut short_data, &owner, false, 0);
+    assert_eq!(VaultAccount::load(&short_account_info).err(), Some(AmmError::InvalidVault.into()));
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "Vault is changed but not committed")]
+fn vault_account_uncommitted_drop() {
+    let (key, owner) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let mut lamports = 0;
+    let mut data = vec![0; Vault::LEN];
+    Vault::default().save(&mut data).unwrap();
+    let account_info = AccountInfo::new(&key, false, true, &mut lamports, &mut data, &owner, false, 0);
+
+    let mut vault = VaultAccount::load(&account_info).unwrap();
+    vault.set_reserves(1_000, 3_000);
+    drop(vault);
+}
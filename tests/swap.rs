@@ -0,0 +1,487 @@
+#![cfg(feature = "program")]
+
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::pubkey::Pubkey;
+use amm::swap::{
+    accumulate_price, amount_to_reach_price, apply_output_granularity, best_two_hop, calc_referral_fee, calc_swap, calc_swap_with_fee,
+    dynamic_fee, integer_sqrt, invariant_ok, normalized_price, price_impact_bps, quote_both, to_u128, to_u64, try_calc_swap,
+    try_calc_swap_with_fee, within_reference, LabeledSwapResult, SwapDirection, SwapError, SwapResult, DYNAMIC_FEE_MAX_MULTIPLIER,
+    INVARIANT_TOLERANCE,
+};
+
+const Q64: u128 = 1 << 64;
+
+// Decimals of the test tokens X, Y from `basic::Env`
+const DECIMALS_X: u8 = 5;
+const DECIMALS_Y: u8 = 9;
+
+#[test]
+fn normalized_price_cross_decimals() {
+    // 1 X = 100_000 raw, 3 Y = 3_000_000_000 raw => 1 X costs 3 Y
+    let price = normalized_price(100_000, 3_000_000_000, DECIMALS_X, DECIMALS_Y)
+        .expect("price");
+    assert_eq!(price, 3 * Q64);
+
+    // raw ratio is 10^4 times bigger than the human price
+    let raw_price = normalized_price(100_000, 3_000_000_000, 0, 0)
+        .expect("raw_price");
+    assert_eq!(raw_price, 30_000 * Q64);
+}
+
+#[test]
+fn normalized_price_reverted_decimals() {
+    // 1 Y = 100_000 raw(5 decimals), 2 X = 2_000_000_000 raw(9 decimals) => 1 X costs 0.5 Y
+    let price = normalized_price(2_000_000_000, 100_000, DECIMALS_Y, DECIMALS_X)
+        .expect("price");
+    assert_eq!(price, Q64 / 2);
+}
+
+#[test]
+fn normalized_price_zero_reserve() {
+    assert_eq!(normalized_price(0, 3_000_000_000, DECIMALS_X, DECIMALS_Y), None);
+}
+
+#[test]
+fn calc_swap_with_zero_fee() {
+    let swap_result = calc_swap_with_fee(100, 500, 300, 0, 10_000).expect("swap_result");
+    assert_eq!(swap_result, calc_swap(100, 500, 300).expect("swap_result_without_fee"));
+    assert_eq!(swap_result.fee_amount, 0);
+}
+
+#[test]
+fn calc_swap_no_op() {
+    // K = 12_000, K / 4_001 rounds up to 3 = Y, so the added 1 X is rounded away
+    assert_eq!(try_calc_swap(1, 4_000, 3), Err(SwapError::NoOp));
+    assert_eq!(calc_swap(1, 4_000, 3), None);
+    assert_eq!(try_calc_swap_with_fee(1, 4_000, 3, 0, 10_000), Err(SwapError::NoOp));
+
+    // the whole reserve Y would be returned
+    assert_eq!(try_calc_swap(5_000, 4_000, 1), Err(SwapError::ZeroReturn));
+    assert_eq!(try_calc_swap(100, 500, 300), Ok(calc_swap(100, 500, 300).expect("swap_result")));
+}
+
+#[test]
+fn calc_swap_never_drains_reserve() {
+    // the largest inputs still swapped, X + dX = K leaves a single unit of Y
+    for (add_source_amount, source_amount, destination_amount) in [
+        (2_999, 1, 3_000),
+        (u64::MAX / 2 - 1, 1, u64::MAX / 2),
+        (1_000 * 999_999, 1_000, 1_000_000),
+    ] {
+        let swap_result = calc_swap(add_source_amount, source_amount, destination_amount).expect("swap_result");
+        assert_eq!(swap_result.return_amount, destination_amount - 1);
+    }
+
+    // X + dX above K would leave less than a unit of Y, so nothing is swapped
+    assert_eq!(try_calc_swap(3_000, 1, 3_000), Err(SwapError::ZeroReturn));
+    assert_eq!(try_calc_swap(u64::MAX / 2, 1, u64::MAX / 2), Err(SwapError::ZeroReturn));
+    assert_eq!(try_calc_swap(u64::MAX / 2, 1, 1), Err(SwapError::ZeroReturn));
+}
+
+#[test]
+fn to_u64_overflow_boundary() {
+    assert_eq!(to_u128(u64::MAX), u64::MAX as u128);
+    assert_eq!(to_u64(0), Ok(0));
+    assert_eq!(to_u64(u64::MAX as u128), Ok(u64::MAX));
+    assert_eq!(to_u64(u64::MAX as u128 + 1), Err(SwapError::Overflow));
+    assert_eq!(to_u64(u128::MAX), Err(SwapError::Overflow));
+}
+
+#[test]
+fn output_granularity() {
+    let swap_result = calc_swap(1_000, 100_000, 300_000).expect("swap_result");
+    assert_eq!(swap_result.return_amount, 2_970);
+
+    assert_eq!(apply_output_granularity(swap_result, 0), Some(swap_result));
+    let floored = apply_output_granularity(swap_result, 100).expect("floored");
+    assert_eq!(floored.return_amount, 2_900);
+    assert_eq!(floored.take_amount, swap_result.take_amount);
+    assert_eq!(apply_output_granularity(swap_result, 3_000), None);
+
+    // the rest of the returned amount stays in the market
+    let invariant = 100_000u128 * 300_000;
+    let invariant_after_swap = (100_000 + floored.take_amount as u128) *
+        (300_000 - floored.return_amount as u128);
+    assert!(invariant_after_swap > invariant);
+}
+
+#[test]
+fn calc_swap_with_fee_stays_in_market() {
+    // 1% fee
+    let swap_result = calc_swap_with_fee(1_000, 100_000, 300_000, 100, 10_000).expect("swap_result");
+    let swap_result_without_fee = calc_swap(990, 100_000, 300_000).expect("swap_result_without_fee");
+
+    assert_eq!(swap_result.fee_amount, 10);
+    assert_eq!(swap_result.take_amount, swap_result_without_fee.take_amount + 10);
+    assert_eq!(swap_result.return_amount, swap_result_without_fee.return_amount);
+}
+
+#[test]
+fn dynamic_fee_grows_with_imbalance() {
+    let base_fee = (30, 10_000);
+    let amount_in = 1_000;
+
+    let balanced_fee = dynamic_fee(base_fee, 100_000, 100_000, amount_in).expect("balanced_fee");
+    let imbalanced_fee = dynamic_fee(base_fee, 400_000, 100_000, amount_in).expect("imbalanced_fee");
+
+    assert_eq!(balanced_fee.1, base_fee.1);
+    assert_eq!(imbalanced_fee.1, base_fee.1);
+    assert!(balanced_fee.0 >= base_fee.0);
+    assert!(imbalanced_fee.0 > balanced_fee.0);
+    assert!(imbalanced_fee.0 <= base_fee.0 * DYNAMIC_FEE_MAX_MULTIPLIER);
+
+    let balanced_swap = calc_swap_with_fee(amount_in, 100_000, 100_000, balanced_fee.0, balanced_fee.1)
+        .expect("balanced_swap");
+    let imbalanced_swap = calc_swap_with_fee(amount_in, 400_000, 100_000, imbalanced_fee.0, imbalanced_fee.1)
+        .expect("imbalanced_swap");
+    assert!(imbalanced_swap.fee_amount > balanced_swap.fee_amount);
+}
+
+#[test]
+fn dynamic_fee_capped() {
+    let base_fee = (30, 10_000);
+
+    let fee = dynamic_fee(base_fee, 1, 1_000_000_000, 1_000_000_000).expect("fee");
+    assert!(fee.0 <= base_fee.0 * DYNAMIC_FEE_MAX_MULTIPLIER);
+
+    let max_fee = (9_000, 10_000);
+    let fee = dynamic_fee(max_fee, 1, 1_000_000_000, 1_000_000_000).expect("fee");
+    assert!(fee.0 <= max_fee.1);
+}
+
+#[test]
+fn swap_direction_flip() {
+    assert_eq!(SwapDirection::XtoY.flip(), SwapDirection::YtoX);
+    assert_eq!(SwapDirection::YtoX.flip(), SwapDirection::XtoY);
+    assert_eq!(SwapDirection::XtoY.flip().flip(), SwapDirection::XtoY);
+}
+
+#[test]
+fn swap_direction_output_mint() {
+    let minter_x = Pubkey::new_unique();
+    let minter_y = Pubkey::new_unique();
+
+    assert_eq!(SwapDirection::XtoY.output_mint(&minter_x, &minter_y), &minter_y);
+    assert_eq!(SwapDirection::YtoX.output_mint(&minter_x, &minter_y), &minter_x);
+    // the output of the flipped direction is the input
+    let swap_direction = SwapDirection::new(&minter_x, &minter_x, &minter_y).expect("swap_direction");
+    assert_eq!(swap_direction.flip().output_mint(&minter_x, &minter_y), &minter_x);
+}
+
+#[test]
+fn price_accumulator() {
+    let price_cumulative = accumulate_price(0, 3 * Q64, 60);
+    assert_eq!(price_cumulative, 180 * Q64);
+    assert_eq!(accumulate_price(price_cumulative, Q64 / 4, 40), 190 * Q64);
+    assert_eq!(accumulate_price(price_cumulative, 5 * Q64, 0), price_cumulative);
+
+    // wraps, the difference of the observations is still the accumulated price
+    let near_max = u128::MAX - Q64 + 1;
+    let wrapped = accumulate_price(near_max, 3 * Q64, 2);
+    assert_eq!(wrapped, 5 * Q64);
+    assert_eq!(wrapped.wrapping_sub(near_max), 6 * Q64);
+}
+
+#[test]
+fn integer_sqrt_squares() {
+    assert_eq!(integer_sqrt(0), 0);
+    assert_eq!(integer_sqrt(1), 1);
+    for root in [2u128, 3, 10, 1_000, 65_535, 1 << 32, u64::MAX as u128] {
+        let square = root * root;
+        assert_eq!(integer_sqrt(square), root);
+        assert_eq!(integer_sqrt(square - 1), root - 1);
+        assert_eq!(integer_sqrt(square + 1), root);
+    }
+    assert_eq!(integer_sqrt(u128::MAX), u64::MAX as u128);
+}
+
+#[test]
+fn quote_both_directions() {
+    let (x_to_y, y_to_x) = quote_both(100, 1_000, 3_000, (0, 10_000));
+    assert_eq!(x_to_y, calc_swap(100, 1_000, 3_000));
+    assert_eq!(y_to_x, calc_swap(100, 3_000, 1_000));
+
+    let (x_to_y, y_to_x) = quote_both(500, 1_000, 3_000, (30, 10_000));
+    assert_eq!(x_to_y, calc_swap_with_fee(500, 1_000, 3_000, 30, 10_000));
+    assert_eq!(y_to_x, calc_swap_with_fee(500, 3_000, 1_000, 30, 10_000));
+    assert!(x_to_y.expect("x_to_y").return_amount > y_to_x.expect("y_to_x").return_amount);
+
+    // rounded to nothing in one direction only
+    let (x_to_y, y_to_x) = quote_both(1, 1_000, 3_000, (0, 10_000));
+    assert!(x_to_y.is_some());
+    assert_eq!(y_to_x, None);
+}
+
+#[test]
+fn invariant_grows_with_fee() {
+    let swap_result = calc_swap_with_fee(1_000, 100_000, 300_000, 100, 10_000).expect("swap_result");
+    let invariant_before = 100_000u128 * 300_000;
+    let invariant_after = (100_000 + swap_result.take_amount as u128) *
+        (300_000 - swap_result.return_amount as u128);
+    assert!(invariant_after > invariant_before);
+    assert!(invariant_ok(invariant_before, invariant_after, swap_result.fee_amount));
+
+    // the fee left in the market isn't a rounding error
+    assert!(!invariant_ok(invariant_after, invariant_before, swap_result.fee_amount));
+    // a swap with the fee gets no rounding band
+    assert!(invariant_ok(invariant_before, invariant_before, swap_result.fee_amount));
+    assert!(!invariant_ok(invariant_before, invariant_before - INVARIANT_TOLERANCE, swap_result.fee_amount));
+}
+
+#[test]
+fn invariant_rounding_boundary() {
+    let invariant = 150_000;
+    assert!(invariant_ok(invariant, invariant, 0));
+    assert!(invariant_ok(invariant, invariant - INVARIANT_TOLERANCE, 0));
+    assert!(!invariant_ok(invariant, invariant - INVARIANT_TOLERANCE - 1, 0));
+    assert!(invariant_ok(0, 0, 0));
+    assert!(invariant_ok(INVARIANT_TOLERANCE, 0, 0));
+}
+
+#[test]
+fn labeled_swap_result_x_to_y() {
+    let (x_pk, y_pk) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let swap_result = calc_swap(100, 500, 300).expect("swap_result");
+
+    assert_eq!(
+        swap_result.labeled(SwapDirection::XtoY, &x_pk, &y_pk),
+        LabeledSwapResult { take_mint: x_pk, take_amount: 100, return_mint: y_pk, return_amount: 50 }
+    );
+}
+
+#[test]
+fn labeled_swap_result_y_to_x() {
+    let (x_pk, y_pk) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let swap_result = calc_swap(100, 300, 500).expect("swap_result");
+
+    assert_eq!(
+        swap_result.labeled(SwapDirection::YtoX, &x_pk, &y_pk),
+        LabeledSwapResult { take_mint: y_pk, take_amount: 100, return_mint: x_pk, return_amount: 125 }
+    );
+}
+
+#[test]
+fn labeled_swap_result_return_data() {
+    let (x_pk, y_pk) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let labeled_swap_result = calc_swap(100, 500, 300).expect("swap_result")
+        .labeled(SwapDirection::XtoY, &x_pk, &y_pk);
+    let data = labeled_swap_result.try_to_vec().unwrap();
+    assert_eq!(data.len(), LabeledSwapResult::LEN);
+
+    assert_eq!(data[..32], x_pk.to_bytes());
+    assert_eq!(data[32..40], 100u64.to_le_bytes());
+    assert_eq!(data[40..72], y_pk.to_bytes());
+    assert_eq!(data[72..80], 50u64.to_le_bytes());
+    assert_eq!(LabeledSwapResult::try_from_slice(&data).unwrap(), labeled_swap_result);
+}
+
+#[test]
+fn swap_result_layout() {
+    let swap_result = calc_swap_with_fee(1_000, 100_000, 300_000, 100, 10_000).expect("swap_result");
+    let data = swap_result.try_to_vec().unwrap();
+    assert_eq!(data.len(), SwapResult::LEN);
+
+    assert_eq!(data[..8], 1_000u64.to_le_bytes());
+    assert_eq!(data[8..16], swap_result.return_amount.to_le_bytes());
+    assert_eq!(data[16..24], 10u64.to_le_bytes());
+    assert_eq!(SwapResult::try_from_slice(&data).unwrap(), swap_result);
+}
+
+#[test]
+fn best_two_hop_higher_output() {
+    let no_fee = (0, 10_000);
+    let amount = 100;
+    // X -> A -> Z, the second market of A is shallow
+    let route_a = (((1_000, 2_000), no_fee), ((2_000, 500), no_fee));
+    // X -> B -> Z
+    let route_b = (((1_000, 1_000), no_fee), ((1_000, 1_000), no_fee));
+
+    let first = calc_swap(amount, 1_000, 2_000).expect("first_hop_a").return_amount;
+    let return_a = calc_swap(first, 2_000, 500).expect("second_hop_a").return_amount;
+    let first = calc_swap(amount, 1_000, 1_000).expect("first_hop_b").return_amount;
+    let return_b = calc_swap(first, 1_000, 1_000).expect("second_hop_b").return_amount;
+    assert!(return_b > return_a);
+
+    assert_eq!(best_two_hop(amount, &[route_a, route_b]), Some((1, return_b)));
+    assert_eq!(best_two_hop(amount, &[route_b, route_a]), Some((0, return_b)));
+}
+
+#[test]
+fn best_two_hop_fee() {
+    let amount = 1_000;
+    let route_without_fee = (((100_000, 100_000), (0, 10_000)), ((100_000, 100_000), (0, 10_000)));
+    // the same markets with 10% fee in the first hop
+    let route_with_fee = (((100_000, 100_000), (1_000, 10_000)), ((100_000, 100_000), (0, 10_000)));
+
+    let (best, _) = best_two_hop(amount, &[route_with_fee, route_without_fee]).expect("best_route");
+    assert_eq!(best, 1);
+    // equal routes, the first one wins
+    assert_eq!(best_two_hop(amount, &[route_without_fee, route_without_fee]).map(|(i, _)| i), Some(0));
+}
+
+#[test]
+fn best_two_hop_no_route() {
+    let no_fee = (0, 10_000);
+    // the first hop returns nothing
+    let dust_route = (((1_000_000, 1), no_fee), ((1_000, 1_000), no_fee));
+    assert_eq!(best_two_hop(1, &[dust_route]), None);
+    assert_eq!(best_two_hop(100, &[]), None);
+}
+
+#[test]
+fn within_reference_tolerance() {
+    // 100 X for 50 Y, 0.5 Y per X
+    let swap_result = calc_swap(100, 500, 300).expect("swap_result");
+    let realized_price = Q64 / 2;
+
+    assert!(within_reference(&swap_result, realized_price, 0));
+    // a better price than the reference
+    assert!(within_reference(&swap_result, Q64 / 4, 0));
+
+    // 0.52 Y per X is 3.85% better than realized
+    let reference_price = Q64 * 52 / 100;
+    assert!(!within_reference(&swap_result, reference_price, 300));
+    assert!(within_reference(&swap_result, reference_price, 400));
+    assert!(within_reference(&swap_result, u128::MAX, 10_000));
+    assert!(!within_reference(&swap_result, u128::MAX, 9_999));
+}
+
+#[test]
+fn within_reference_with_fee() {
+    let swap_result = calc_swap_with_fee(1_000, 100_000, 100_000, 100, 10_000).expect("swap_result");
+    // the fee of 1% and the price impact of ~1% against the spot price
+    assert!(!within_reference(&swap_result, Q64, 100));
+    assert!(within_reference(&swap_result, Q64, 200));
+}
+
+/// Spot price after the swap of the added amount in the direction
+fn price_after_swap(
+    reserve_x: u64,
+    reserve_y: u64,
+    decimals_x: u8,
+    decimals_y: u8,
+    direction: SwapDirection,
+    amount: u64,
+    fee: (u64, u64),
+) -> u128 {
+    let (fee_numerator, fee_denominator) = fee;
+    let (reserve_x, reserve_y) = match direction {
+        SwapDirection::XtoY => {
+            let swap_result = calc_swap_with_fee(amount, reserve_x, reserve_y, fee_numerator, fee_denominator)
+                .expect("swap_result");
+            (reserve_x + swap_result.take_amount, reserve_y - swap_result.return_amount)
+        }
+        SwapDirection::YtoX => {
+            let swap_result = calc_swap_with_fee(amount, reserve_y, reserve_x, fee_numerator, fee_denominator)
+                .expect("swap_result");
+            (reserve_x - swap_result.return_amount, reserve_y + swap_result.take_amount)
+        }
+    };
+    normalized_price(reserve_x, reserve_y, decimals_x, decimals_y).expect("price")
+}
+
+fn assert_price_near(price: u128, target_price: u128, tolerance_bps: u128) {
+    let difference = if price > target_price { price - target_price } else { target_price - price };
+    assert!(
+        difference * 10_000 <= target_price * tolerance_bps,
+        "price {} is more than {} bps from {}", price, tolerance_bps, target_price
+    );
+}
+
+#[test]
+fn amount_to_reach_price_lands_at_target() {
+    let (reserve_x, reserve_y) = (1_000_000, 3_000_000);
+    let no_fee = (0, 10_000);
+
+    // price of X falls, X is added
+    let (direction, amount) = amount_to_reach_price(reserve_x, reserve_y, 0, 0, 2 * Q64, no_fee).expect("amount");
+    assert_eq!(direction, SwapDirection::XtoY);
+    assert_price_near(price_after_swap(reserve_x, reserve_y, 0, 0, direction, amount, no_fee), 2 * Q64, 1);
+
+    // price of X rises, Y is added
+    let (direction, amount) = amount_to_reach_price(reserve_x, reserve_y, 0, 0, 4 * Q64, no_fee).expect("amount");
+    assert_eq!(direction, SwapDirection::YtoX);
+    assert_price_near(price_after_swap(reserve_x, reserve_y, 0, 0, direction, amount, no_fee), 4 * Q64, 1);
+
+    // the fee is added on top and stays in the market, the price lands next to the target
+    let fee = (30, 10_000);
+    let (direction, amount) = amount_to_reach_price(reserve_x, reserve_y, 0, 0, 2 * Q64, fee).expect("amount");
+    assert!(amount > amount_to_reach_price(reserve_x, reserve_y, 0, 0, 2 * Q64, no_fee).unwrap().1);
+    assert_price_near(price_after_swap(reserve_x, reserve_y, 0, 0, direction, amount, fee), 2 * Q64, 10);
+    let (direction, amount) = amount_to_reach_price(reserve_x, reserve_y, 0, 0, Q64 * 31 / 10, fee).expect("amount");
+    assert_eq!(direction, SwapDirection::YtoX);
+    assert_price_near(price_after_swap(reserve_x, reserve_y, 0, 0, direction, amount, fee), Q64 * 31 / 10, 10);
+}
+
+#[test]
+fn amount_to_reach_price_cross_decimals() {
+    // 10 X and 30 Y of the test tokens, 1 X costs 3 Y while the raw ratio is 30_000
+    let (reserve_x, reserve_y) = (1_000_000, 30_000_000_000);
+    let no_fee = (0, 10_000);
+    assert_eq!(normalized_price(reserve_x, reserve_y, DECIMALS_X, DECIMALS_Y), Some(3 * Q64));
+    assert_eq!(amount_to_reach_price(reserve_x, reserve_y, DECIMALS_X, DECIMALS_Y, 3 * Q64, no_fee), None);
+
+    let (direction, amount) = amount_to_reach_price(reserve_x, reserve_y, DECIMALS_X, DECIMALS_Y, 2 * Q64, no_fee)
+        .expect("amount");
+    assert_eq!(direction, SwapDirection::XtoY);
+    let price = price_after_swap(reserve_x, reserve_y, DECIMALS_X, DECIMALS_Y, direction, amount, no_fee);
+    assert_price_near(price, 2 * Q64, 1);
+
+    let (direction, amount) = amount_to_reach_price(reserve_x, reserve_y, DECIMALS_X, DECIMALS_Y, 4 * Q64, no_fee)
+        .expect("amount");
+    assert_eq!(direction, SwapDirection::YtoX);
+    let price = price_after_swap(reserve_x, reserve_y, DECIMALS_X, DECIMALS_Y, direction, amount, no_fee);
+    assert_price_near(price, 4 * Q64, 1);
+
+    // the decimals swapped, 10 X and 30 Y again
+    let (reserve_x, reserve_y) = (10_000_000_000, 3_000_000);
+    let (direction, amount) = amount_to_reach_price(reserve_x, reserve_y, DECIMALS_Y, DECIMALS_X, 2 * Q64, no_fee)
+        .expect("amount");
+    assert_eq!(direction, SwapDirection::XtoY);
+    let price = price_after_swap(reserve_x, reserve_y, DECIMALS_Y, DECIMALS_X, direction, amount, no_fee);
+    assert_price_near(price, 2 * Q64, 1);
+}
+
+#[test]
+fn amount_to_reach_price_none() {
+    let no_fee = (0, 10_000);
+    // already at the target
+    assert_eq!(amount_to_reach_price(1_000, 3_000, 0, 0, 3 * Q64, no_fee), None);
+    assert_eq!(amount_to_reach_price(0, 3_000, 0, 0, 3 * Q64, no_fee), None);
+    assert_eq!(amount_to_reach_price(1_000, 3_000, 0, 0, 0, no_fee), None);
+    assert_eq!(amount_to_reach_price(1_000, 3_000, 0, 0, 2 * Q64, (10_000, 10_000)), None);
+    // the reserve X to reach the price doesn't fit u64
+    assert_eq!(amount_to_reach_price(u64::MAX / 2, u64::MAX / 2, 0, 0, 1, no_fee), None);
+}
+
+#[test]
+fn price_impact_bps_rounded_up() {
+    assert_eq!(price_impact_bps(Q64, Q64), Some(0));
+    assert_eq!(price_impact_bps(Q64, Q64 - Q64 / 100), Some(100));
+    assert_eq!(price_impact_bps(Q64, Q64 + Q64 / 100), Some(100));
+    assert_eq!(price_impact_bps(Q64, Q64 + 1), Some(1));
+    assert_eq!(price_impact_bps(Q64, 3 * Q64), Some(20_000));
+
+    // the impact of a swap of 10% of the reserve X, the price of X falls from 3 to 2.48
+    let swap_result = calc_swap(100, 1_000, 3_000).expect("swap_result");
+    let price_before = normalized_price(1_000, 3_000, 0, 0).unwrap();
+    let price_after = normalized_price(1_100, 3_000 - swap_result.return_amount, 0, 0).unwrap();
+    assert_eq!(price_impact_bps(price_before, price_after), Some(1_734));
+
+    assert_eq!(price_impact_bps(0, Q64), None);
+    assert_eq!(price_impact_bps(1, u128::MAX), None);
+}
+
+#[test]
+fn referral_fee_bounded_by_swap_fee() {
+    // 0.5% of the amount, rounded down
+    assert_eq!(calc_referral_fee(1_000, 50, 10), Some(5));
+    assert_eq!(calc_referral_fee(999, 50, 10), Some(4));
+    assert_eq!(calc_referral_fee(1_000, 0, 0), Some(0));
+    // at most the whole swap fee
+    assert_eq!(calc_referral_fee(1_000, 100, 10), Some(10));
+    assert_eq!(calc_referral_fee(1_000, 110, 10), None);
+    assert_eq!(calc_referral_fee(1_000, 50, 0), None);
+    // the product doesn't overflow
+    assert_eq!(calc_referral_fee(u64::MAX, 10_000, u64::MAX), Some(u64::MAX));
+}